@@ -0,0 +1,249 @@
+#!/usr/bin/env python3
+"""Writes the tiny hand-assembled DLLs used by the dyload::windows::pe tests.
+
+None of them are meant to run, they only need to look right on disk.
+"""
+
+import os
+import struct
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+
+FILE_ALIGN = 0x200
+SECTION_ALIGN = 0x1000
+IMAGE_BASE = 0x180000000
+
+MACHINE_AMD64 = 0x8664
+MACHINE_I386 = 0x14c
+FILE_DLL = 0x2000
+FILE_EXECUTABLE = 0x0002
+FILE_LARGE_ADDRESS_AWARE = 0x0020
+FILE_32BIT = 0x0100
+
+SCN_CODE = 0x00000020 | 0x20000000 | 0x40000000
+SCN_RDATA = 0x00000040 | 0x40000000
+
+ORDINAL_FLAG64 = 1 << 63
+
+
+def align(n, a):
+	return (n + a - 1) // a * a
+
+
+class Blob:
+	"""Section contents, addressed by RVA"""
+
+	def __init__(self, rva):
+		self.rva = rva
+		self.data = bytearray()
+
+	def here(self):
+		return self.rva + len(self.data)
+
+	def put(self, data, alignment=1):
+		while len(self.data) % alignment:
+			self.data.append(0)
+		rva = self.here()
+		self.data += data
+		return rva
+
+	def cstr(self, s):
+		return self.put(s.encode() + b"\0")
+
+	def patch(self, rva, data):
+		off = rva - self.rva
+		self.data[off:off + len(data)] = data
+
+
+def export_dir(rdata, text, dll_name, exports, base=1):
+	"""`exports` is a list of (ordinal, name or None, code offset or forwarder string)"""
+	count = max(ordinal for ordinal, _, _ in exports) - base + 1
+	named = sorted((name, ordinal) for ordinal, name, _ in exports if name is not None)
+
+	dir_rva = rdata.put(bytes(40), 4)
+	functions = rdata.put(bytes(4 * count), 4)
+	names = rdata.put(bytes(4 * len(named)), 4)
+	ordinals = rdata.put(bytes(2 * len(named)), 2)
+
+	for ordinal, _, target in exports:
+		if isinstance(target, str):
+			rva = rdata.cstr(target)
+		else:
+			rva = text.rva + target
+		rdata.patch(functions + 4 * (ordinal - base), struct.pack("<I", rva))
+	for i, (name, ordinal) in enumerate(named):
+		rdata.patch(names + 4 * i, struct.pack("<I", rdata.cstr(name)))
+		rdata.patch(ordinals + 2 * i, struct.pack("<H", ordinal - base))
+	name_rva = rdata.cstr(dll_name)
+	end = rdata.here()
+
+	rdata.patch(dir_rva, struct.pack("<IIHHIIIIIII",
+		0, 0x12345678, 0, 0, name_rva, base, count, len(named), functions, names, ordinals,
+	))
+	return dir_rva, end - dir_rva
+
+
+def import_dir(rdata, imports):
+	"""`imports` is a list of (dll name, [name or ordinal])"""
+	descriptors = rdata.put(bytes(20 * (len(imports) + 1)), 4)
+	for i, (dll, symbols) in enumerate(imports):
+		thunks = []
+		for sym in symbols:
+			if isinstance(sym, int):
+				thunks.append(ORDINAL_FLAG64 | sym)
+			else:
+				thunks.append(rdata.put(struct.pack("<H", 0) + sym.encode() + b"\0", 2))
+		table = struct.pack("<%dQ" % (len(thunks) + 1), *thunks, 0)
+		lookup = rdata.put(table, 8)
+		iat = rdata.put(table, 8)
+		name = rdata.cstr(dll)
+		rdata.patch(descriptors + 20 * i, struct.pack("<IIIII", lookup, 0, 0, name, iat))
+	return descriptors, 20 * (len(imports) + 1)
+
+
+def version_block(key, value=b"", text=False, children=()):
+	"""One node of a VS_VERSIONINFO tree, without trailing padding"""
+	data = bytearray(struct.pack("<HHH", 0, 0, 1 if text else 0))
+	data += key.encode("utf-16-le") + b"\0\0"
+	data += bytes(-len(data) % 4)
+	data += value
+	for child in children:
+		data += bytes(-len(data) % 4)
+		data += child
+	value_length = len(value) // 2 if text else len(value)
+	data[0:4] = struct.pack("<HH", len(data), value_length)
+	return bytes(data)
+
+
+def version_info(file_version, product_version, strings, lang="040904b0"):
+	def split(version):
+		a, b, c, d = version
+		return a << 16 | b, c << 16 | d
+	fixed = struct.pack("<13I",
+		0xfeef04bd, 0x00010000, *split(file_version), *split(product_version),
+		0x3f, 0, 0x40004, 2, 0, 0, 0,
+	)
+	table = version_block(lang, children=[
+		version_block(key, value.encode("utf-16-le") + b"\0\0", text=True)
+		for key, value in strings
+	])
+	return version_block("VS_VERSION_INFO", fixed, children=[
+		version_block("StringFileInfo", text=True, children=[table]),
+		version_block("VarFileInfo", text=True, children=[
+			version_block("Translation", struct.pack("<HH", 0x0409, 1200)),
+		]),
+	])
+
+
+def resource_dir(rsrc, kind, name, lang, data):
+	"""A resource tree holding a single resource"""
+	def directory(id, target):
+		return struct.pack("<IIHHHHII", 0, 0, 0, 0, 0, 1, id, target)
+	base = rsrc.rva
+	SUBDIR = 0x80000000
+	root = rsrc.put(bytes(24), 4)
+	names = rsrc.put(bytes(24), 4)
+	langs = rsrc.put(bytes(24), 4)
+	entry = rsrc.put(bytes(16), 4)
+	data_rva = rsrc.put(data, 4)
+	rsrc.patch(root, directory(kind, SUBDIR | (names - base)))
+	rsrc.patch(names, directory(name, SUBDIR | (langs - base)))
+	rsrc.patch(langs, directory(lang, entry - base))
+	rsrc.patch(entry, struct.pack("<IIII", data_rva, len(data), 1200, 0))
+	return root, rsrc.here() - root
+
+
+def pe(machine, timestamp, sections, directories):
+	"""`sections` is a list of (name, Blob, characteristics)"""
+	pe32plus = machine != MACHINE_I386
+	optional_size = 240 if pe32plus else 224
+	headers_size = align(0x80 + 4 + 20 + optional_size + 40 * len(sections), FILE_ALIGN)
+
+	dos = bytearray(0x80)
+	dos[0:2] = b"MZ"
+	dos[0x3c:0x40] = struct.pack("<i", 0x80)
+
+	characteristics = FILE_DLL | FILE_EXECUTABLE
+	characteristics |= FILE_LARGE_ADDRESS_AWARE if pe32plus else FILE_32BIT
+	file_header = struct.pack("<HHIIIHH",
+		machine, len(sections), timestamp, 0, 0, optional_size, characteristics,
+	)
+
+	raw = headers_size
+	section_headers = bytearray()
+	body = bytearray()
+	for name, blob, flags in sections:
+		raw_size = align(len(blob.data), FILE_ALIGN)
+		section_headers += struct.pack("<8sIIIIIIHHI",
+			name.encode(), len(blob.data), blob.rva, raw_size, raw, 0, 0, 0, 0, flags,
+		)
+		body += blob.data + bytes(raw_size - len(blob.data))
+		raw += raw_size
+	image_size = align(max([headers_size] + [b.rva + len(b.data) for _, b, _ in sections]), SECTION_ALIGN)
+
+	dirs = [(0, 0)] * 16
+	for index, entry in directories.items():
+		dirs[index] = entry
+	dirs = b"".join(struct.pack("<II", *d) for d in dirs)
+
+	if pe32plus:
+		optional = struct.pack("<HBBIIIIIQIIHHHHHHIIIIHHQQQQII",
+			0x20b, 14, 0, 0, 0, 0, 0, 0x1000, IMAGE_BASE, SECTION_ALIGN, FILE_ALIGN,
+			6, 0, 0, 0, 6, 0, 0, image_size, headers_size, 0, 2, 0x160,
+			0x100000, 0x1000, 0x100000, 0x1000, 0, 16,
+		) + dirs
+	else:
+		optional = struct.pack("<HBBIIIIIIIIIHHHHHHIIIIHHIIIIII",
+			0x10b, 14, 0, 0, 0, 0, 0, 0x1000, 0, 0x10000000, SECTION_ALIGN, FILE_ALIGN,
+			6, 0, 0, 0, 6, 0, 0, image_size, headers_size, 0, 2, 0x140,
+			0x100000, 0x1000, 0x100000, 0x1000, 0, 16,
+		) + dirs
+	assert len(optional) == optional_size
+
+	headers = dos + b"PE\0\0" + file_header + optional + section_headers
+	return headers + bytes(headers_size - len(headers)) + body
+
+
+def amd64_dll(dll_name, timestamp, exports, imports, version=None):
+	text = Blob(0x1000)
+	text.put(b"\xc3" * 16)
+	rdata = Blob(0x2000)
+	directories = {0: export_dir(rdata, text, dll_name, exports)}
+	if imports:
+		directories[1] = import_dir(rdata, imports)
+	sections = [(".text", text, SCN_CODE), (".rdata", rdata, SCN_RDATA)]
+	if version:
+		rsrc = Blob(0x3000)
+		directories[2] = resource_dir(rsrc, 16, 1, 0x409, version)
+		sections.append((".rsrc", rsrc, SCN_RDATA))
+	return pe(MACHINE_AMD64, timestamp, sections, directories)
+
+
+FIXTURES = {
+	"arcdps_fixture.dll": amd64_dll("arcdps_fixture.dll", 0x65000000, [
+		(1, "get_init_addr", 0),
+		(2, "get_release_addr", 4),
+		(5, None, 8),
+	], [
+		("KERNEL32.dll", ["GetModuleHandleW", 5]),
+		("USER32.dll", ["MessageBoxW"]),
+	], version_info((1, 2, 3, 4), (1, 2, 0, 0), [
+		("CompanyName", "arcloader"),
+		("FileDescription", "arcdps test fixture"),
+		("FileVersion", "1.2.3.4"),
+		("ProductName", "Fixture Addon"),
+		("ProductVersion", "1.2"),
+	])),
+	"nexus_fixture.dll": amd64_dll("nexus_fixture.dll", 0x66000000, [
+		(1, "GetAddonDef", 0),
+		(2, "Sleep", "KERNEL32.Sleep"),
+	], [
+		("KERNEL32.dll", ["GetProcAddress"]),
+	]),
+	"i386_fixture.dll": pe(MACHINE_I386, 0x40000000, [], {}),
+}
+
+if __name__ == "__main__":
+	for name, data in FIXTURES.items():
+		with open(os.path.join(HERE, name), "wb") as f:
+			f.write(data)
@@ -0,0 +1,369 @@
+use crate::hook::HookError;
+
+/// The architectural limit, anything longer faults
+pub const MAX_INSTRUCTION_LEN: usize = 15;
+
+/// Just enough of an x86-64 instruction to move it somewhere else
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Instruction {
+	pub len: usize,
+	/// Offset of a `[rip+disp32]` displacement within the instruction
+	pub rip_disp: Option<usize>,
+	pub flow: Flow,
+}
+
+/// Where execution goes after an instruction
+///
+/// Relative displacements are from the end of the instruction.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Flow {
+	Next,
+	/// `ret`
+	Return,
+	/// `jmp` through a register or memory
+	JumpIndirect,
+	Jump { rel: i32 },
+	Call { rel: i32 },
+	/// `jcc`, with `cond` being the low nibble of its opcode
+	Branch { cond: u8, rel: i32 },
+	/// `loop` and `jrcxz`, which only come in rel8 flavours
+	Loop { rel: i32 },
+}
+
+impl Instruction {
+	/// Decode the first instruction of `code`, assuming 64-bit mode
+	pub fn decode(code: &[u8]) -> Result<Self, HookError> {
+		Decoder { code, pos: 0 }.decode()
+	}
+
+	/// Whether execution never falls through to whatever follows
+	pub fn is_terminal(&self) -> bool {
+		matches!(self.flow, Flow::Return | Flow::Jump { .. } | Flow::JumpIndirect)
+	}
+
+	/// The destination of a relative branch, given the address of the instruction
+	pub fn branch_target(&self, ip: u64) -> Option<u64> {
+		let rel = match self.flow {
+			Flow::Jump { rel } | Flow::Call { rel } | Flow::Branch { rel, .. } | Flow::Loop { rel } => rel,
+			Flow::Next | Flow::Return | Flow::JumpIndirect => return None,
+		};
+		Some(ip.wrapping_add(self.len as u64).wrapping_add(rel as i64 as u64))
+	}
+}
+
+struct Decoder<'c> {
+	code: &'c [u8],
+	pos: usize,
+}
+
+impl<'c> Decoder<'c> {
+	fn peek(&self) -> Result<u8, HookError> {
+		self.code.get(self.pos).copied()
+			.ok_or(HookError::Truncated)
+	}
+
+	fn byte(&mut self) -> Result<u8, HookError> {
+		let b = self.peek()?;
+		self.pos += 1;
+		Ok(b)
+	}
+
+	fn skip(&mut self, len: usize) -> Result<(), HookError> {
+		match self.pos + len <= self.code.len() {
+			true => {
+				self.pos += len;
+				Ok(())
+			},
+			false => Err(HookError::Truncated),
+		}
+	}
+
+	fn rel(&mut self, size: usize) -> Result<i32, HookError> {
+		let start = self.pos;
+		self.skip(size)?;
+		Ok(match size {
+			1 => self.code[start] as i8 as i32,
+			_ => i32::from_le_bytes([self.code[start], self.code[start + 1], self.code[start + 2], self.code[start + 3]]),
+		})
+	}
+
+	fn unknown(&self, opcode: u8) -> HookError {
+		HookError::Unknown {
+			offset: 0,
+			opcode,
+		}
+	}
+
+	/// Skip over a ModRM byte and whatever SIB and displacement it implies
+	fn modrm(&mut self) -> Result<Option<usize>, HookError> {
+		let modrm = self.byte()?;
+		let (mode, rm) = (modrm >> 6, modrm & 7);
+		let mut rip_disp = None;
+		match (mode, rm) {
+			(3, _) => return Ok(None),
+			(_, 4) => {
+				let sib = self.byte()?;
+				if mode == 0 && sib & 7 == 5 {
+					self.skip(4)?;
+				}
+			},
+			(0, 5) => {
+				rip_disp = Some(self.pos);
+				self.skip(4)?;
+			},
+			_ => (),
+		}
+		match mode {
+			1 => self.skip(1)?,
+			2 => self.skip(4)?,
+			_ => (),
+		}
+		Ok(rip_disp)
+	}
+
+	fn modrm_reg(&self) -> Result<u8, HookError> {
+		self.peek().map(|modrm| (modrm >> 3) & 7)
+	}
+
+	fn decode(mut self) -> Result<Instruction, HookError> {
+		let mut opsize16 = false;
+		let mut addr32 = false;
+		loop {
+			match self.peek()? {
+				0x66 => opsize16 = true,
+				0x67 => addr32 = true,
+				0xf0 | 0xf2 | 0xf3 | 0x26 | 0x2e | 0x36 | 0x3e | 0x64 | 0x65 => (),
+				_ => break,
+			}
+			self.pos += 1;
+		}
+		let rex_w = match self.peek()? {
+			rex @ 0x40..=0x4f => {
+				self.pos += 1;
+				rex & 0x08 != 0
+			},
+			_ => false,
+		};
+		let immz = if opsize16 { 2 } else { 4 };
+
+		let opcode = self.byte()?;
+		let mut flow = Flow::Next;
+		let (modrm, imm) = match opcode {
+			0x0f => return self.decode_0f(),
+			0xc4 | 0xc5 | 0x62 => return self.decode_vex(opcode),
+			0x70..=0x7f => {
+				flow = Flow::Branch { cond: opcode & 0x0f, rel: self.rel(1)? };
+				(false, 0)
+			},
+			0xe0..=0xe3 => {
+				flow = Flow::Loop { rel: self.rel(1)? };
+				(false, 0)
+			},
+			0xe8 => {
+				flow = Flow::Call { rel: self.rel(4)? };
+				(false, 0)
+			},
+			0xe9 | 0xeb => {
+				flow = Flow::Jump { rel: self.rel(if opcode == 0xe9 { 4 } else { 1 })? };
+				(false, 0)
+			},
+			0xc3 | 0xcb => {
+				flow = Flow::Return;
+				(false, 0)
+			},
+			0xc2 | 0xca => {
+				flow = Flow::Return;
+				(false, 2)
+			},
+			0x00..=0x3f => match opcode & 7 {
+				0..=3 => (true, 0),
+				4 => (false, 1),
+				5 => (false, immz),
+				// segment pushes, BCD and prefixes, none of which exist here
+				_ => return Err(self.unknown(opcode)),
+			},
+			0x50..=0x5f => (false, 0),
+			0x63 => (true, 0),
+			0x68 => (false, immz),
+			0x69 => (true, immz),
+			0x6a => (false, 1),
+			0x6b => (true, 1),
+			0x6c..=0x6f => (false, 0),
+			0x80 | 0x83 => (true, 1),
+			0x81 => (true, immz),
+			0x84..=0x8f => (true, 0),
+			0x90..=0x99 | 0x9b..=0x9f => (false, 0),
+			// moffs are address-sized
+			0xa0..=0xa3 => (false, if addr32 { 4 } else { 8 }),
+			0xa4..=0xa7 | 0xaa..=0xaf => (false, 0),
+			0xa8 => (false, 1),
+			0xa9 => (false, immz),
+			0xb0..=0xb7 => (false, 1),
+			0xb8..=0xbf => (false, if rex_w { 8 } else { immz }),
+			0xc0 | 0xc1 | 0xc6 => (true, 1),
+			// xbegin is relative, and not worth relocating
+			0xc7 if self.peek()? == 0xf8 => return Err(self.unknown(opcode)),
+			0xc7 => (true, immz),
+			0xc8 => (false, 3),
+			0xc9 | 0xcc | 0xcf => (false, 0),
+			0xcd => (false, 1),
+			0xd0..=0xd3 | 0xd8..=0xdf => (true, 0),
+			0xd7 => (false, 0),
+			0xe4..=0xe7 => (false, 1),
+			0xec..=0xef => (false, 0),
+			0xf1 | 0xf4 | 0xf5 | 0xf8..=0xfd => (false, 0),
+			// only test takes an immediate
+			0xf6 => (true, if self.modrm_reg()? <= 1 { 1 } else { 0 }),
+			0xf7 => (true, if self.modrm_reg()? <= 1 { immz } else { 0 }),
+			0xfe => (true, 0),
+			0xff => {
+				if let 4 | 5 = self.modrm_reg()? {
+					flow = Flow::JumpIndirect;
+				}
+				(true, 0)
+			},
+			_ => return Err(self.unknown(opcode)),
+		};
+		self.finish(modrm, imm, flow)
+	}
+
+	fn decode_0f(mut self) -> Result<Instruction, HookError> {
+		let opcode = self.byte()?;
+		let (modrm, imm) = match opcode {
+			0x38 => {
+				self.byte()?;
+				(true, 0)
+			},
+			0x3a => {
+				self.byte()?;
+				(true, 1)
+			},
+			0x80..=0x8f => {
+				let flow = Flow::Branch { cond: opcode & 0x0f, rel: self.rel(4)? };
+				return self.finish(false, 0, flow)
+			},
+			0x05..=0x09 | 0x0b | 0x0e | 0x30..=0x37 | 0x77 | 0xa0..=0xa2 | 0xa8..=0xaa | 0xc8..=0xcf => (false, 0),
+			0x70..=0x73 | 0xa4 | 0xac | 0xba | 0xc2 | 0xc4..=0xc6 => (true, 1),
+			0x00..=0x03 | 0x0d | 0x10..=0x23 | 0x28..=0x2f | 0x40..=0x6f | 0x74..=0x76 | 0x78..=0x7f
+				| 0x90..=0x9f | 0xa3 | 0xa5 | 0xab | 0xad..=0xb9 | 0xbb..=0xc1 | 0xc3 | 0xc7 | 0xd0..=0xff => (true, 0),
+			_ => return Err(self.unknown(opcode)),
+		};
+		self.finish(modrm, imm, Flow::Next)
+	}
+
+	/// VEX and EVEX encodings, which carry their opcode map along with them
+	fn decode_vex(mut self, prefix: u8) -> Result<Instruction, HookError> {
+		let map = match prefix {
+			0xc5 => {
+				self.byte()?;
+				1
+			},
+			0xc4 => {
+				let map = self.byte()? & 0x1f;
+				self.byte()?;
+				map
+			},
+			_ => {
+				let map = self.byte()? & 0x07;
+				self.skip(2)?;
+				map
+			},
+		};
+		let opcode = self.byte()?;
+		let (modrm, imm) = match (map, opcode) {
+			// vzeroupper/vzeroall
+			(1, 0x77) => (false, 0),
+			(1, 0x70..=0x73 | 0xc2 | 0xc4..=0xc6) => (true, 1),
+			(1 | 2 | 5 | 6, _) => (true, 0),
+			(3, _) => (true, 1),
+			_ => return Err(self.unknown(prefix)),
+		};
+		self.finish(modrm, imm, Flow::Next)
+	}
+
+	fn finish(mut self, modrm: bool, imm: usize, flow: Flow) -> Result<Instruction, HookError> {
+		let rip_disp = match modrm {
+			true => self.modrm()?,
+			false => None,
+		};
+		self.skip(imm)?;
+		if self.pos > MAX_INSTRUCTION_LEN {
+			return Err(self.unknown(self.code[0]))
+		}
+		Ok(Instruction {
+			len: self.pos,
+			rip_disp,
+			flow,
+		})
+	}
+}
+
+#[test]
+fn decode_lengths() {
+	let cases: &[(&[u8], usize)] = &[
+		(&[0x55], 1), // push rbp
+		(&[0x48, 0x89, 0xe5], 3), // mov rbp, rsp
+		(&[0x48, 0x89, 0x5c, 0x24, 0x08], 5), // mov [rsp+8], rbx
+		(&[0x48, 0x83, 0xec, 0x28], 4), // sub rsp, 0x28
+		(&[0x48, 0x81, 0xec, 0x00, 0x01, 0x00, 0x00], 7), // sub rsp, 0x100
+		(&[0x40, 0x53], 2), // push rbx
+		(&[0x41, 0x57], 2), // push r15
+		(&[0x66, 0xb8, 0x34, 0x12], 4), // mov ax, 0x1234
+		(&[0x48, 0xb8, 1, 2, 3, 4, 5, 6, 7, 8], 10), // mov rax, imm64
+		(&[0x48, 0xc7, 0x44, 0x24, 0x10, 1, 0, 0, 0], 9), // mov qword [rsp+0x10], 1
+		(&[0xf6, 0x41, 0x08, 0x01], 4), // test byte [rcx+8], 1
+		(&[0xf7, 0xd8], 2), // neg eax
+		(&[0x0f, 0x1f, 0x44, 0x00, 0x00], 5), // nop dword [rax+rax]
+		(&[0x66, 0x0f, 0x1f, 0x84, 0x00, 0, 0, 0, 0], 9), // nop word [rax+rax+0]
+		(&[0xf3, 0x0f, 0x1e, 0xfa], 4), // endbr64
+		(&[0x0f, 0xb6, 0xc1], 3), // movzx eax, cl
+		(&[0x66, 0x0f, 0x3a, 0x0f, 0xc1, 0x08], 6), // palignr xmm0, xmm1, 8
+		(&[0xc5, 0xf8, 0x77], 3), // vzeroupper
+		(&[0xc5, 0xfc, 0x28, 0x44, 0x24, 0x20], 6), // vmovaps ymm0, [rsp+0x20]
+		(&[0xc4, 0xe3, 0x7d, 0x18, 0xc1, 0x01], 6), // vinsertf128 ymm0, ymm0, xmm1, 1
+		(&[0x62, 0xf1, 0x7c, 0x48, 0x28, 0xc1], 6), // vmovaps zmm0, zmm1
+		(&[0x4c, 0x8d, 0x04, 0xcd, 0, 0, 0, 0], 8), // lea r8, [rcx*8+0]
+		(&[0xcc], 1),
+		(&[0xc3], 1),
+		(&[0xc2, 0x08, 0x00], 3),
+	];
+	for &(code, len) in cases {
+		let insn = Instruction::decode(code).unwrap_or_else(|e| panic!("{code:02x?}: {e}"));
+		assert_eq!(insn.len, len, "{code:02x?}");
+		assert_eq!(insn.rip_disp, None, "{code:02x?}");
+	}
+
+	assert!(Instruction::decode(&[0x48, 0x8b]).is_err());
+	assert!(Instruction::decode(&[0x06]).is_err());
+	assert!(Instruction::decode(&[0x66; 16]).is_err());
+}
+
+#[test]
+fn decode_relative() {
+	// mov rax, [rip+0x1000]
+	let insn = Instruction::decode(&[0x48, 0x8b, 0x05, 0x00, 0x10, 0x00, 0x00]).unwrap();
+	assert_eq!((insn.len, insn.rip_disp, insn.flow), (7, Some(3), Flow::Next));
+	// cmp dword [rip-0x10], 1
+	let insn = Instruction::decode(&[0x83, 0x3d, 0xf0, 0xff, 0xff, 0xff, 0x01]).unwrap();
+	assert_eq!((insn.len, insn.rip_disp), (7, Some(2)));
+	// jmp [rip+0]
+	let insn = Instruction::decode(&[0xff, 0x25, 0, 0, 0, 0]).unwrap();
+	assert_eq!((insn.len, insn.rip_disp, insn.flow), (6, Some(2), Flow::JumpIndirect));
+	assert!(insn.is_terminal());
+	// call [rip+8] keeps going after it returns
+	let insn = Instruction::decode(&[0xff, 0x15, 8, 0, 0, 0]).unwrap();
+	assert_eq!(insn.flow, Flow::Next);
+
+	let insn = Instruction::decode(&[0xe8, 0x10, 0, 0, 0]).unwrap();
+	assert_eq!((insn.len, insn.flow), (5, Flow::Call { rel: 0x10 }));
+	assert_eq!(insn.branch_target(0x1000), Some(0x1015));
+	let insn = Instruction::decode(&[0xeb, 0xfe]).unwrap();
+	assert_eq!((insn.len, insn.flow), (2, Flow::Jump { rel: -2 }));
+	assert_eq!(insn.branch_target(0x1000), Some(0x1000));
+	let insn = Instruction::decode(&[0x74, 0x05]).unwrap();
+	assert_eq!(insn.flow, Flow::Branch { cond: 4, rel: 5 });
+	let insn = Instruction::decode(&[0x0f, 0x85, 0x00, 0x01, 0x00, 0x00]).unwrap();
+	assert_eq!((insn.len, insn.flow), (6, Flow::Branch { cond: 5, rel: 0x100 }));
+	let insn = Instruction::decode(&[0xe3, 0x02]).unwrap();
+	assert_eq!(insn.flow, Flow::Loop { rel: 2 });
+}
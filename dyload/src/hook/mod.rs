@@ -0,0 +1,246 @@
+//! Inline function hooking
+//!
+//! The start of a target function is overwritten with a jump to its detour,
+//! and the instructions that were displaced get moved to a trampoline that
+//! then continues on to the rest of the original function.
+
+use std::{error::Error, fmt};
+
+pub mod decode;
+
+pub use self::decode::{Flow, Instruction};
+
+/// `jmp rel32`, the patch written over a target
+pub const JMP_REL32_LEN: usize = 5;
+/// `jmp [rip+0]` followed by the destination
+pub const JMP_ABS_LEN: usize = 14;
+/// `call [rip+2]; jmp +8` followed by the destination
+pub const CALL_ABS_LEN: usize = 16;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HookError {
+	/// Ran out of bytes in the middle of an instruction
+	Truncated,
+	/// Not a valid instruction, or at least not one we know about
+	Unknown { offset: usize, opcode: u8 },
+	/// The function ends before there's room for a patch
+	TooShort,
+	/// An instruction that can't run from anywhere else
+	Unrelocatable { offset: usize },
+	/// A relocated displacement no longer fits in 32 bits
+	OutOfRange { offset: usize },
+	NotExecutable,
+	MemoryAlloc,
+	MemoryProtect,
+}
+
+impl HookError {
+	fn at(self, offset: usize) -> Self {
+		match self {
+			Self::Unknown { opcode, .. } => Self::Unknown { offset, opcode },
+			e => e,
+		}
+	}
+}
+
+impl fmt::Display for HookError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Truncated => write!(f, "instruction truncated"),
+			Self::Unknown { offset, opcode } => write!(f, "unknown instruction {opcode:#04x} at +{offset:#x}"),
+			Self::TooShort => write!(f, "function too short to patch"),
+			Self::Unrelocatable { offset } => write!(f, "cannot relocate instruction at +{offset:#x}"),
+			Self::OutOfRange { offset } => write!(f, "relocated instruction at +{offset:#x} out of range"),
+			Self::NotExecutable => write!(f, "target is not executable"),
+			Self::MemoryAlloc => write!(f, "failed to allocate trampoline"),
+			Self::MemoryProtect => write!(f, "failed to change memory protection"),
+		}
+	}
+}
+
+impl Error for HookError {}
+
+/// The displaced start of a function, relocated to run from somewhere else
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Trampoline {
+	pub code: Vec<u8>,
+	/// How much of the target was moved
+	///
+	/// This covers whole instructions, so it may exceed the patch itself.
+	pub displaced: usize,
+	/// Offsets of each displaced instruction, in the target and in [code](Self::code)
+	pub boundaries: Vec<(usize, usize)>,
+}
+
+impl Trampoline {
+	/// Move at least `patch_len` bytes of `code` from `target` to `address`
+	pub fn build(code: &[u8], target: u64, address: u64, patch_len: usize) -> Result<Self, HookError> {
+		let mut out = Self::default();
+		let mut destinations = Vec::new();
+		let mut ended = false;
+		let mut pos = 0;
+		while pos < patch_len {
+			if ended {
+				// whatever padding follows a return or jump is free to overwrite
+				match code.get(pos) {
+					Some(0xcc | 0x90) => {
+						pos += 1;
+						continue
+					},
+					Some(_) => return Err(HookError::TooShort),
+					None => return Err(HookError::Truncated),
+				}
+			}
+
+			let insn = Instruction::decode(&code[pos..])
+				.map_err(|e| e.at(pos))?;
+			let bytes = &code[pos..pos + insn.len];
+			let ip = target.wrapping_add(pos as u64);
+			let start = out.code.len();
+			let new_ip = address.wrapping_add(start as u64);
+			out.boundaries.push((pos, start));
+
+			let dest = insn.branch_target(ip);
+			if let Some(dest) = dest {
+				destinations.push((pos, dest));
+			}
+			match (insn.flow, dest) {
+				(Flow::Next | Flow::Return | Flow::JumpIndirect, _) => {
+					out.code.extend_from_slice(bytes);
+					if let Some(at) = insn.rip_disp {
+						let disp = i32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]);
+						let dest = ip.wrapping_add(insn.len as u64).wrapping_add(disp as i64 as u64);
+						let disp = dest.wrapping_sub(new_ip.wrapping_add(insn.len as u64)) as i64;
+						let disp = i32::try_from(disp)
+							.map_err(|_| HookError::OutOfRange { offset: pos })?;
+						out.code[start + at..start + at + 4].copy_from_slice(&disp.to_le_bytes());
+					}
+				},
+				(Flow::Jump { .. }, Some(dest)) => {
+					out.code.extend_from_slice(&jmp_abs(dest));
+				},
+				(Flow::Call { .. }, Some(dest)) => {
+					out.code.extend_from_slice(&call_abs(dest));
+				},
+				(Flow::Branch { cond, .. }, Some(dest)) => {
+					// skip over the jump unless the opposite condition holds
+					out.code.extend_from_slice(&[0x70 | (cond ^ 1), JMP_ABS_LEN as u8]);
+					out.code.extend_from_slice(&jmp_abs(dest));
+				},
+				_ => return Err(HookError::Unrelocatable { offset: pos }),
+			}
+			ended = insn.is_terminal();
+			pos += insn.len;
+		}
+
+		// branching back into the middle of the patch can't end well
+		let displaced = target..target.wrapping_add(pos as u64);
+		if let Some(&(offset, _)) = destinations.iter().find(|&&(_, dest)| dest > displaced.start && displaced.contains(&dest)) {
+			return Err(HookError::Unrelocatable { offset })
+		}
+
+		if !ended {
+			out.code.extend_from_slice(&jmp_abs(displaced.end));
+		}
+		out.displaced = pos;
+		Ok(out)
+	}
+
+	/// Where an instruction pointer within the displaced target should move to
+	pub fn relocate_ip(&self, offset: usize) -> Option<usize> {
+		self.boundaries.iter()
+			.find(|&&(from, _)| from == offset)
+			.map(|&(_, to)| to)
+	}
+
+	/// Where an instruction pointer within the trampoline should move back to
+	pub fn restore_ip(&self, offset: usize) -> Option<usize> {
+		self.boundaries.iter()
+			.find(|&&(_, to)| to == offset)
+			.map(|&(from, _)| from)
+	}
+}
+
+/// `jmp rel32` from `from` to `to`, if they're close enough together
+pub fn jmp_rel32(from: u64, to: u64) -> Option<[u8; JMP_REL32_LEN]> {
+	let rel = i32::try_from(to.wrapping_sub(from.wrapping_add(JMP_REL32_LEN as u64)) as i64).ok()?;
+	let [a, b, c, d] = rel.to_le_bytes();
+	Some([0xe9, a, b, c, d])
+}
+
+pub fn jmp_abs(to: u64) -> [u8; JMP_ABS_LEN] {
+	let mut code = [0xff, 0x25, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+	code[6..].copy_from_slice(&to.to_le_bytes());
+	code
+}
+
+pub fn call_abs(to: u64) -> [u8; CALL_ABS_LEN] {
+	let mut code = [0xff, 0x15, 0x02, 0, 0, 0, 0xeb, 0x08, 0, 0, 0, 0, 0, 0, 0, 0];
+	code[8..].copy_from_slice(&to.to_le_bytes());
+	code
+}
+
+#[test]
+fn trampoline_prologue() {
+	// push rbx; sub rsp, 0x20; mov rbx, rcx
+	let code = [0x40, 0x53, 0x48, 0x83, 0xec, 0x20, 0x48, 0x8b, 0xd9];
+	let tramp = Trampoline::build(&code, 0x1000, 0x9000, JMP_REL32_LEN).unwrap();
+	assert_eq!(tramp.displaced, 6);
+	assert_eq!(tramp.boundaries, [(0, 0), (2, 2)]);
+	assert_eq!(&tramp.code[..6], &code[..6]);
+	assert_eq!(&tramp.code[6..], &jmp_abs(0x1006));
+	assert_eq!(tramp.relocate_ip(2), Some(2));
+	assert_eq!(tramp.relocate_ip(3), None);
+	assert_eq!(tramp.restore_ip(2), Some(2));
+}
+
+#[test]
+fn trampoline_relocation() {
+	// mov rax, [rip+0x100]; ret
+	let code = [0x48, 0x8b, 0x05, 0x00, 0x01, 0x00, 0x00, 0xc3];
+	let tramp = Trampoline::build(&code, 0x1000, 0x2000, JMP_REL32_LEN).unwrap();
+	// still pointing at 0x1107
+	assert_eq!(&tramp.code[..7], &[0x48, 0x8b, 0x05, 0x00, 0xf1, 0xff, 0xff]);
+	assert_eq!(&tramp.code[7..], &jmp_abs(0x1007));
+
+	// call +0x10; jz +0x20
+	let code = [0xe8, 0x10, 0x00, 0x00, 0x00, 0x74, 0x20];
+	let tramp = Trampoline::build(&code, 0x1000, 0x2000, JMP_REL32_LEN).unwrap();
+	assert_eq!(tramp.displaced, 5);
+	assert_eq!(&tramp.code[..CALL_ABS_LEN], &call_abs(0x1015));
+	assert_eq!(&tramp.code[CALL_ABS_LEN..], &jmp_abs(0x1005));
+	let tramp = Trampoline::build(&code, 0x1000, 0x2000, 6).unwrap();
+	assert_eq!(tramp.displaced, 7);
+	assert_eq!(&tramp.code[CALL_ABS_LEN..CALL_ABS_LEN + 2], &[0x75, JMP_ABS_LEN as u8]);
+	assert_eq!(&tramp.code[CALL_ABS_LEN + 2..][..JMP_ABS_LEN], &jmp_abs(0x1027));
+	assert_eq!(tramp.boundaries, [(0, 0), (5, CALL_ABS_LEN)]);
+
+	// jmp [rip+0], as found in import thunks
+	let code = [0xff, 0x25, 0x00, 0x00, 0x00, 0x00, 0xcc, 0xcc];
+	let tramp = Trampoline::build(&code, 0x7fff_0000, 0x7ff0_0000, JMP_REL32_LEN).unwrap();
+	assert_eq!(tramp.code, [0xff, 0x25, 0x00, 0x00, 0x0f, 0x00]);
+}
+
+#[test]
+fn trampoline_errors() {
+	// ret; mov eax, eax
+	assert_eq!(Trampoline::build(&[0xc3, 0x89, 0xc0, 0x90, 0x90], 0, 0, JMP_REL32_LEN), Err(HookError::TooShort));
+	// ret, padded
+	let tramp = Trampoline::build(&[0xc3, 0xcc, 0xcc, 0xcc, 0xcc], 0, 0, JMP_REL32_LEN).unwrap();
+	assert_eq!((&tramp.code[..], tramp.displaced), (&[0xc3][..], 5));
+	// jrcxz +1; nop; ...
+	assert_eq!(Trampoline::build(&[0xe3, 0x01, 0x90, 0x90, 0x90, 0x90], 0, 0, JMP_REL32_LEN), Err(HookError::Unrelocatable { offset: 0 }));
+	// jz +1; nop; nop; nop
+	assert_eq!(Trampoline::build(&[0x74, 0x01, 0x90, 0x90, 0x90, 0x90], 0, 0, JMP_REL32_LEN), Err(HookError::Unrelocatable { offset: 0 }));
+	// mov rax, [rip+0] moved 4GB away
+	assert_eq!(Trampoline::build(&[0x48, 0x8b, 0x05, 0, 0, 0, 0], 0, 0x1_0000_0000, JMP_REL32_LEN), Err(HookError::OutOfRange { offset: 0 }));
+	assert_eq!(Trampoline::build(&[0x55, 0x06], 0, 0, JMP_REL32_LEN), Err(HookError::Unknown { offset: 1, opcode: 0x06 }));
+	assert_eq!(Trampoline::build(&[0x55, 0x48], 0, 0, JMP_REL32_LEN), Err(HookError::Truncated));
+}
+
+#[test]
+fn patch_jumps() {
+	assert_eq!(jmp_rel32(0x1000, 0x2000), Some([0xe9, 0xfb, 0x0f, 0x00, 0x00]));
+	assert_eq!(jmp_rel32(0x2000, 0x1000), Some([0xe9, 0xfb, 0xef, 0xff, 0xff]));
+	assert_eq!(jmp_rel32(0, 0x1_0000_0000), None);
+}
@@ -1,3 +1,4 @@
 pub mod log;
+pub mod hook;
 #[cfg(feature = "windows")]
 pub mod windows;
@@ -0,0 +1,359 @@
+use std::{ffi::c_void, mem, ptr::{self, NonNull}, slice, sync::Mutex};
+use windows::Win32::{
+	Foundation::{CloseHandle, HANDLE},
+	System::{
+		Diagnostics::{
+			Debug::{FlushInstructionCache, GetThreadContext, SetThreadContext, CONTEXT, CONTEXT_CONTROL_AMD64},
+			ToolHelp::{CreateToolhelp32Snapshot, Thread32First, Thread32Next, TH32CS_SNAPTHREAD, THREADENTRY32},
+		},
+		Memory::{
+			VirtualAlloc, VirtualFree, VirtualProtect, VirtualQuery, MEMORY_BASIC_INFORMATION,
+			MEM_COMMIT, MEM_FREE, MEM_RELEASE, MEM_RESERVE,
+			PAGE_EXECUTE_READ, PAGE_EXECUTE_READWRITE, PAGE_EXECUTE_WRITECOPY, PAGE_GUARD, PAGE_PROTECTION_FLAGS,
+		},
+		SystemInformation::{GetSystemInfo, SYSTEM_INFO},
+		Threading::{GetCurrentProcess, GetCurrentProcessId, GetCurrentThreadId, OpenThread, ResumeThread, SuspendThread, THREAD_GET_CONTEXT, THREAD_SET_CONTEXT, THREAD_SUSPEND_RESUME},
+	},
+};
+use crate::hook::{jmp_abs, jmp_rel32, HookError, Trampoline, JMP_ABS_LEN, JMP_REL32_LEN};
+use crate::log::*;
+
+/// An inline hook, patched into its target only while enabled
+///
+/// The target jumps to a relay next to the trampoline, which can then reach
+/// the detour wherever it happens to be.
+pub struct Hook {
+	target: NonNull<u8>,
+	detour: *const c_void,
+	slot: NonNull<u8>,
+	trampoline: Trampoline,
+	patch: [u8; JMP_REL32_LEN],
+	original: [u8; JMP_REL32_LEN],
+	enabled: bool,
+}
+
+impl Hook {
+	pub const SLOT_SIZE: usize = 0x80;
+	const TRAMPOLINE_OFFSET: usize = 0x10;
+	/// More than enough to cover a patch split across instructions, plus any padding
+	const PEEK_LEN: usize = 0x40;
+
+	/// Prepare to redirect `target` to `detour`, without patching anything yet
+	pub unsafe fn new(target: *const c_void, detour: *const c_void) -> Result<Self, HookError> {
+		let target = NonNull::new(target as *mut u8)
+			.ok_or(HookError::NotExecutable)?;
+		let available = executable_len(target.as_ptr())?;
+		let code = slice::from_raw_parts(target.as_ptr(), available.min(Self::PEEK_LEN));
+
+		let slot = SlotBlock::alloc(target.as_ptr() as usize)?;
+		let relay = slot.as_ptr();
+		let address = relay.add(Self::TRAMPOLINE_OFFSET);
+		let built = Trampoline::build(code, target.as_ptr() as u64, address as u64, JMP_REL32_LEN)
+			.and_then(|trampoline| match trampoline.code.len() <= Self::SLOT_SIZE - Self::TRAMPOLINE_OFFSET {
+				true => Ok(trampoline),
+				false => Err(HookError::MemoryAlloc),
+			})
+			.and_then(|trampoline| jmp_rel32(target.as_ptr() as u64, relay as u64)
+				.map(|patch| (trampoline, patch))
+				.ok_or(HookError::MemoryAlloc)
+			);
+		let (trampoline, patch) = match built {
+			Ok(built) => built,
+			Err(e) => {
+				SlotBlock::free(slot);
+				return Err(e)
+			},
+		};
+
+		ptr::copy_nonoverlapping(jmp_abs(detour as u64).as_ptr(), relay, JMP_ABS_LEN);
+		ptr::copy_nonoverlapping(trampoline.code.as_ptr(), address, trampoline.code.len());
+		let mut original = [0u8; JMP_REL32_LEN];
+		original.copy_from_slice(&code[..JMP_REL32_LEN]);
+
+		Ok(Self {
+			target,
+			detour,
+			slot,
+			trampoline,
+			patch,
+			original,
+			enabled: false,
+		})
+	}
+
+	pub fn target(&self) -> *const c_void {
+		self.target.as_ptr() as *const c_void
+	}
+
+	pub fn detour(&self) -> *const c_void {
+		self.detour
+	}
+
+	/// Calls the original function
+	pub fn trampoline(&self) -> *const c_void {
+		unsafe {
+			self.slot.as_ptr().add(Self::TRAMPOLINE_OFFSET) as *const c_void
+		}
+	}
+
+	pub fn is_enabled(&self) -> bool {
+		self.enabled
+	}
+
+	pub unsafe fn enable(&mut self) -> Result<(), HookError> {
+		Self::set_enabled(&mut [self], true)
+	}
+
+	pub unsafe fn disable(&mut self) -> Result<(), HookError> {
+		Self::set_enabled(&mut [self], false)
+	}
+
+	/// Patch (or unpatch) several hooks at once, while every other thread is suspended
+	pub unsafe fn set_enabled(hooks: &mut [&mut Self], enabled: bool) -> Result<(), HookError> {
+		let threads = ThreadFreeze::new();
+		for hook in hooks.iter_mut().filter(|hook| hook.enabled != enabled) {
+			hook.write_patch(enabled)?;
+			threads.relocate(hook);
+		}
+		Ok(())
+	}
+
+	unsafe fn write_patch(&mut self, enabled: bool) -> Result<(), HookError> {
+		let bytes = match enabled {
+			true => &self.patch,
+			false => &self.original,
+		};
+		let target = self.target.as_ptr();
+		let mut protect = PAGE_PROTECTION_FLAGS::default();
+		VirtualProtect(target as *const c_void, JMP_REL32_LEN, PAGE_EXECUTE_READWRITE, &mut protect)
+			.map_err(|_| HookError::MemoryProtect)?;
+		ptr::copy_nonoverlapping(bytes.as_ptr(), target, JMP_REL32_LEN);
+		let _ = VirtualProtect(target as *const c_void, JMP_REL32_LEN, protect, &mut protect);
+		let _ = FlushInstructionCache(GetCurrentProcess(), Some(target as *const c_void), JMP_REL32_LEN);
+		self.enabled = enabled;
+		Ok(())
+	}
+}
+
+impl Drop for Hook {
+	fn drop(&mut self) {
+		if self.enabled {
+			if let Err(_e) = unsafe { self.disable() } {
+				// the relay can't go anywhere now
+				warn!("failed to unhook {:?}: {_e}", self.target);
+				return
+			}
+		}
+		SlotBlock::free(self.slot);
+	}
+}
+
+unsafe impl Send for Hook {}
+
+/// Relays and trampolines are only reachable by rel32 jumps if they're close by
+const MAX_DISTANCE: usize = 0x4000_0000;
+
+static SLOT_BLOCKS: Mutex<Vec<SlotBlock>> = Mutex::new(Vec::new());
+
+struct SlotBlock {
+	base: usize,
+	free: Vec<usize>,
+}
+
+impl SlotBlock {
+	const SIZE: usize = 0x10000;
+	const SLOTS: usize = Self::SIZE / Hook::SLOT_SIZE;
+
+	fn alloc(target: usize) -> Result<NonNull<u8>, HookError> {
+		let mut blocks = SLOT_BLOCKS.lock()
+			.unwrap_or_else(|e| e.into_inner());
+		let index = blocks.iter()
+			.position(|block| !block.free.is_empty() && block.base.abs_diff(target) < MAX_DISTANCE - Self::SIZE);
+		let index = match index {
+			Some(index) => index,
+			None => {
+				let base = unsafe { alloc_near(target, Self::SIZE) }
+					.ok_or(HookError::MemoryAlloc)?;
+				blocks.push(Self {
+					base,
+					free: (0..Self::SLOTS).rev().collect(),
+				});
+				blocks.len() - 1
+			},
+		};
+		let block = &mut blocks[index];
+		let slot = block.free.pop().unwrap();
+		NonNull::new((block.base + slot * Hook::SLOT_SIZE) as *mut u8)
+			.ok_or(HookError::MemoryAlloc)
+	}
+
+	fn free(slot: NonNull<u8>) {
+		let slot = slot.as_ptr() as usize;
+		let mut blocks = SLOT_BLOCKS.lock()
+			.unwrap_or_else(|e| e.into_inner());
+		let index = match blocks.iter().position(|block| (block.base..block.base + Self::SIZE).contains(&slot)) {
+			Some(index) => index,
+			None => return,
+		};
+		let block = &mut blocks[index];
+		block.free.push((slot - block.base) / Hook::SLOT_SIZE);
+		if block.free.len() == Self::SLOTS {
+			let block = blocks.swap_remove(index);
+			let _ = unsafe { VirtualFree(block.base as *mut c_void, 0, MEM_RELEASE) };
+		}
+	}
+}
+
+unsafe fn query(address: usize) -> Option<MEMORY_BASIC_INFORMATION> {
+	let mut info = MEMORY_BASIC_INFORMATION::default();
+	match VirtualQuery(Some(address as *const c_void), &mut info, mem::size_of_val(&info)) {
+		0 => None,
+		_ => Some(info),
+	}
+}
+
+/// How much can be read from `target` before it stops being code
+unsafe fn executable_len(target: *const u8) -> Result<usize, HookError> {
+	let info = query(target as usize)
+		.ok_or(HookError::NotExecutable)?;
+	let executable = PAGE_EXECUTE_READ.0 | PAGE_EXECUTE_READWRITE.0 | PAGE_EXECUTE_WRITECOPY.0;
+	match info.State == MEM_COMMIT && info.Protect.0 & executable != 0 && info.Protect.0 & PAGE_GUARD.0 == 0 {
+		true => Ok(info.BaseAddress as usize + info.RegionSize - target as usize),
+		false => Err(HookError::NotExecutable),
+	}
+}
+
+/// Allocate executable memory within [MAX_DISTANCE] of `target`,
+/// preferring addresses below it
+unsafe fn alloc_near(target: usize, size: usize) -> Option<usize> {
+	let mut system = SYSTEM_INFO::default();
+	GetSystemInfo(&mut system);
+	let granularity = system.dwAllocationGranularity as usize;
+	let align_down = |address: usize| address - address % granularity;
+	let min = (system.lpMinimumApplicationAddress as usize).max(target.saturating_sub(MAX_DISTANCE));
+	let max = (system.lpMaximumApplicationAddress as usize).min(target.saturating_add(MAX_DISTANCE));
+	let try_alloc = |address: usize| {
+		let block = VirtualAlloc(Some(address as *const c_void), size, MEM_COMMIT | MEM_RESERVE, PAGE_EXECUTE_READWRITE);
+		(!block.is_null()).then_some(block as usize)
+	};
+
+	let mut address = align_down(target);
+	while address >= min + granularity {
+		address -= granularity;
+		let info = query(address)?;
+		match info.State {
+			MEM_FREE => if let Some(block) = try_alloc(address) {
+				return Some(block)
+			},
+			// skip over whatever else is allocated here
+			_ => address = align_down(info.AllocationBase as usize).min(address),
+		}
+	}
+
+	let mut address = align_down(target);
+	while address < max {
+		let info = query(address)?;
+		if info.State == MEM_FREE {
+			if let Some(block) = try_alloc(address) {
+				return Some(block)
+			}
+		}
+		let end = info.BaseAddress as usize + info.RegionSize;
+		address = (align_down(end + granularity - 1)).max(address + granularity);
+	}
+
+	debug!("no room for hooks near {target:#x}");
+	None
+}
+
+#[repr(C, align(16))]
+struct AlignedContext(CONTEXT);
+
+/// Suspends every other thread in the process until dropped
+struct ThreadFreeze {
+	threads: Vec<HANDLE>,
+}
+
+impl ThreadFreeze {
+	unsafe fn new() -> Self {
+		// no allocating once threads are suspended, one of them may hold the heap lock
+		let ids = Self::thread_ids();
+		let mut threads = Vec::with_capacity(ids.len());
+		for id in ids {
+			let thread = match OpenThread(THREAD_SUSPEND_RESUME | THREAD_GET_CONTEXT | THREAD_SET_CONTEXT, false, id) {
+				Ok(thread) => thread,
+				Err(_) => continue,
+			};
+			match SuspendThread(thread) {
+				u32::MAX => {
+					let _ = CloseHandle(thread);
+				},
+				_ => threads.push(thread),
+			}
+		}
+		Self {
+			threads,
+		}
+	}
+
+	unsafe fn thread_ids() -> Vec<u32> {
+		let snapshot = match CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0) {
+			Ok(snapshot) => snapshot,
+			Err(_e) => {
+				debug!("failed to list threads: {_e}");
+				return Vec::new()
+			},
+		};
+		let (process, current) = (GetCurrentProcessId(), GetCurrentThreadId());
+		let mut entry = THREADENTRY32 {
+			dwSize: mem::size_of::<THREADENTRY32>() as u32,
+			..Default::default()
+		};
+		let mut ids = Vec::new();
+		let mut next = Thread32First(snapshot, &mut entry);
+		while next.is_ok() {
+			if entry.th32OwnerProcessID == process && entry.th32ThreadID != current {
+				ids.push(entry.th32ThreadID);
+			}
+			next = Thread32Next(snapshot, &mut entry);
+		}
+		let _ = CloseHandle(snapshot);
+		ids
+	}
+
+	/// Move any thread caught in the middle of code that was just swapped out
+	unsafe fn relocate(&self, hook: &Hook) {
+		let target = hook.target.as_ptr() as usize;
+		let trampoline = hook.trampoline() as usize;
+		for &thread in &self.threads {
+			let mut context = AlignedContext(mem::zeroed());
+			context.0.ContextFlags = CONTEXT_CONTROL_AMD64;
+			if GetThreadContext(thread, &mut context.0).is_err() {
+				continue
+			}
+			let ip = context.0.Rip as usize;
+			let moved = match hook.enabled {
+				true if (target..target + hook.trampoline.displaced).contains(&ip) =>
+					hook.trampoline.relocate_ip(ip - target).map(|offset| trampoline + offset),
+				false if (trampoline..trampoline + hook.trampoline.code.len()).contains(&ip) =>
+					hook.trampoline.restore_ip(ip - trampoline).map(|offset| target + offset),
+				_ => None,
+			};
+			if let Some(ip) = moved {
+				context.0.Rip = ip as u64;
+				let _ = SetThreadContext(thread, &context.0);
+			}
+		}
+	}
+}
+
+impl Drop for ThreadFreeze {
+	fn drop(&mut self) {
+		for &thread in &self.threads {
+			unsafe {
+				ResumeThread(thread);
+				let _ = CloseHandle(thread);
+			}
+		}
+	}
+}
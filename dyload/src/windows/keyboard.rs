@@ -1,5 +1,5 @@
 use std::{ffi::OsString, num::NonZeroU16, os::windows::ffi::OsStringExt};
-use windows::Win32::{Foundation::LPARAM, UI::Input::KeyboardAndMouse::{GetKeyNameTextW, MapVirtualKeyA, MAPVK_VK_TO_VSC, MAPVK_VSC_TO_VK, VIRTUAL_KEY}};
+use windows::Win32::{Foundation::LPARAM, UI::Input::KeyboardAndMouse::{GetKeyNameTextW, MapVirtualKeyA, MAPVK_VK_TO_VSC, MAPVK_VSC_TO_VK, MAPVK_VSC_TO_VK_EX, VIRTUAL_KEY}};
 use crate::windows::{WinResult, WinError};
 use crate::log::*;
 
@@ -28,9 +28,13 @@ pub fn get_scan_code(vk: VIRTUAL_KEY) -> Option<NonZeroU16> {
 	NonZeroU16::new(vsc as u16)
 }
 
+/// `vsc` may have `0x100` set for extended keys
 pub fn get_vk(vsc: u16) -> Option<VIRTUAL_KEY> {
 	let vk = unsafe {
-		MapVirtualKeyA(vsc.into(), MAPVK_VSC_TO_VK)
+		match vsc & 0xff00 {
+			0 => MapVirtualKeyA(vsc.into(), MAPVK_VSC_TO_VK),
+			_ => MapVirtualKeyA((0xe000 | (vsc & 0xff)).into(), MAPVK_VSC_TO_VK_EX),
+		}
 	};
 	NonZeroU16::new(vk as u16)
 		.map(|vk| vk.get())
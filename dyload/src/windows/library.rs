@@ -1,8 +1,8 @@
 #![allow(non_snake_case)]
 
-use std::{ffi::{c_void, c_uchar, CString, OsStr, OsString}, os::windows::ffi::OsStringExt, ptr::NonNull, slice::from_raw_parts};
-use windows::{core::{HSTRING, PCWSTR, PCSTR, Owned, Param}, Win32::{Foundation::{FreeLibrary, GetLastError, ERROR_INSUFFICIENT_BUFFER, ERROR_MOD_NOT_FOUND, HMODULE, MAX_PATH}, System::LibraryLoader::{FindResourceA, GetModuleFileNameW, GetModuleHandleExA, GetModuleHandleExW, LoadLibraryExW, LoadLibraryExA, LoadResource, LockResource, SizeofResource, GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS, GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT, LOAD_LIBRARY_FLAGS}}};
-use crate::windows::WinResult;
+use std::{ffi::{c_void, c_uchar, CString, OsStr, OsString}, ops::Range, os::windows::ffi::OsStringExt, ptr::NonNull, slice::from_raw_parts};
+use windows::{core::{HSTRING, PCWSTR, PCSTR, Owned, Param}, Win32::{Foundation::{FreeLibrary, GetLastError, ERROR_BAD_EXE_FORMAT, ERROR_INSUFFICIENT_BUFFER, ERROR_INVALID_HANDLE, ERROR_MOD_NOT_FOUND, HMODULE, MAX_PATH}, System::LibraryLoader::{FindResourceA, GetModuleFileNameW, GetModuleHandleExA, GetModuleHandleExW, LoadLibraryExW, LoadLibraryExA, LoadResource, LockResource, SizeofResource, GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS, GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT, LOAD_LIBRARY_FLAGS}}};
+use crate::windows::{pe::PeFile, WinError, WinResult};
 use crate::log::*;
 
 pub fn load_library_path<P>(path: P, flags: LOAD_LIBRARY_FLAGS) -> WinResult<Owned<HMODULE>> where
@@ -106,6 +106,33 @@ pub fn get_module_from_ptr(p: *const c_void) -> WinResult<Option<HMODULE>> {
 	res.map(|()| if handle.is_invalid() && handle != HMODULE::default() { None } else { Some(handle) })
 }
 
+/// The address range a loaded module's image occupies, according to its own headers
+pub fn get_module_range(module: HMODULE) -> WinResult<Range<usize>> {
+	// the headers always get at least a page to themselves
+	const HEADER_SIZE: usize = 0x1000;
+
+	if module.is_invalid() || LDR_IS_RESOURCE(module) {
+		return Err(ERROR_INVALID_HANDLE.into())
+	}
+	let base = module.0 as usize;
+	let headers = unsafe {
+		from_raw_parts(base as *const u8, HEADER_SIZE)
+	};
+	let size = PeFile::parse(headers)?
+		.image_size()
+		.ok_or_else(|| WinError::new(ERROR_BAD_EXE_FORMAT.to_hresult(), "module is not PE32+"))?;
+	Ok(base..base + size)
+}
+
+/// Take another reference to an already loaded module
+pub fn retain_library(module: HMODULE) -> WinResult<Owned<HMODULE>> {
+	let mut handle = HMODULE::default();
+	unsafe {
+		GetModuleHandleExW(GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS, PCWSTR(module.0 as *const _), &mut handle)?;
+		Ok(Owned::new(handle))
+	}
+}
+
 pub fn free_library(module: HMODULE) -> WinResult<()> {
 	let res = unsafe {
 		FreeLibrary(module)
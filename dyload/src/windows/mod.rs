@@ -45,12 +45,14 @@ pub use windows::Win32;
 #[path = "win32.rs"]
 pub mod Win32;
 
+pub mod pe;
+
 #[cfg(windows)]
 mod library;
 #[cfg(windows)]
 pub use self::library::{
-	free_library, load_library_path, load_library_w,
-	get_module_path, get_module_from_name, get_module_from_ptr,
+	free_library, retain_library, load_library_path, load_library_w,
+	get_module_path, get_module_from_name, get_module_from_ptr, get_module_range,
 	find_resource,
 	LDR_IS_DATAFILE, LDR_IS_RESOURCE, LDR_IS_IMAGEMAPPING,
 	MAKERESOURCEA,
@@ -62,3 +64,10 @@ mod keyboard;
 #[cfg(windows)]
 #[cfg(feature = "keyboard")]
 pub use self::keyboard::{get_key_name, get_scan_code, get_vk};
+
+#[cfg(windows)]
+#[cfg(feature = "hook")]
+mod hook;
+#[cfg(windows)]
+#[cfg(feature = "hook")]
+pub use self::hook::Hook;
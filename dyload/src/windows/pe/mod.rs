@@ -0,0 +1,510 @@
+//! Reading PE images straight from disk, without asking the loader to map them
+
+mod version;
+pub use self::version::{FileVersion, VersionInfo, RT_VERSION};
+
+use std::{ffi::CStr, fs, io, mem::size_of, path::Path, ptr};
+use crate::windows::{
+	core::HRESULT,
+	Win32::{
+		Foundation::{ERROR_BAD_EXE_FORMAT, ERROR_NOT_SUPPORTED, ERROR_READ_FAULT},
+		System::{
+			Diagnostics::Debug::{
+				IMAGE_DATA_DIRECTORY, IMAGE_DIRECTORY_ENTRY, IMAGE_DIRECTORY_ENTRY_EXPORT, IMAGE_DIRECTORY_ENTRY_IMPORT, IMAGE_DIRECTORY_ENTRY_RESOURCE,
+				IMAGE_FILE_HEADER, IMAGE_NT_HEADERS64, IMAGE_NT_OPTIONAL_HDR64_MAGIC, IMAGE_OPTIONAL_HEADER64, IMAGE_SECTION_HEADER,
+				IMAGE_FILE_DLL, IMAGE_OPTIONAL_HEADER_MAGIC,
+			},
+			SystemInformation::{IMAGE_FILE_MACHINE, IMAGE_FILE_MACHINE_AMD64},
+			SystemServices::{
+				IMAGE_DOS_HEADER, IMAGE_DOS_SIGNATURE, IMAGE_EXPORT_DIRECTORY, IMAGE_IMPORT_DESCRIPTOR, IMAGE_NT_SIGNATURE, IMAGE_ORDINAL_FLAG64,
+				IMAGE_RESOURCE_DATA_ENTRY, IMAGE_RESOURCE_DATA_IS_DIRECTORY, IMAGE_RESOURCE_DIRECTORY, IMAGE_RESOURCE_NAME_IS_STRING,
+			},
+		},
+	},
+	WinError, WinResult,
+};
+
+/// A PE image as it sits on disk
+///
+/// Only PE32+ images have their data directories parsed,
+/// anything older is still identified by its [machine](Self::machine).
+#[derive(Clone)]
+pub struct PeFile<'a> {
+	data: &'a [u8],
+	file_header: IMAGE_FILE_HEADER,
+	optional_header: Option<IMAGE_OPTIONAL_HEADER64>,
+	sections: Vec<IMAGE_SECTION_HEADER>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeExport {
+	pub ordinal: u32,
+	pub name: Option<String>,
+	pub rva: u32,
+	/// `DLL.Symbol` that the export actually refers to
+	pub forwarder: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeImport {
+	pub dll: String,
+	pub symbols: Vec<PeImportSymbol>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PeImportSymbol {
+	Name(String),
+	Ordinal(u16),
+}
+
+/// An owned summary of a [PeFile]
+#[derive(Clone, Debug)]
+pub struct PeInfo {
+	pub machine: IMAGE_FILE_MACHINE,
+	pub timestamp: u32,
+	pub is_dll: bool,
+	pub exports: Vec<PeExport>,
+	pub imports: Vec<PeImport>,
+	pub version: Option<VersionInfo>,
+}
+
+fn bad_format(msg: &str) -> WinError {
+	WinError::new(ERROR_BAD_EXE_FORMAT.to_hresult(), msg)
+}
+
+/// Copy a header out of `data`, which is in no way guaranteed to be aligned
+fn read_at<T: Copy>(data: &[u8], offset: usize) -> WinResult<T> {
+	match offset.checked_add(size_of::<T>()) {
+		Some(end) if end <= data.len() => Ok(unsafe {
+			ptr::read_unaligned(data[offset..].as_ptr() as *const T)
+		}),
+		_ => Err(bad_format("PE image truncated")),
+	}
+}
+
+impl<'a> PeFile<'a> {
+	/// Upper bound on table sizes, so a corrupt header can't send us off allocating forever
+	const MAX_ENTRIES: usize = 0x10000;
+
+	pub fn parse(data: &'a [u8]) -> WinResult<Self> {
+		let dos: IMAGE_DOS_HEADER = read_at(data, 0)?;
+		if dos.e_magic != IMAGE_DOS_SIGNATURE {
+			return Err(bad_format("missing MZ signature"))
+		}
+		let nt_offset = usize::try_from(dos.e_lfanew)
+			.map_err(|_| bad_format("invalid e_lfanew"))?;
+
+		let signature: u32 = read_at(data, nt_offset)?;
+		if signature != IMAGE_NT_SIGNATURE {
+			return Err(bad_format("missing PE signature"))
+		}
+		let file_header_offset = nt_offset + size_of::<u32>();
+		let file_header: IMAGE_FILE_HEADER = read_at(data, file_header_offset)?;
+		let optional_offset = file_header_offset + size_of::<IMAGE_FILE_HEADER>();
+
+		let magic: IMAGE_OPTIONAL_HEADER_MAGIC = match file_header.SizeOfOptionalHeader {
+			0 => Default::default(),
+			_ => read_at(data, optional_offset)?,
+		};
+		let optional_header = match magic == IMAGE_NT_OPTIONAL_HDR64_MAGIC {
+			true if (file_header.SizeOfOptionalHeader as usize) < size_of::<IMAGE_OPTIONAL_HEADER64>() =>
+				return Err(bad_format("optional header truncated")),
+			true => Some(read_at::<IMAGE_NT_HEADERS64>(data, nt_offset)?.OptionalHeader),
+			false => None,
+		};
+
+		let sections_offset = optional_offset + file_header.SizeOfOptionalHeader as usize;
+		let sections = (0..file_header.NumberOfSections as usize)
+			.map(|i| read_at(data, sections_offset + i * size_of::<IMAGE_SECTION_HEADER>()))
+			.collect::<WinResult<_>>()?;
+
+		Ok(Self {
+			data,
+			file_header,
+			optional_header,
+			sections,
+		})
+	}
+
+	pub fn data(&self) -> &'a [u8] {
+		self.data
+	}
+
+	pub fn file_header(&self) -> &IMAGE_FILE_HEADER {
+		&self.file_header
+	}
+
+	pub fn optional_header(&self) -> Option<&IMAGE_OPTIONAL_HEADER64> {
+		self.optional_header.as_ref()
+	}
+
+	pub fn sections(&self) -> &[IMAGE_SECTION_HEADER] {
+		&self.sections
+	}
+
+	pub fn machine(&self) -> IMAGE_FILE_MACHINE {
+		self.file_header.Machine
+	}
+
+	/// Link time, in seconds since the unix epoch
+	///
+	/// Reproducible builds tend to fill this with a hash instead.
+	pub fn timestamp(&self) -> u32 {
+		self.file_header.TimeDateStamp
+	}
+
+	pub fn is_dll(&self) -> bool {
+		self.file_header.Characteristics.0 & IMAGE_FILE_DLL.0 != 0
+	}
+
+	/// Whether this could be loaded into a 64-bit process like the game
+	pub fn is_amd64(&self) -> bool {
+		self.machine() == IMAGE_FILE_MACHINE_AMD64 && self.optional_header.is_some()
+	}
+
+	/// How much address space the image takes up once mapped
+	pub fn image_size(&self) -> Option<usize> {
+		self.optional_header.as_ref()
+			.map(|header| header.SizeOfImage as usize)
+	}
+
+	pub fn data_directory(&self, entry: IMAGE_DIRECTORY_ENTRY) -> WinResult<Option<IMAGE_DATA_DIRECTORY>> {
+		let optional = self.optional_header.as_ref()
+			.ok_or_else(|| WinError::new(ERROR_NOT_SUPPORTED.to_hresult(), "not a PE32+ image"))?;
+		let index = entry.0 as usize;
+		if index >= optional.NumberOfRvaAndSizes as usize {
+			return Ok(None)
+		}
+		Ok(optional.DataDirectory.get(index)
+			.filter(|dir| dir.VirtualAddress != 0)
+			.copied())
+	}
+
+	pub fn section_for_rva(&self, rva: u32) -> Option<&IMAGE_SECTION_HEADER> {
+		self.sections.iter().find(|section| {
+			let size = section_virtual_size(section).max(section.SizeOfRawData);
+			rva >= section.VirtualAddress && rva - section.VirtualAddress < size
+		})
+	}
+
+	/// Where an RVA ends up in the file, if it's backed by anything at all
+	pub fn rva_to_offset(&self, rva: u32) -> WinResult<usize> {
+		let headers_size = self.optional_header.as_ref()
+			.map(|optional| optional.SizeOfHeaders)
+			.unwrap_or_default();
+		let offset = match self.section_for_rva(rva) {
+			Some(section) if rva - section.VirtualAddress < section.SizeOfRawData =>
+				section.PointerToRawData as usize + (rva - section.VirtualAddress) as usize,
+			Some(..) => return Err(bad_format("RVA points into uninitialized data")),
+			None if rva < headers_size => rva as usize,
+			None => return Err(bad_format("RVA outside of any section")),
+		};
+		match offset < self.data.len() {
+			true => Ok(offset),
+			false => Err(bad_format("PE image truncated")),
+		}
+	}
+
+	pub fn read_rva<T: Copy>(&self, rva: u32) -> WinResult<T> {
+		read_at(self.data, self.rva_to_offset(rva)?)
+	}
+
+	pub fn read_cstr_rva(&self, rva: u32) -> WinResult<&'a CStr> {
+		let offset = self.rva_to_offset(rva)?;
+		CStr::from_bytes_until_nul(&self.data[offset..])
+			.map_err(|_| bad_format("unterminated string"))
+	}
+
+	fn read_string_rva(&self, rva: u32) -> WinResult<String> {
+		self.read_cstr_rva(rva)
+			.map(|s| s.to_string_lossy().into_owned())
+	}
+
+	pub fn exports(&self) -> WinResult<Vec<PeExport>> {
+		let dir_entry = match self.data_directory(IMAGE_DIRECTORY_ENTRY_EXPORT)? {
+			Some(dir) => dir,
+			None => return Ok(Vec::new()),
+		};
+		let dir: IMAGE_EXPORT_DIRECTORY = self.read_rva(dir_entry.VirtualAddress)?;
+		let dir_range = dir_entry.VirtualAddress..dir_entry.VirtualAddress.saturating_add(dir_entry.Size);
+
+		let count = dir.NumberOfFunctions as usize;
+		let named = dir.NumberOfNames as usize;
+		if count > Self::MAX_ENTRIES || named > Self::MAX_ENTRIES {
+			return Err(bad_format("implausibly large export table"))
+		}
+
+		let mut names = vec![None; count];
+		for i in 0..named {
+			let name_rva: u32 = self.read_rva(dir.AddressOfNames.wrapping_add(4 * i as u32))?;
+			let index: u16 = self.read_rva(dir.AddressOfNameOrdinals.wrapping_add(2 * i as u32))?;
+			let name = self.read_string_rva(name_rva)?;
+			match names.get_mut(index as usize) {
+				Some(slot) => *slot = Some(name),
+				None => return Err(bad_format("export name refers to a missing function")),
+			}
+		}
+
+		let mut exports = Vec::with_capacity(count);
+		for (i, name) in names.into_iter().enumerate() {
+			let rva: u32 = self.read_rva(dir.AddressOfFunctions.wrapping_add(4 * i as u32))?;
+			if rva == 0 {
+				// a gap in the ordinals
+				continue
+			}
+			let forwarder = match dir_range.contains(&rva) {
+				true => Some(self.read_string_rva(rva)?),
+				false => None,
+			};
+			exports.push(PeExport {
+				ordinal: dir.Base.wrapping_add(i as u32),
+				name,
+				rva,
+				forwarder,
+			});
+		}
+
+		Ok(exports)
+	}
+
+	pub fn has_export(&self, name: &str) -> WinResult<bool> {
+		self.exports()
+			.map(|exports| exports.iter().any(|e| e.name.as_deref() == Some(name)))
+	}
+
+	pub fn imports(&self) -> WinResult<Vec<PeImport>> {
+		let dir_entry = match self.data_directory(IMAGE_DIRECTORY_ENTRY_IMPORT)? {
+			Some(dir) => dir,
+			None => return Ok(Vec::new()),
+		};
+
+		let mut imports = Vec::new();
+		for i in 0..Self::MAX_ENTRIES as u32 {
+			let desc_rva = dir_entry.VirtualAddress.wrapping_add(i * size_of::<IMAGE_IMPORT_DESCRIPTOR>() as u32);
+			let desc: IMAGE_IMPORT_DESCRIPTOR = self.read_rva(desc_rva)?;
+			if desc.Name == 0 && desc.FirstThunk == 0 {
+				return Ok(imports)
+			}
+
+			// the lookup table is optional, the IAT looks the same on disk
+			let lookup = match unsafe { desc.Anonymous.OriginalFirstThunk } {
+				0 => desc.FirstThunk,
+				rva => rva,
+			};
+			imports.push(PeImport {
+				dll: self.read_string_rva(desc.Name)?,
+				symbols: self.import_symbols(lookup)?,
+			});
+		}
+
+		Err(bad_format("unterminated import table"))
+	}
+
+	fn import_symbols(&self, lookup: u32) -> WinResult<Vec<PeImportSymbol>> {
+		let mut symbols = Vec::new();
+		for i in 0..Self::MAX_ENTRIES as u32 {
+			let thunk: u64 = self.read_rva(lookup.wrapping_add(i * size_of::<u64>() as u32))?;
+			let symbol = match thunk {
+				0 => return Ok(symbols),
+				thunk if thunk & IMAGE_ORDINAL_FLAG64 != 0 =>
+					PeImportSymbol::Ordinal(thunk as u16),
+				// skipping over the hint
+				thunk => PeImportSymbol::Name(self.read_string_rva((thunk as u32).wrapping_add(2))?),
+			};
+			symbols.push(symbol);
+		}
+
+		Err(bad_format("unterminated import lookup table"))
+	}
+
+	/// Find a resource by its numeric type and name, in whichever language comes first
+	///
+	/// A `name` of [None] matches any.
+	pub fn resource(&self, kind: u16, name: Option<u16>) -> WinResult<Option<&'a [u8]>> {
+		let root = match self.data_directory(IMAGE_DIRECTORY_ENTRY_RESOURCE)? {
+			Some(dir) => dir.VirtualAddress,
+			None => return Ok(None),
+		};
+
+		// type, name, and then language
+		let mut offset = 0;
+		for (depth, id) in [Some(kind), name, None].into_iter().enumerate() {
+			let entry = match self.resource_entry(root, offset, id)? {
+				Some(entry) => entry,
+				None => return Ok(None),
+			};
+			let is_dir = entry & IMAGE_RESOURCE_DATA_IS_DIRECTORY != 0;
+			match (depth, is_dir) {
+				(0 | 1, true) | (2, false) => (),
+				_ => return Err(bad_format("unexpected resource directory layout")),
+			}
+			offset = entry & !IMAGE_RESOURCE_DATA_IS_DIRECTORY;
+		}
+
+		let entry: IMAGE_RESOURCE_DATA_ENTRY = self.read_rva(root.wrapping_add(offset))?;
+		let start = self.rva_to_offset(entry.OffsetToData)?;
+		self.data.get(start..start.saturating_add(entry.Size as usize))
+			.map(Some)
+			.ok_or_else(|| bad_format("resource truncated"))
+	}
+
+	/// `OffsetToData` of the first entry in a resource directory matching `id`
+	fn resource_entry(&self, root: u32, offset: u32, id: Option<u16>) -> WinResult<Option<u32>> {
+		let dir_rva = root.wrapping_add(offset);
+		let dir: IMAGE_RESOURCE_DIRECTORY = self.read_rva(dir_rva)?;
+		let entries_rva = dir_rva.wrapping_add(size_of::<IMAGE_RESOURCE_DIRECTORY>() as u32);
+		let count = dir.NumberOfNamedEntries as u32 + dir.NumberOfIdEntries as u32;
+		for i in 0..count {
+			let [name, data]: [u32; 2] = self.read_rva(entries_rva.wrapping_add(i * 8))?;
+			let matches = match id {
+				None => true,
+				Some(id) => name & IMAGE_RESOURCE_NAME_IS_STRING == 0 && name == id as u32,
+			};
+			if matches {
+				return Ok(Some(data))
+			}
+		}
+		Ok(None)
+	}
+
+	pub fn version_info(&self) -> WinResult<Option<VersionInfo>> {
+		match self.resource(RT_VERSION, None)? {
+			Some(data) => VersionInfo::parse(data).map(Some),
+			None => Ok(None),
+		}
+	}
+
+	pub fn info(&self) -> WinResult<PeInfo> {
+		let (exports, imports) = match self.optional_header {
+			Some(..) => (self.exports()?, self.imports()?),
+			None => Default::default(),
+		};
+		// plenty of otherwise fine DLLs have sloppy version resources
+		let version = match self.optional_header {
+			Some(..) => self.version_info().ok().flatten(),
+			None => None,
+		};
+		Ok(PeInfo {
+			machine: self.machine(),
+			timestamp: self.timestamp(),
+			is_dll: self.is_dll(),
+			exports,
+			imports,
+			version,
+		})
+	}
+}
+
+impl PeInfo {
+	pub fn read(path: &Path) -> WinResult<Self> {
+		let data = fs::read(path)
+			.map_err(|e| WinError::new(io_hresult(&e), e.to_string()))?;
+		PeFile::parse(&data)?.info()
+	}
+
+	pub fn has_export(&self, name: &str) -> bool {
+		self.exports.iter()
+			.any(|e| e.name.as_deref() == Some(name))
+	}
+
+	pub fn is_amd64(&self) -> bool {
+		self.machine == IMAGE_FILE_MACHINE_AMD64
+	}
+}
+
+fn io_hresult(e: &io::Error) -> HRESULT {
+	match e.raw_os_error() {
+		Some(code) => HRESULT::from_win32(code as u32),
+		None => ERROR_READ_FAULT.to_hresult(),
+	}
+}
+
+fn section_virtual_size(section: &IMAGE_SECTION_HEADER) -> u32 {
+	unsafe { section.Misc.VirtualSize }
+}
+
+#[cfg(test)]
+const FIXTURE_ARCDPS: &[u8] = include_bytes!("../../../fixtures/pe/arcdps_fixture.dll");
+#[cfg(test)]
+const FIXTURE_NEXUS: &[u8] = include_bytes!("../../../fixtures/pe/nexus_fixture.dll");
+#[cfg(test)]
+const FIXTURE_I386: &[u8] = include_bytes!("../../../fixtures/pe/i386_fixture.dll");
+
+#[test]
+fn pe_headers() {
+	use crate::windows::Win32::System::SystemInformation::IMAGE_FILE_MACHINE_I386;
+
+	let arcdps = PeFile::parse(FIXTURE_ARCDPS).unwrap();
+	assert_eq!(arcdps.machine(), IMAGE_FILE_MACHINE_AMD64);
+	assert_eq!(arcdps.timestamp(), 0x65000000);
+	assert!(arcdps.is_dll());
+	assert!(arcdps.is_amd64());
+	assert_eq!(arcdps.sections().len(), 3);
+	assert_eq!(arcdps.image_size(), Some(0x4000));
+
+	let i386 = PeFile::parse(FIXTURE_I386).unwrap();
+	assert_eq!(i386.machine(), IMAGE_FILE_MACHINE_I386);
+	assert_eq!(i386.timestamp(), 0x40000000);
+	assert!(i386.is_dll());
+	assert!(!i386.is_amd64());
+	assert_eq!(i386.image_size(), None);
+	assert!(i386.exports().is_err());
+
+	let info = i386.info().unwrap();
+	assert!(!info.is_amd64());
+	assert!(info.exports.is_empty());
+	assert!(info.version.is_none());
+}
+
+#[test]
+fn pe_exports() {
+	let arcdps = PeFile::parse(FIXTURE_ARCDPS).unwrap();
+	let exports = arcdps.exports().unwrap();
+	assert_eq!(exports, [
+		PeExport { ordinal: 1, name: Some("get_init_addr".into()), rva: 0x1000, forwarder: None },
+		PeExport { ordinal: 2, name: Some("get_release_addr".into()), rva: 0x1004, forwarder: None },
+		PeExport { ordinal: 5, name: None, rva: 0x1008, forwarder: None },
+	]);
+	assert!(arcdps.has_export("get_init_addr").unwrap());
+	assert!(!arcdps.has_export("GetAddonDef").unwrap());
+
+	let nexus = PeFile::parse(FIXTURE_NEXUS).unwrap().info().unwrap();
+	assert!(nexus.has_export("GetAddonDef"));
+	assert!(!nexus.has_export("get_init_addr"));
+	let sleep = nexus.exports.iter()
+		.find(|e| e.name.as_deref() == Some("Sleep"))
+		.unwrap();
+	assert_eq!(sleep.forwarder.as_deref(), Some("KERNEL32.Sleep"));
+}
+
+#[test]
+fn pe_imports() {
+	let imports = PeFile::parse(FIXTURE_ARCDPS).unwrap()
+		.imports().unwrap();
+	assert_eq!(imports, [
+		PeImport {
+			dll: "KERNEL32.dll".into(),
+			symbols: vec![PeImportSymbol::Name("GetModuleHandleW".into()), PeImportSymbol::Ordinal(5)],
+		},
+		PeImport {
+			dll: "USER32.dll".into(),
+			symbols: vec![PeImportSymbol::Name("MessageBoxW".into())],
+		},
+	]);
+}
+
+#[test]
+fn pe_malformed() {
+	assert!(PeFile::parse(&[]).is_err());
+	assert!(PeFile::parse(b"not a dll at all").is_err());
+	assert!(PeFile::parse(&FIXTURE_ARCDPS[..0x100]).is_err());
+
+	// headers intact, but the sections they describe are missing
+	let truncated = PeFile::parse(&FIXTURE_ARCDPS[..0x200]).unwrap();
+	assert!(truncated.exports().is_err());
+	assert!(truncated.imports().is_err());
+
+	let mut bad_signature = FIXTURE_NEXUS.to_vec();
+	bad_signature[0x80] = b'X';
+	assert!(PeFile::parse(&bad_signature).is_err());
+}
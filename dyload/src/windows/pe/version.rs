@@ -0,0 +1,223 @@
+use std::{collections::BTreeMap, fmt};
+use super::{bad_format, read_at};
+use crate::windows::WinResult;
+
+/// `RT_VERSION`, but as the plain resource id it really is
+pub const RT_VERSION: u16 = 16;
+
+/// `VS_FIXEDFILEINFO::dwSignature`
+const VS_FFI_SIGNATURE: u32 = 0xfeef04bd;
+
+/// `MAJOR.MINOR.BUILD.REVISION`, as packed into `VS_FIXEDFILEINFO`
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FileVersion(pub [u16; 4]);
+
+/// The interesting parts of a `VS_VERSIONINFO` resource
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VersionInfo {
+	pub file_version: Option<FileVersion>,
+	pub product_version: Option<FileVersion>,
+	/// The contents of a single `StringTable`, preferring US English
+	pub strings: BTreeMap<String, String>,
+}
+
+/// Every node in a `VS_VERSIONINFO` tree shares this layout
+struct VersionBlock<'a> {
+	key: String,
+	is_text: bool,
+	value: &'a [u8],
+	children: &'a [u8],
+}
+
+fn align4(offset: usize) -> usize {
+	(offset + 3) & !3
+}
+
+fn read_utf16(data: &[u8]) -> (String, usize) {
+	let units: Vec<u16> = data.chunks_exact(2)
+		.map(|c| u16::from_le_bytes([c[0], c[1]]))
+		.take_while(|&c| c != 0)
+		.collect();
+	(String::from_utf16_lossy(&units), units.len() * 2)
+}
+
+impl FileVersion {
+	pub fn from_parts(ms: u32, ls: u32) -> Self {
+		Self([(ms >> 16) as u16, ms as u16, (ls >> 16) as u16, ls as u16])
+	}
+}
+
+impl fmt::Display for FileVersion {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let [major, minor, build, revision] = self.0;
+		write!(f, "{major}.{minor}.{build}.{revision}")
+	}
+}
+
+impl<'a> VersionBlock<'a> {
+	const HEADER_SIZE: usize = 6;
+
+	/// Split the first block off of `data`, returning whatever follows it
+	fn parse(data: &'a [u8]) -> WinResult<(Self, &'a [u8])> {
+		let [len, value_len, kind]: [u16; 3] = read_at(data, 0)?;
+		let len = len as usize;
+		let block = match data.get(..len) {
+			Some(block) if len >= Self::HEADER_SIZE => block,
+			_ => return Err(bad_format("version block truncated")),
+		};
+
+		let (key, key_len) = read_utf16(&block[Self::HEADER_SIZE..]);
+		let is_text = kind == 1;
+		let value_start = align4(Self::HEADER_SIZE + key_len + 2).min(len);
+		// text lengths are counted in characters, though not every linker agrees
+		let value_len = match is_text {
+			true => value_len as usize * 2,
+			false => value_len as usize,
+		};
+		let value_end = (value_start + value_len).min(len);
+		let children_start = align4(value_end).min(len);
+
+		let rest = data.get(align4(len)..).unwrap_or_default();
+		Ok((Self {
+			key,
+			is_text,
+			value: &block[value_start..value_end],
+			children: &block[children_start..],
+		}, rest))
+	}
+
+	fn children(&self) -> VersionBlocks<'a> {
+		VersionBlocks {
+			data: self.children,
+		}
+	}
+
+	fn text(&self) -> String {
+		read_utf16(self.value).0
+	}
+}
+
+struct VersionBlocks<'a> {
+	data: &'a [u8],
+}
+
+impl<'a> Iterator for VersionBlocks<'a> {
+	type Item = WinResult<VersionBlock<'a>>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		// trailing padding isn't always accounted for by the parent
+		if self.data.iter().all(|&b| b == 0) {
+			return None
+		}
+		match VersionBlock::parse(self.data) {
+			Ok((block, rest)) => {
+				self.data = rest;
+				Some(Ok(block))
+			},
+			Err(e) => {
+				self.data = &[];
+				Some(Err(e))
+			},
+		}
+	}
+}
+
+impl VersionInfo {
+	pub const KEY_FILE_VERSION: &'static str = "FileVersion";
+	pub const KEY_PRODUCT_VERSION: &'static str = "ProductVersion";
+	pub const KEY_PRODUCT_NAME: &'static str = "ProductName";
+	pub const KEY_COMPANY_NAME: &'static str = "CompanyName";
+	pub const KEY_FILE_DESCRIPTION: &'static str = "FileDescription";
+
+	const LANG_EN_US: &'static str = "0409";
+
+	/// Parse the raw contents of an [RT_VERSION] resource
+	pub fn parse(data: &[u8]) -> WinResult<Self> {
+		let (root, _) = VersionBlock::parse(data)?;
+		if root.key != "VS_VERSION_INFO" {
+			return Err(bad_format("not a VS_VERSIONINFO resource"))
+		}
+
+		let mut info = Self::default();
+		if let Ok([signature, _struc_version, file_ms, file_ls, product_ms, product_ls]) = read_at::<[u32; 6]>(root.value, 0) {
+			if signature == VS_FFI_SIGNATURE {
+				info.file_version = Some(FileVersion::from_parts(file_ms, file_ls));
+				info.product_version = Some(FileVersion::from_parts(product_ms, product_ls));
+			}
+		}
+
+		for child in root.children() {
+			let child = child?;
+			if child.key != "StringFileInfo" {
+				continue
+			}
+
+			let mut tables = Vec::new();
+			for table in child.children() {
+				tables.push(table?);
+			}
+			let table = tables.iter()
+				.find(|table| table.key.get(..4).map(|lang| lang.eq_ignore_ascii_case(Self::LANG_EN_US)).unwrap_or(false))
+				.or(tables.first());
+			if let Some(table) = table {
+				for string in table.children() {
+					let string = string?;
+					if string.is_text || !string.value.is_empty() {
+						info.strings.insert(string.key.clone(), string.text());
+					}
+				}
+			}
+		}
+
+		Ok(info)
+	}
+
+	pub fn string(&self, key: &str) -> Option<&str> {
+		self.strings.get(key)
+			.map(|s| s.trim())
+			.filter(|s| !s.is_empty())
+	}
+
+	pub fn product_name(&self) -> Option<&str> {
+		self.string(Self::KEY_PRODUCT_NAME)
+	}
+
+	pub fn company_name(&self) -> Option<&str> {
+		self.string(Self::KEY_COMPANY_NAME)
+	}
+
+	pub fn file_description(&self) -> Option<&str> {
+		self.string(Self::KEY_FILE_DESCRIPTION)
+	}
+
+	/// `FileVersion` as the author wrote it, or else the fixed version
+	pub fn version(&self) -> Option<String> {
+		self.string(Self::KEY_FILE_VERSION)
+			.map(|s| s.to_owned())
+			.or_else(|| self.file_version.map(|v| v.to_string()))
+	}
+}
+
+#[test]
+fn version_info() {
+	use super::PeFile;
+
+	let pe = PeFile::parse(super::FIXTURE_ARCDPS).unwrap();
+	let info = pe.version_info().unwrap().unwrap();
+	assert_eq!(info.file_version, Some(FileVersion([1, 2, 3, 4])));
+	assert_eq!(info.product_version, Some(FileVersion([1, 2, 0, 0])));
+	assert_eq!(info.file_version.unwrap().to_string(), "1.2.3.4");
+	assert_eq!(info.product_name(), Some("Fixture Addon"));
+	assert_eq!(info.company_name(), Some("arcloader"));
+	assert_eq!(info.file_description(), Some("arcdps test fixture"));
+	assert_eq!(info.version().as_deref(), Some("1.2.3.4"));
+	assert_eq!(info.string(VersionInfo::KEY_PRODUCT_VERSION), Some("1.2"));
+
+	assert_eq!(pe.info().unwrap().version, Some(info));
+
+	let nexus = PeFile::parse(super::FIXTURE_NEXUS).unwrap();
+	assert_eq!(nexus.version_info().unwrap(), None);
+
+	assert!(VersionInfo::parse(&[]).is_err());
+	assert!(VersionInfo::parse(&[0xff, 0, 0, 0, 0, 0]).is_err());
+}
@@ -46,6 +46,7 @@ pub mod Foundation {
 	pub const ERROR_BAD_COMMAND: WIN32_ERROR = WIN32_ERROR(22);
 	pub const ERROR_BAD_CRC: WIN32_ERROR = WIN32_ERROR(23);
 	pub const ERROR_BAD_LENGTH: WIN32_ERROR = WIN32_ERROR(24);
+	pub const ERROR_READ_FAULT: WIN32_ERROR = WIN32_ERROR(30);
 	pub const ERROR_HANDLE_EOF: WIN32_ERROR = WIN32_ERROR(38);
 	pub const ERROR_NOT_SUPPORTED: WIN32_ERROR = WIN32_ERROR(50);
 	pub const ERROR_NETWORK_BUSY: WIN32_ERROR = WIN32_ERROR(54);
@@ -60,12 +61,14 @@ pub mod Foundation {
 	pub const ERROR_BAD_ARGUMENTS: WIN32_ERROR = WIN32_ERROR(160);
 	pub const ERROR_BAD_PATHNAME: WIN32_ERROR = WIN32_ERROR(161);
 	pub const ERROR_BUSY: WIN32_ERROR = WIN32_ERROR(170);
+	pub const ERROR_BAD_EXE_FORMAT: WIN32_ERROR = WIN32_ERROR(193);
 }
 
 pub mod System {
 	pub mod Diagnostics {
 		pub mod Debug {
 			use super::super::super::Foundation::*;
+			use super::super::SystemInformation::IMAGE_FILE_MACHINE;
 
 			#[derive(Debug, Copy, Clone, Default)]
 			#[repr(C)]
@@ -78,19 +81,31 @@ pub mod System {
 			#[derive(Debug, Copy, Clone, Default)]
 			#[repr(C)]
 			pub struct IMAGE_FILE_HEADER {
-				pub Machine: WORD,
+				pub Machine: IMAGE_FILE_MACHINE,
 				pub NumberOfSections: WORD,
 				pub TimeDateStamp: DWORD,
 				pub PointerToSymbolTable: DWORD,
 				pub NumberOfSymbols: DWORD,
 				pub SizeOfOptionalHeader: WORD,
-				pub Characteristics: WORD,
+				pub Characteristics: IMAGE_FILE_CHARACTERISTICS,
 			}
 
+			#[derive(Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+			#[repr(transparent)]
+			pub struct IMAGE_FILE_CHARACTERISTICS(pub u16);
+			pub const IMAGE_FILE_EXECUTABLE_IMAGE: IMAGE_FILE_CHARACTERISTICS = IMAGE_FILE_CHARACTERISTICS(0x0002);
+			pub const IMAGE_FILE_DLL: IMAGE_FILE_CHARACTERISTICS = IMAGE_FILE_CHARACTERISTICS(0x2000);
+
+			#[derive(Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+			#[repr(transparent)]
+			pub struct IMAGE_OPTIONAL_HEADER_MAGIC(pub u16);
+			pub const IMAGE_NT_OPTIONAL_HDR32_MAGIC: IMAGE_OPTIONAL_HEADER_MAGIC = IMAGE_OPTIONAL_HEADER_MAGIC(0x10b);
+			pub const IMAGE_NT_OPTIONAL_HDR64_MAGIC: IMAGE_OPTIONAL_HEADER_MAGIC = IMAGE_OPTIONAL_HEADER_MAGIC(0x20b);
+
 			#[derive(Debug, Copy, Clone, Default)]
 			#[repr(C)]
 			pub struct IMAGE_OPTIONAL_HEADER64 {
-				pub Magic: WORD,
+				pub Magic: IMAGE_OPTIONAL_HEADER_MAGIC,
 				pub MajorLinkerVersion: BYTE,
 				pub MinorLinkerVersion: BYTE,
 				pub SizeOfCode: DWORD,
@@ -148,12 +163,12 @@ pub mod System {
 				pub Size: DWORD,
 			}
 
-			#[derive(Debug, Copy, Clone, Default)]
+			#[derive(Copy, Clone, Default)]
 			#[repr(C)]
 			pub struct IMAGE_SECTION_HEADER {
 				pub Name: [BYTE; IMAGE_SIZEOF_SHORT_NAME as usize],
 				/// See also: [self.VirtualSize()]
-				pub Misc: DWORD,
+				pub Misc: IMAGE_SECTION_HEADER_0,
 				pub VirtualAddress: DWORD,
 				pub SizeOfRawData: DWORD,
 				pub PointerToRawData: DWORD,
@@ -165,25 +180,40 @@ pub mod System {
 			}
 			pub const IMAGE_SIZEOF_SHORT_NAME: u32 = 8;
 
+			#[derive(Copy, Clone)]
+			#[repr(C)]
+			pub union IMAGE_SECTION_HEADER_0 {
+				pub PhysicalAddress: DWORD,
+				pub VirtualSize: DWORD,
+			}
+
+			impl Default for IMAGE_SECTION_HEADER_0 {
+				fn default() -> Self {
+					Self { VirtualSize: 0 }
+				}
+			}
+
 			impl IMAGE_SECTION_HEADER {
 				pub fn PhysicalAddress(&self) -> DWORD {
-					self.Misc
+					unsafe { self.Misc.PhysicalAddress }
 				}
 				pub fn VirtualSize(&self) -> DWORD {
-					self.Misc
+					unsafe { self.Misc.VirtualSize }
 				}
 			}
 		}
 	}
 
 	pub mod SystemInformation {
-		use super::super::Foundation::*;
+		#[derive(Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+		#[repr(transparent)]
+		pub struct IMAGE_FILE_MACHINE(pub u16);
 
-		pub const IMAGE_FILE_MACHINE_UNKNOWN: WORD = 0;
-		pub const IMAGE_FILE_MACHINE_TARGET_HOST: WORD = 1;
-		pub const IMAGE_FILE_MACHINE_I386: WORD = 0x014c;
-		pub const IMAGE_FILE_MACHINE_AMD64: WORD = 0x8664;
-		pub const IMAGE_FILE_MACHINE_ARM64: WORD = 0xAA64;
+		pub const IMAGE_FILE_MACHINE_UNKNOWN: IMAGE_FILE_MACHINE = IMAGE_FILE_MACHINE(0);
+		pub const IMAGE_FILE_MACHINE_TARGET_HOST: IMAGE_FILE_MACHINE = IMAGE_FILE_MACHINE(1);
+		pub const IMAGE_FILE_MACHINE_I386: IMAGE_FILE_MACHINE = IMAGE_FILE_MACHINE(0x014c);
+		pub const IMAGE_FILE_MACHINE_AMD64: IMAGE_FILE_MACHINE = IMAGE_FILE_MACHINE(0x8664);
+		pub const IMAGE_FILE_MACHINE_ARM64: IMAGE_FILE_MACHINE = IMAGE_FILE_MACHINE(0xAA64);
 	}
 
 	pub mod SystemServices {
@@ -233,5 +263,53 @@ pub mod System {
 			pub AddressOfNames: DWORD,
 			pub AddressOfNameOrdinals: DWORD,
 		}
+
+		#[derive(Copy, Clone, Default)]
+		#[repr(C)]
+		pub struct IMAGE_IMPORT_DESCRIPTOR {
+			pub Anonymous: IMAGE_IMPORT_DESCRIPTOR_0,
+			pub TimeDateStamp: DWORD,
+			pub ForwarderChain: DWORD,
+			pub Name: DWORD,
+			pub FirstThunk: DWORD,
+		}
+
+		#[derive(Copy, Clone)]
+		#[repr(C)]
+		pub union IMAGE_IMPORT_DESCRIPTOR_0 {
+			pub Characteristics: DWORD,
+			pub OriginalFirstThunk: DWORD,
+		}
+
+		impl Default for IMAGE_IMPORT_DESCRIPTOR_0 {
+			fn default() -> Self {
+				Self { OriginalFirstThunk: 0 }
+			}
+		}
+
+		pub const IMAGE_ORDINAL_FLAG64: u64 = 0x8000000000000000;
+
+		#[derive(Debug, Copy, Clone, Default)]
+		#[repr(C)]
+		pub struct IMAGE_RESOURCE_DIRECTORY {
+			pub Characteristics: DWORD,
+			pub TimeDateStamp: DWORD,
+			pub MajorVersion: WORD,
+			pub MinorVersion: WORD,
+			pub NumberOfNamedEntries: WORD,
+			pub NumberOfIdEntries: WORD,
+		}
+
+		#[derive(Debug, Copy, Clone, Default)]
+		#[repr(C)]
+		pub struct IMAGE_RESOURCE_DATA_ENTRY {
+			pub OffsetToData: DWORD,
+			pub Size: DWORD,
+			pub CodePage: DWORD,
+			pub Reserved: DWORD,
+		}
+
+		pub const IMAGE_RESOURCE_DATA_IS_DIRECTORY: u32 = 0x80000000;
+		pub const IMAGE_RESOURCE_NAME_IS_STRING: u32 = 0x80000000;
 	}
 }
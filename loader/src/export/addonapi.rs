@@ -0,0 +1,127 @@
+use std::{ffi::CStr, ptr, sync::atomic::{AtomicPtr, Ordering}};
+use nexus::{addon::{AddonDefinition, AddonVersion}, gui::RenderType, AddonApi, AddonFlags, UpdateProvider};
+use windows::Win32::{Foundation::{HMODULE, HWND, LPARAM, WPARAM}, System::LibraryLoader::GetProcAddress};
+use windows_strings::HSTRING;
+use crate::{export, util::win::get_module_from_name};
+
+/// Nexus wants negative signatures for anything not published by Raidcore
+pub const SIG: i32 = -((export::SIG.get() & 0x7fffffff) as i32);
+
+pub const NAME: &'static CStr = cstr!(env!("CARGO_PKG_NAME"));
+pub const AUTHOR: &'static CStr = cstr!("mew");
+pub const DESCRIPTION: &'static CStr = cstr!("Loads arcdps extensions, from inside Nexus");
+pub const UPDATE_LINK: &'static CStr = cstr!(env!("CARGO_PKG_REPOSITORY"));
+
+/// Wherever arcdps may have been installed, for Nexus to load it alongside us
+const ARCDPS_MODULE_NAMES: &'static [&'static str] = &["d3d11.dll", "dxgi.dll", "gw2addon_arcdps.dll", "arcdps.dll"];
+
+static NEXUS_API: AtomicPtr<AddonApi> = AtomicPtr::new(ptr::null_mut());
+
+static mut ADDON_DEF: AddonDefinition = AddonDefinition {
+	signature: SIG,
+	api_version: nexus::v6::AddonApi::VERSION,
+	name: NAME.as_ptr(),
+	version: AddonVersion {
+		major: parse_version(env!("CARGO_PKG_VERSION_MAJOR")),
+		minor: parse_version(env!("CARGO_PKG_VERSION_MINOR")),
+		build: parse_version(env!("CARGO_PKG_VERSION_PATCH")),
+		revision: 0,
+	},
+	author: AUTHOR.as_ptr(),
+	description: DESCRIPTION.as_ptr(),
+	load,
+	unload: Some(unload),
+	flags: AddonFlags::None,
+	provider: UpdateProvider::GitHub,
+	update_link: UPDATE_LINK.as_ptr(),
+};
+
+const fn parse_version(s: &str) -> i16 {
+	let s = s.as_bytes();
+	let mut v = 0i16;
+	let mut i = 0;
+	while i < s.len() {
+		v = v * 10 + (s[i] - b'0') as i16;
+		i += 1;
+	}
+	v
+}
+
+/// The API Nexus handed us, if it was Nexus that loaded us and we started up
+pub fn nexus_api() -> Option<&'static AddonApi> {
+	unsafe {
+		NEXUS_API.load(Ordering::Acquire).as_ref()
+	}
+}
+
+/// Find arcdps by what it exports, since it goes by many names
+pub fn find_arcdps() -> Option<HMODULE> {
+	ARCDPS_MODULE_NAMES.iter()
+		.filter_map(|&name| get_module_from_name(&HSTRING::from(name)).ok().flatten())
+		.find(|&module| unsafe { GetProcAddress(module, windows_strings::s!("addextension2")).is_some() })
+}
+
+#[no_mangle]
+pub unsafe extern "system-unwind" fn GetAddonDef() -> *const AddonDefinition {
+	ptr::addr_of!(ADDON_DEF)
+}
+
+unsafe extern "C-unwind" fn load(api: *const AddonApi) {
+	let api = &*api;
+
+	#[cfg(all(feature = "arcdps", not(feature = "arcdps-codegen")))] {
+		let arcdps = match find_arcdps() {
+			Some(arcdps) => arcdps,
+			None => {
+				warn!("arcdps not found, so there's nothing for arcloader to host");
+				return
+			},
+		};
+		// both are a single interface pointer
+		let id3d = std::mem::transmute_copy(&api.swap_chain);
+		export::arcdps::extern_::init_hosted(arcdps, api.imgui_context, api.imgui_malloc, api.imgui_free, id3d);
+	}
+
+	if let Err(_e) = export::init() {
+		error!("arcloader failed to start inside Nexus: {_e:?}");
+		return
+	}
+
+	(api.renderer.register)(RenderType::Render, render);
+	(api.renderer.register)(RenderType::OptionsRender, options_render);
+	(api.wnd_proc.register)(wnd_proc);
+
+	// only once there's something for unload to undo
+	NEXUS_API.store(api as *const _ as *mut _, Ordering::Release);
+}
+
+unsafe extern "C-unwind" fn unload() {
+	let api = match nexus_api() {
+		Some(api) => api,
+		None => return,
+	};
+	(api.wnd_proc.deregister)(wnd_proc);
+	(api.renderer.deregister)(options_render);
+	(api.renderer.deregister)(render);
+
+	export::release();
+
+	NEXUS_API.store(ptr::null_mut(), Ordering::Release);
+}
+
+unsafe extern "C-unwind" fn render() {
+	// Nexus hides addon windows on its own during loading screens
+	export::imgui(true)
+}
+
+unsafe extern "C-unwind" fn options_render() {
+	export::options_end()
+}
+
+unsafe extern "C-unwind" fn wnd_proc(window: HWND, message: u32, param_w: WPARAM, param_l: LPARAM) -> u32 {
+	// same order arcdps calls them in
+	match export::wnd_nofilter(window, message, param_w, param_l) {
+		0 => 0,
+		message => export::wnd_filter(window, message, param_w, param_l),
+	}
+}
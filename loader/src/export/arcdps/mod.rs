@@ -50,9 +50,9 @@ pub fn allocator_fns() -> (Option<MallocFn>, Option<FreeFn>, *mut c_void) {
 
 #[cfg(not(feature = "arcdps-codegen"))]
 pub mod extern_ {
-	use std::{alloc::{GlobalAlloc, Layout}, ffi::{c_char, c_void, CStr}, ptr::{self, NonNull}};
+	use std::{alloc::{GlobalAlloc, Layout}, ffi::{c_char, c_void, CStr}, ptr::{self, NonNull}, sync::Mutex};
 	use windows::Win32::Foundation::{HMODULE, HWND, LPARAM, WPARAM};
-	use windows_strings::PCSTR;
+	use windows_strings::{HSTRING, PCSTR};
 	use arcdps::{
 		imgui::sys as imgui_sys,
 		callbacks::ArcDpsExport,
@@ -108,6 +108,25 @@ pub mod extern_ {
 		Some(init)
 	}
 
+	/// Stand-in for [get_init_addr] when it's Nexus that loaded us,
+	/// so that arcdps extensions can still be hosted through `addextension2`
+	#[cfg(feature = "addonapi")]
+	pub(crate) unsafe fn init_hosted(
+		arcdps: HMODULE,
+		imgui_ctx: *mut imgui_sys::ImGuiContext,
+		malloc: Option<MallocFn>,
+		free: Option<FreeFn>,
+		id3d: *mut c_void,
+	) {
+		const ARC_VERSION_UNKNOWN: &'static CStr = cstr!("unknown");
+		const D3D_VERSION: u32 = 11;
+
+		ptr::write(ptr::addr_of_mut!(MALLOC), malloc);
+		ptr::write(ptr::addr_of_mut!(FREE), free);
+		ptr::write(ptr::addr_of_mut!(IMGUI_CTX), imgui_ctx);
+		arcdps_rs_init(ARC_VERSION_UNKNOWN.as_ptr(), arcdps, imgui_ctx, malloc, free, id3d, D3D_VERSION, env!("CARGO_PKG_NAME"));
+	}
+
 	unsafe extern "C" fn init() -> *const ArcDpsExport {
 		match export::init() {
 			Ok(()) => {
@@ -190,6 +209,23 @@ extern_fns! {
 		Some(release)
 	}
 
+	/// Where arcdps should download a newer arcloader from, if there is one
+	#[no_mangle]
+	pub unsafe extern "system" fn get_update_url() -> *const u16 {
+		// arcdps reads it after we return, so it has to outlive the call
+		static UPDATE_URL: Mutex<Option<HSTRING>> = Mutex::new(None);
+
+		let url = export::update_url()
+			.map(|url| HSTRING::from(url.as_str()));
+		let mut stored = UPDATE_URL.lock()
+			.unwrap_or_else(|e| e.into_inner());
+		*stored = url;
+		match &*stored {
+			Some(url) => url.as_ptr(),
+			None => ptr::null(),
+		}
+	}
+
 	unsafe extern "C" fn release() {
 		export::release()
 	}
@@ -1,5 +1,5 @@
 use std::{ffi::CStr, num::NonZeroU32, sync::atomic::{AtomicBool, Ordering}};
-use crate::{extensions::Loader, supervisor::Supervisor, ui::Options, RenderThread};
+use crate::{extensions::{Loader, ReloadState}, supervisor::{SafeMode, Supervisor}, ui::Options, RenderThread};
 #[cfg(feature = "host-addonapi")]
 use crate::host::addonapi::NexusHost;
 use ::arcdps::evtc::{Agent, Event};
@@ -14,6 +14,9 @@ pub mod arcdps;
 #[cfg(feature = "arcdps")]
 pub use self::arcdps::{imgui_ctx, imgui_ui, allocator_fns};
 
+#[cfg(feature = "addonapi")]
+pub mod addonapi;
+
 pub const SIG: NonZeroU32 = unsafe { NonZeroU32::new_unchecked(
 	u32::from_le_bytes([b'm', b'e', b'w', 3])
 ) };
@@ -35,12 +38,17 @@ pub unsafe fn init() -> Result<(), &'static CStr> {
 		env::set_var("RUST_LIB_BACKTRACE", "1");
 	}
 
+	// has to be decided while the modifier might still be held
+	SafeMode::init();
 	Supervisor::init();
 	Loader::init();
 	Options::init();
 	#[cfg(feature = "host-addonapi")] {
 		NexusHost::init();
 	}
+	if let Some(state) = ReloadState::take() {
+		state.restore();
+	}
 
 	ARC_LOADED.store(true, Ordering::Relaxed);
 
@@ -61,7 +69,12 @@ pub fn release() {
 }
 
 pub fn update_url() -> Option<String> {
-	None
+	match () {
+		#[cfg(feature = "host-addonapi")]
+		() => crate::host::addonapi::Updates::self_update_url(),
+		#[cfg(not(feature = "host-addonapi"))]
+		() => None,
+	}
 }
 
 pub fn evtc(event: Option<&Event>, src: Option<&Agent>, dst: Option<&Agent>, skill_name: PCSTR, id: u64, revision: u64, is_local: bool) {
@@ -97,6 +110,11 @@ pub fn imgui(not_charsel_or_loading: bool) {
 		NexusHost::imgui_present(not_charsel_or_loading);
 	}
 	Supervisor::imgui_present();
+	Loader::imgui_present();
+	// arcloader may have just been asked to exit
+	if !ARC_LOADED.load(Ordering::Relaxed) {
+		return
+	}
 	Options::imgui_present();
 }
 
@@ -0,0 +1,169 @@
+use crate::{extensions::AddonLoader, settings::{settings_key, Settings}, util::arc::config_dir};
+use std::{fmt::Write as _, fs, io, path::{Path, PathBuf}, sync::{Mutex, MutexGuard}, time::{Duration, SystemTime}};
+
+static LOAD_JOURNAL: Mutex<LoadJournal> = Mutex::new(LoadJournal::empty());
+
+/// Extensions in the middle of initializing, written out before calling into
+/// them so that a crash can be pinned on the right one next session
+#[derive(Debug)]
+pub struct LoadJournal {
+	next_id: u64,
+	attempts: Vec<(u64, LoadAttempt)>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoadAttempt {
+	pub loader: AddonLoader,
+	pub path: PathBuf,
+	/// Seconds since the unix epoch
+	pub since: u64,
+}
+
+/// Clears its [LoadAttempt] from the journal once the call into the extension returns
+#[must_use]
+#[derive(Debug)]
+pub struct LoadAttemptGuard {
+	id: u64,
+}
+
+impl LoadJournal {
+	pub const FILE_NAME: &'static str = "arcloader-journal.txt";
+
+	pub const fn empty() -> Self {
+		Self {
+			next_id: 1,
+			attempts: Vec::new(),
+		}
+	}
+
+	fn lock() -> MutexGuard<'static, Self> {
+		LOAD_JOURNAL.lock()
+			.unwrap_or_else(|e| e.into_inner())
+	}
+
+	pub fn path() -> Option<PathBuf> {
+		config_dir()
+			.map(|dir| dir.join(Self::FILE_NAME))
+	}
+
+	/// Record that `path` is about to be initialized
+	pub fn attempt(loader: AddonLoader, path: &Path) -> LoadAttemptGuard {
+		let mut journal = Self::lock();
+		let id = journal.next_id;
+		journal.next_id += 1;
+		journal.attempts.push((id, LoadAttempt {
+			loader,
+			path: path.to_owned(),
+			since: unix_now(),
+		}));
+		if let Err(_e) = journal.write() {
+			warn!("failed to write load journal: {_e}");
+		}
+
+		LoadAttemptGuard {
+			id,
+		}
+	}
+
+	fn finish(id: u64) {
+		let mut journal = Self::lock();
+		journal.attempts.retain(|&(i, _)| i != id);
+		if let Err(_e) = journal.write() {
+			warn!("failed to write load journal: {_e}");
+		}
+	}
+
+	fn write(&self) -> io::Result<()> {
+		let path = Self::path()
+			.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "arcdps config dir unavailable"))?;
+		if self.attempts.is_empty() {
+			return match fs::remove_file(&path) {
+				Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+				_ => Ok(()),
+			}
+		}
+
+		let mut data = String::new();
+		for (_, attempt) in &self.attempts {
+			let _ = writeln!(data, "{}\t{}\t{}", attempt.loader.journal_name(), attempt.since, attempt.path.display());
+		}
+		// the process is about to find out whether it survives, so this can't wait
+		fs::write(&path, data)
+	}
+
+	/// Whatever was still initializing when the previous session ended
+	pub fn take_leftovers() -> Vec<LoadAttempt> {
+		let path = match Self::path() {
+			Some(path) => path,
+			None => return Vec::new(),
+		};
+		let data = match fs::read_to_string(&path) {
+			Ok(data) => data,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => return Vec::new(),
+			Err(_e) => {
+				error!("failed to read {}: {_e}", path.display());
+				return Vec::new()
+			},
+		};
+		let _ = fs::remove_file(&path);
+
+		data.lines()
+			.filter_map(|line| {
+				let mut fields = line.splitn(3, '\t');
+				let loader = AddonLoader::from_journal_name(fields.next()?)?;
+				let since = fields.next()?.parse().ok()?;
+				let path = PathBuf::from(fields.next()?);
+				Some(LoadAttempt { loader, path, since })
+			}).collect()
+	}
+
+	/// Quarantine anything that took the game down with it last time
+	pub fn recover() {
+		let leftovers = Self::take_leftovers();
+		if leftovers.is_empty() {
+			return
+		}
+
+		Settings::update_with(|settings| {
+			let mut changed = false;
+			for attempt in &leftovers {
+				let key = match settings_key(&attempt.path) {
+					Some(key) => key,
+					None => continue,
+				};
+				warn!("{} crashed while loading, quarantining it", attempt.path.display());
+				changed |= settings.extensions_mut(attempt.loader).quarantine(&key, attempt.since);
+			}
+			changed
+		});
+	}
+}
+
+impl Drop for LoadAttemptGuard {
+	fn drop(&mut self) {
+		LoadJournal::finish(self.id);
+	}
+}
+
+impl AddonLoader {
+	fn journal_name(&self) -> &'static str {
+		match self {
+			AddonLoader::Arcdps => "arcdps",
+			AddonLoader::NexusHost => "nexus",
+		}
+	}
+
+	fn from_journal_name(name: &str) -> Option<Self> {
+		match name {
+			"arcdps" => Some(AddonLoader::Arcdps),
+			"nexus" => Some(AddonLoader::NexusHost),
+			_ => None,
+		}
+	}
+}
+
+pub fn unix_now() -> u64 {
+	SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)
+		.unwrap_or(Duration::ZERO)
+		.as_secs()
+}
@@ -1,10 +1,11 @@
-use crate::{supervisor::Supervisor, util::{arc::{add_extension, remove_extension}, win::{load_library_w, WinResult}}};
-use std::num::NonZeroU32;
+use crate::{extensions::{original_module_path, LoadJournal, LoaderQueue, LoaderRequestId, ReloadState, ShadowCache, TrustStore}, settings::Settings, supervisor::{ExtCollision, ExtDir, ExtImage, Supervisor, SUPERVISOR}, util::{arc::{add_extension, remove_extension}, win::{get_module_from_name, get_module_from_ptr, get_module_path, load_library_w, pe::PeInfo, retain_library, WinResult}}};
+use arcdps::exports;
+use std::{mem, num::NonZeroU32, path::{Path, PathBuf}, thread, time::{Duration, Instant}};
 #[cfg(feature = "arcdps-extras")]
 use arcdps::extras::{ExtrasAddonInfo, UserInfoIter};
 use windows::{
 	core::{Error as WinError, Owned},
-	Win32::{Foundation::{ERROR_CALL_NOT_IMPLEMENTED, ERROR_NOT_FOUND, HMODULE}, System::LibraryLoader::GetProcAddress},
+	Win32::{Foundation::{ERROR_BAD_EXE_FORMAT, ERROR_BUSY, ERROR_CALL_NOT_IMPLEMENTED, ERROR_CANNOT_COPY, ERROR_MOD_NOT_FOUND, ERROR_NOT_FOUND, ERROR_NOT_SUPPORTED, ERROR_TIMEOUT, ERROR_WRITE_FAULT, HANDLE, HMODULE, WAIT_OBJECT_0}, System::{LibraryLoader::{FreeLibraryAndExitThread, GetProcAddress}, Threading::WaitForSingleObject}},
 };
 use windows_strings::HSTRING;
 
@@ -13,7 +14,8 @@ pub enum LoaderCommand {
 	LoaderExit,
 	LoaderReload,
 	LoadPath {
-		path: HSTRING
+		path: HSTRING,
+		loader: Option<AddonLoader>,
 	},
 	LoadModule {
 		module: Owned<HMODULE>,
@@ -26,36 +28,378 @@ pub enum LoaderCommand {
 	Reload {
 		module: Owned<HMODULE>,
 	},
+	/// The second half of [Reload](Self::Reload), once the old module is unloaded
+	ReloadPath {
+		path: HSTRING,
+		/// The old module, which has to be gone before `path` can be loaded again
+		previous: HMODULE,
+		previous_path: HSTRING,
+		target: ReloadTarget,
+	},
+	/// Whatever [ReloadPath](Self::ReloadPath) loaded, ready to be initialized
+	ReloadModule {
+		module: Owned<HMODULE>,
+		target: ReloadTarget,
+	},
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AddonLoader {
 	Arcdps,
 	NexusHost,
 }
 
+impl AddonLoader {
+	/// Guess from the file name alone, without loading anything
+	pub fn for_path(path: &Path) -> Option<Self> {
+		let fname = path.file_name()?.to_string_lossy();
+		match () {
+			_ if fname.contains(ExtDir::INFIX_ARCDPS) => Some(AddonLoader::Arcdps),
+			#[cfg(feature = "host-addonapi")]
+			_ if fname.contains(ExtDir::INFIX_NEXUS) => Some(AddonLoader::NexusHost),
+			_ => None,
+		}
+	}
+
+	/// Decide by which entry points a DLL exports, without loading it
+	pub fn for_exports(info: &PeInfo) -> Option<Self> {
+		match () {
+			_ if info.has_export(Self::SYM_ARCDPS) => Some(AddonLoader::Arcdps),
+			#[cfg(feature = "host-addonapi")]
+			_ if info.has_export(Self::SYM_NEXUS) => Some(AddonLoader::NexusHost),
+			_ => None,
+		}
+	}
+
+	pub const SYM_ARCDPS: &'static str = "get_init_addr";
+	pub const SYM_NEXUS: &'static str = "GetAddonDef";
+}
+
 pub struct Loader {
 }
 
+/// Where a module being reloaded came from
+#[derive(Copy, Clone, Debug)]
+pub enum ReloadTarget {
+	Arcdps {
+		sig: NonZeroU32,
+	},
+	#[cfg(feature = "host-addonapi")]
+	NexusHost {
+		sig: crate::util::nexus::NexusId,
+		loaded: bool,
+	},
+}
+
 impl Loader {
 	pub fn init() {
+		LoadJournal::recover();
+		ShadowCache::init();
+		LoaderQueue::start();
 	}
 
 	pub fn unload() {
+		LoaderQueue::stop();
+		ShadowCache::unload();
+		TrustStore::unload();
+	}
+
+	pub fn imgui_present() {
+		ReloadState::imgui_present();
+		LoaderQueue::process_pending();
+	}
+
+	/// Run `cmd` in the background, see [LoaderQueue]
+	pub fn queue_command(cmd: LoaderCommand) -> LoaderRequestId {
+		LoaderQueue::lock().queue(cmd)
+	}
+
+	/// The slow half of [LoaderCommand::LoadPath], which doesn't need the render thread
+	pub fn load_path(path: &HSTRING, loader: Option<AddonLoader>) -> WinResult<LoaderCommand> {
+		let path_buf = PathBuf::from(path.to_os_string());
+		let image = match ExtImage::read(&path_buf) {
+			Ok(image) => Some(image),
+			Err(_e) => {
+				// LoadLibrary will have something better to say about it
+				debug!("failed to inspect {}: {_e}", path_buf.display());
+				None
+			},
+		};
+		if let Some(image) = image.as_ref().filter(|image| !image.is_compatible()) {
+			return Err(WinError::new(ERROR_BAD_EXE_FORMAT.to_hresult(), format!("{} is built for {}", path_buf.display(), image.machine_name())))
+		}
+
+		let module = Self::load_library(path)?;
+		let loader = image.and_then(|image| image.preferred_loader(&path_buf))
+			.or_else(|| AddonLoader::for_path(&path_buf))
+			.or(loader);
+		Ok(LoaderCommand::LoadModule { module, loader })
+	}
+
+	/// Hand `module` to arcdps, journaling the attempt in case its init crashes
+	pub fn add_extension(module: Owned<HMODULE>) -> WinResult<()> {
+		let _attempt = original_module_path(*module).ok()
+			.map(|path| LoadJournal::attempt(AddonLoader::Arcdps, &path));
+		add_extension(module)
+	}
+
+	/// Load an extension DLL, from a shadow copy if settings ask for it,
+	/// but only once the user trusts it (see [TrustStore])
+	pub fn load_library(path: &HSTRING) -> WinResult<Owned<HMODULE>> {
+		let original = PathBuf::from(path.to_os_string());
+		let shadow_copy = Settings::lock_read().wants_shadow_copy();
+		let shadow = match shadow_copy {
+			true => match ShadowCache::shadow(&original) {
+				Ok(shadow) => Some(HSTRING::from(shadow.as_path())),
+				Err(_e) => {
+					warn!("failed to shadow copy {}, loading it directly: {_e}", original.display());
+					None
+				},
+			},
+			false => None,
+		};
+
+		// hash whatever is actually about to be loaded, not whatever the original is by now
+		let loading = shadow.as_ref().unwrap_or(path);
+		let hash = TrustStore::verify(&original, &PathBuf::from(loading.to_os_string()))?;
+
+		// TODO: load with LOAD_LIBRARY_SEARCH_DLL_LOAD_DIR or something idk
+		let module = load_library_w(loading, Default::default())?;
+		if let Some(hash) = hash {
+			TrustStore::loaded(&original, hash);
+		}
+		Ok(module)
+	}
+
+	/// How long an unloaded module gets to actually go away
+	const RELOAD_TIMEOUT: Duration = Duration::from_secs(2);
+
+	/// Unload `module`, returning the [ReloadPath](LoaderCommand::ReloadPath) that brings it back
+	pub fn reload_unload(module: Owned<HMODULE>) -> WinResult<LoaderCommand> {
+		let handle = *module;
+		let loaded_path = get_module_path(Some(handle))
+			.map_err(|e| Self::stage_error("path lookup", e))?;
+		let path = ShadowCache::original_path(Path::new(&loaded_path))
+			.unwrap_or_else(|| PathBuf::from(&loaded_path));
+		let target = Self::reload_target(handle)?;
+
+		info!("reloading {}...", path.display());
+
+		match target {
+			ReloadTarget::Arcdps { sig } =>
+				Self::send_command(LoaderCommand::Unload { sig }),
+			#[cfg(feature = "host-addonapi")]
+			ReloadTarget::NexusHost { sig, .. } =>
+				crate::host::addonapi::NexusHost::unload_addon(sig),
+		}.map_err(|e| Self::stage_error("unload", e))?;
+
+		// ours should be the last reference
+		drop(module);
+
+		Ok(LoaderCommand::ReloadPath {
+			path: HSTRING::from(path.as_path()),
+			previous: handle,
+			previous_path: HSTRING::from(loaded_path),
+			target,
+		})
+	}
+
+	/// The slow half of [LoaderCommand::ReloadPath], which doesn't need the render thread
+	pub fn reload_path(path: &HSTRING, previous: HMODULE, previous_path: &HSTRING, target: ReloadTarget) -> WinResult<LoaderCommand> {
+		Self::wait_for_free(previous, previous_path)
+			.map_err(|e| Self::stage_error("unload", e))?;
+
+		#[cfg(feature = "host-addonapi")]
+		if let ReloadTarget::NexusHost { .. } = target {
+			crate::host::addonapi::Updates::apply_reloading(Path::new(&path.to_os_string()));
+		}
+
+		let module = Self::load_library(path)
+			.map_err(|e| Self::stage_error("load", e))?;
+		Ok(LoaderCommand::ReloadModule { module, target })
+	}
+
+	fn reload_module(module: Owned<HMODULE>, target: ReloadTarget) -> WinResult<()> {
+		match target {
+			ReloadTarget::Arcdps { .. } =>
+				Self::add_extension(module),
+			#[cfg(feature = "host-addonapi")]
+			ReloadTarget::NexusHost { loaded, .. } => {
+				use crate::host::addonapi::NexusHost;
+
+				let sig = NexusHost::enumerate_addon(module)?;
+				match loaded {
+					true => NexusHost::load_addon(sig),
+					false => Ok(()),
+				}
+			},
+		}.map_err(|e| Self::stage_error("init", e))
+	}
+
+	fn reload_target(module: HMODULE) -> WinResult<ReloadTarget> {
+		let arcdps = SUPERVISOR.read().ok()
+			.and_then(|sv| sv.arcdps.values()
+				.find(|ext| ext.module == module)
+				.map(|ext| ext.sig)
+			);
+		if let Some(sig) = arcdps {
+			return Ok(ReloadTarget::Arcdps { sig })
+		}
+
+		#[cfg(feature = "host-addonapi")] {
+			let host = crate::host::addonapi::NexusHost::lock_read();
+			if let Some(addon) = host.addons.values().find(|a| a.module() == module) {
+				if !addon.can_hotload() {
+					return Err(WinError::new(ERROR_NOT_SUPPORTED.to_hresult(), format!("{addon} does not support hotloading")))
+				}
+				return Ok(ReloadTarget::NexusHost {
+					sig: addon.signature,
+					loaded: addon.is_loaded(),
+				})
+			}
+		}
+
+		Err(WinError::new(ERROR_NOT_FOUND.to_hresult(), format!("{module:?} is not a known extension")))
+	}
+
+	fn wait_for_free(module: HMODULE, path: &HSTRING) -> WinResult<()> {
+		let start = Instant::now();
+		loop {
+			match get_module_from_name(path) {
+				Ok(Some(h)) if h == module => (),
+				_ => return Ok(()),
+			}
+			if start.elapsed() >= Self::RELOAD_TIMEOUT {
+				return Err(WinError::new(ERROR_TIMEOUT.to_hresult(), format!("{} is still loaded", path)))
+			}
+			thread::sleep(Duration::from_millis(10));
+		}
+	}
+
+	/// How long the render thread gets to return out of our code before it's unmapped
+	const EXIT_GRACE: Duration = Duration::from_secs(1);
+	/// How long to wait on a reloaded instance before giving up and staying resident
+	const TAKEOVER_TIMEOUT: Duration = Duration::from_secs(60);
+
+	pub fn self_module() -> WinResult<HMODULE> {
+		get_module_from_ptr(Self::self_module as *const _)?
+			.ok_or_else(|| ERROR_MOD_NOT_FOUND.into())
+	}
+
+	/// Tear arcloader down, optionally replacing it with a fresh copy of itself
+	fn exit(reload: bool) -> WinResult<()> {
+		if !exports::has_remove_extension() || (reload && !exports::has_add_extension()) {
+			return Err(WinError::new(ERROR_NOT_SUPPORTED.to_hresult(), "arcdps does not support this"))
+		}
+
+		#[cfg(feature = "host-addonapi")] {
+			let guests = crate::host::addonapi::NexusHost::lock_read().guest_modules();
+			if !guests.is_empty() {
+				let names: Vec<String> = guests.into_iter()
+					.map(|module| original_module_path(module).ok()
+						.and_then(|path| crate::settings::settings_key(&path))
+						.unwrap_or_else(|| format!("{module:?}"))
+					).collect();
+				return Err(WinError::new(ERROR_BUSY.to_hresult(), format!("still hosting the AddonAPI for {}", names.join(", "))))
+			}
+		}
+
+		let self_module = Self::self_module()
+			.map_err(|e| Self::stage_error("self lookup", e))?;
+
+		// prepared up front so that failing here leaves us running
+		let takeover = match reload {
+			true => Some(ReloadState::takeover_event()
+				.map_err(|e| Self::stage_error("takeover", e))?),
+			false => None,
+		};
+		let next = match reload {
+			true => {
+				let origin = original_module_path(self_module)
+					.map_err(|e| Self::stage_error("path lookup", e))?;
+				let copy = ShadowCache::shadow_fresh(&origin)
+					.map_err(|e| Self::stage_error("copy", WinError::new(ERROR_CANNOT_COPY.to_hresult(), e.to_string())))?;
+				ReloadState::capture(Some(origin)).save()
+					.map_err(|e| Self::stage_error("saving state", WinError::new(ERROR_WRITE_FAULT.to_hresult(), e.to_string())))?;
+				// loading it now also stops our own teardown from cleaning the copy up
+				match load_library_w(&HSTRING::from(copy.as_path()), Default::default()) {
+					Ok(module) => Some(module),
+					Err(e) => {
+						ReloadState::discard();
+						return Err(Self::stage_error("load", e))
+					},
+				}
+			},
+			false => None,
+		};
+
+		// arcdps is about to let go of us, but this code still needs to return
+		let retained = retain_library(self_module)
+			.map_err(|e| Self::stage_error("retain", e))?;
+
+		info!("arcloader {}...", if reload { "reloading" } else { "exiting" });
+		if let Err(()) = remove_extension(crate::export::arcdps::SIG) {
+			ReloadState::discard();
+			return Err(Self::stage_error("remove", ERROR_NOT_FOUND.into()))
+		}
+
+		let res = match next {
+			Some(module) => add_extension(module)
+				.map_err(|e| Self::stage_error("add", e)),
+			None => Ok(()),
+		};
+		if let Err(_e) = &res {
+			error!("arcloader failed to come back: {_e}");
+		}
+
+		Self::free_self_later(retained, takeover.filter(|_| res.is_ok()));
+
+		res
+	}
+
+	/// Unmap ourselves once the render thread is definitely done with us,
+	/// which is whenever the new instance signals `takeover` if there is one
+	fn free_self_later(module: Owned<HMODULE>, takeover: Option<Owned<HANDLE>>) {
+		// handles aren't Send, but these are only ever used by the new thread
+		let raw = module.0 as usize;
+		mem::forget(module);
+		let takeover = takeover.map(|event| {
+			let raw = event.0 as usize;
+			mem::forget(event);
+			raw
+		});
+		let res = thread::Builder::new()
+			.name("arcloader-exit".into())
+			.spawn(move || unsafe {
+				let module = HMODULE(raw as *mut _);
+				match takeover.map(|raw| Owned::new(HANDLE(raw as *mut _))) {
+					Some(event) => match WaitForSingleObject(*event, Self::TAKEOVER_TIMEOUT.as_millis() as u32) {
+						WAIT_OBJECT_0 => (),
+						_ => {
+							// leaking ourselves is better than pulling the rug out from under the render thread
+							warn!("arcloader was never taken over, and will stay resident");
+							return
+						},
+					},
+					None => thread::sleep(Self::EXIT_GRACE),
+				}
+				FreeLibraryAndExitThread(module, 0)
+			});
+		if let Err(_e) = res {
+			warn!("arcloader will stay resident: {_e}");
+		}
+	}
+
+	fn stage_error(stage: &str, e: WinError) -> WinError {
+		WinError::new(e.code(), format!("{stage} failed: {}", e.message()))
 	}
 
 	pub fn send_command(cmd: LoaderCommand) -> WinResult<()> {
 		match cmd {
-			LoaderCommand::LoadPath { path } => {
-				// TODO: load with LOAD_LIBRARY_SEARCH_DLL_LOAD_DIR or something idk
-				let module = load_library_w(&path, Default::default())?;
-				let loader = match () {
-					_ if path.to_string_lossy().contains(crate::supervisor::ExtDir::INFIX_ARCDPS) => Some(AddonLoader::Arcdps),
-					_ if path.to_string_lossy().contains(crate::supervisor::ExtDir::INFIX_NEXUS) => Some(AddonLoader::NexusHost),
-					_ => None,
-				};
-				let res = Self::send_command(LoaderCommand::LoadModule { module, loader });
-				res
+			LoaderCommand::LoadPath { path, loader } => {
+				let cmd = Self::load_path(&path, loader)?;
+				Self::send_command(cmd)
 			},
 			LoaderCommand::LoadModule { module, loader } => {
 				let loader = match loader {
@@ -70,15 +414,36 @@ impl Loader {
 
 				#[allow(unreachable_patterns)]
 				match loader {
-					AddonLoader::Arcdps => add_extension(module),
+					AddonLoader::Arcdps => {
+						let path = original_module_path(*module).ok();
+						if let Some((kind, existing)) = Supervisor::nexus_collision(*module, path.as_deref()) {
+							let collision = ExtCollision {
+								kind,
+								existing,
+								rejected: path.unwrap_or_default(),
+								rejected_loader: AddonLoader::Arcdps,
+							};
+							let err = collision.to_error();
+							Supervisor::record_collision(collision);
+							return Err(err)
+						}
+						Self::add_extension(module)
+					},
 					#[cfg(feature = "host-addonapi")]
 					AddonLoader::NexusHost => crate::host::addonapi::NexusHost::enumerate_addon(module).map(drop),
 					_ => return Err(WinError::new(ERROR_CALL_NOT_IMPLEMENTED.to_hresult(), format!("arcloader {:?} support disabled", loader))),
 				}
 			},
 			LoaderCommand::Reload { module } => {
-				Err(ERROR_CALL_NOT_IMPLEMENTED.into())
+				let cmd = Self::reload_unload(module)?;
+				Self::send_command(cmd)
+			},
+			LoaderCommand::ReloadPath { path, previous, previous_path, target } => {
+				let cmd = Self::reload_path(&path, previous, &previous_path, target)?;
+				Self::send_command(cmd)
 			},
+			LoaderCommand::ReloadModule { module, target } =>
+				Self::reload_module(module, target),
 			LoaderCommand::Unload { sig } => {
 				#[cfg(todo)]
 				if retain_handle {
@@ -99,8 +464,10 @@ impl Loader {
 
 				Ok(())
 			},
-			LoaderCommand::LoaderExit => Err(ERROR_CALL_NOT_IMPLEMENTED.into()),
-			LoaderCommand::LoaderReload => Err(ERROR_CALL_NOT_IMPLEMENTED.into()),
+			LoaderCommand::LoaderExit =>
+				Self::exit(false),
+			LoaderCommand::LoaderReload =>
+				Self::exit(true),
 		}
 	}
 }
@@ -1,3 +1,13 @@
+mod journal;
 mod loader;
+mod queue;
+mod reload;
+mod shadow;
+mod trust;
 
-pub use self::loader::{Loader, LoaderCommand};
+pub use self::journal::{unix_now, LoadAttempt, LoadAttemptGuard, LoadJournal};
+pub use self::loader::{AddonLoader, Loader, LoaderCommand, ReloadTarget};
+pub use self::queue::{LoaderQueue, LoaderRequest, LoaderRequestId, LoaderStatus, LoaderTarget, LOADER_QUEUE};
+pub use self::reload::{ReloadAddon, ReloadState};
+pub use self::shadow::{content_hash, original_module_path, ShadowCache};
+pub use self::trust::{FileHash, TrustPrompt, TrustStore, TrustedHash};
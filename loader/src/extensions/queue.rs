@@ -0,0 +1,273 @@
+use crate::{extensions::{original_module_path, Loader, LoaderCommand}, settings::settings_key, supervisor::{Supervisor, SupervisorCommand}};
+use std::{collections::{BTreeMap, VecDeque}, num::{NonZeroU32, NonZeroU64}, path::Path, sync::{mpsc, Mutex, MutexGuard, TryLockError}, thread::{self, JoinHandle}};
+
+pub static LOADER_QUEUE: Mutex<LoaderQueue> = Mutex::new(LoaderQueue::empty());
+
+pub type LoaderRequestId = NonZeroU64;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LoaderStatus {
+	Pending,
+	Ok,
+	Err(String),
+}
+
+/// Which extension a request concerns, so the UI can find it again
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LoaderTarget {
+	/// See [settings_key]
+	pub key: Option<String>,
+	pub sig: Option<NonZeroU32>,
+}
+
+#[derive(Clone, Debug)]
+pub struct LoaderRequest {
+	pub target: LoaderTarget,
+	pub status: LoaderStatus,
+}
+
+/// [LoaderCommand]s waiting their turn
+///
+/// Library loading happens on a worker thread,
+/// and everything else on the render thread during [Loader::imgui_present].
+pub struct LoaderQueue {
+	next_id: u64,
+	pending: VecDeque<QueuedCommand>,
+	requests: BTreeMap<LoaderRequestId, LoaderRequest>,
+	sender: Option<mpsc::Sender<QueuedCommand>>,
+	thread: Option<JoinHandle<()>>,
+}
+
+struct QueuedCommand {
+	id: LoaderRequestId,
+	cmd: LoaderCommand,
+}
+/// modules are only ever handed over, never used from two threads at once
+unsafe impl Send for QueuedCommand {}
+
+impl LoaderTarget {
+	pub fn for_command(cmd: &LoaderCommand) -> Self {
+		match cmd {
+			LoaderCommand::LoadPath { path, .. } => Self {
+				key: settings_key(Path::new(&path.to_os_string())),
+				sig: None,
+			},
+			LoaderCommand::ReloadPath { path, .. } => Self {
+				key: settings_key(Path::new(&path.to_os_string())),
+				sig: None,
+			},
+			LoaderCommand::LoadModule { module, .. } | LoaderCommand::Reload { module } | LoaderCommand::ReloadModule { module, .. } => Self {
+				key: original_module_path(**module).ok()
+					.and_then(|path| settings_key(&path)),
+				sig: None,
+			},
+			LoaderCommand::Unload { sig } => Self {
+				key: None,
+				sig: Some(*sig),
+			},
+			LoaderCommand::LoaderExit | LoaderCommand::LoaderReload => Self {
+				key: None,
+				sig: Some(crate::export::arcdps::SIG),
+			},
+		}
+	}
+
+	pub fn matches(&self, key: Option<&str>, sig: Option<NonZeroU32>) -> bool {
+		let key_matches = match (self.key.as_deref(), key) {
+			(Some(ours), Some(theirs)) => ours == theirs,
+			_ => false,
+		};
+		let sig_matches = match (self.sig, sig) {
+			(Some(ours), Some(theirs)) => ours == theirs,
+			_ => false,
+		};
+		key_matches || sig_matches
+	}
+}
+
+impl LoaderQueue {
+	/// Finished requests to remember for the UI
+	pub const MAX_FINISHED: usize = 32;
+	const THREAD_NAME: &'static str = "arcloader-loader";
+
+	pub const fn empty() -> Self {
+		Self {
+			next_id: 1,
+			pending: VecDeque::new(),
+			requests: BTreeMap::new(),
+			sender: None,
+			thread: None,
+		}
+	}
+
+	pub fn lock() -> MutexGuard<'static, Self> {
+		LOADER_QUEUE.lock()
+			.unwrap_or_else(|e| e.into_inner())
+	}
+
+	pub fn try_lock() -> Option<MutexGuard<'static, Self>> {
+		match LOADER_QUEUE.try_lock() {
+			Ok(queue) => Some(queue),
+			Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+			Err(TryLockError::WouldBlock) => None,
+		}
+	}
+
+	pub fn start() {
+		let mut queue = Self::lock();
+		if queue.sender.is_some() {
+			return
+		}
+
+		let (sender, receiver) = mpsc::channel();
+		match thread::Builder::new().name(Self::THREAD_NAME.into()).spawn(move || Self::run(receiver)) {
+			Ok(thread) => {
+				queue.sender = Some(sender);
+				queue.thread = Some(thread);
+			},
+			Err(_e) => {
+				error!("failed to start loader worker, loads will block: {_e}");
+			},
+		}
+	}
+
+	pub fn stop() {
+		let thread = {
+			let mut queue = Self::lock();
+			queue.sender = None;
+			queue.thread.take()
+		};
+
+		if let Some(thread) = thread {
+			if let Err(_e) = thread.join() {
+				error!("loader worker panicked: {_e:?}");
+			}
+		}
+
+		// anything left over is dropped, freeing its module
+		*Self::lock() = Self::empty();
+	}
+
+	pub fn queue(&mut self, cmd: LoaderCommand) -> LoaderRequestId {
+		let id = LoaderRequestId::new(self.next_id).unwrap();
+		self.next_id += 1;
+
+		self.requests.insert(id, LoaderRequest {
+			target: LoaderTarget::for_command(&cmd),
+			status: LoaderStatus::Pending,
+		});
+
+		self.resume(id, cmd);
+
+		id
+	}
+
+	/// Queue the next step of request `id`, on the worker if it's a slow one
+	fn resume(&mut self, id: LoaderRequestId, cmd: LoaderCommand) {
+		let is_load = matches!(cmd, LoaderCommand::LoadPath { .. } | LoaderCommand::ReloadPath { .. });
+		let mut queued = QueuedCommand { id, cmd };
+		if let (true, Some(sender)) = (is_load, &self.sender) {
+			match sender.send(queued) {
+				Ok(()) => return,
+				Err(mpsc::SendError(q)) => queued = q,
+			}
+		}
+		self.pending.push_back(queued);
+	}
+
+	pub fn request(&self, id: LoaderRequestId) -> Option<&LoaderRequest> {
+		self.requests.get(&id)
+	}
+
+	/// The most recent request concerning an extension
+	pub fn latest_for(&self, key: Option<&str>, sig: Option<NonZeroU32>) -> Option<&LoaderRequest> {
+		self.requests.values().rev()
+			.find(|req| req.target.matches(key, sig))
+	}
+
+	fn finish(&mut self, id: LoaderRequestId, status: LoaderStatus) {
+		if let Some(req) = self.requests.get_mut(&id) {
+			req.status = status;
+		}
+
+		let mut finished = self.requests.iter()
+			.filter(|(_, req)| req.status != LoaderStatus::Pending)
+			.count();
+		while finished > Self::MAX_FINISHED {
+			let oldest = self.requests.iter()
+				.find(|(_, req)| req.status != LoaderStatus::Pending)
+				.map(|(&id, _)| id);
+			match oldest {
+				Some(id) => self.requests.remove(&id),
+				None => break,
+			};
+			finished -= 1;
+		}
+	}
+
+	fn run(receiver: mpsc::Receiver<QueuedCommand>) {
+		for QueuedCommand { id, cmd } in receiver {
+			let cmd = match cmd {
+				LoaderCommand::LoadPath { path, loader } => Loader::load_path(&path, loader),
+				LoaderCommand::ReloadPath { path, previous, previous_path, target } =>
+					Loader::reload_path(&path, previous, &previous_path, target),
+				cmd => Ok(cmd),
+			};
+
+			let mut queue = Self::lock();
+			match cmd {
+				// registering with arcdps or nexus is left to the render thread
+				Ok(cmd) => queue.pending.push_back(QueuedCommand { id, cmd }),
+				Err(e) => {
+					error!("loader request {id} failed: {e}");
+					queue.finish(id, LoaderStatus::Err(e.message()));
+				},
+			}
+		}
+	}
+
+	/// Run everything that's ready, on the render thread
+	pub fn process_pending() {
+		let pending = match Self::try_lock() {
+			Some(mut queue) if !queue.pending.is_empty() => queue.pending.split_off(0),
+			_ => return,
+		};
+
+		let mut pending = pending.into_iter();
+		while let Some(QueuedCommand { id, cmd }) = pending.next() {
+			debug!("loader request {id}: {cmd:?}");
+			let exiting = matches!(cmd, LoaderCommand::LoaderExit | LoaderCommand::LoaderReload);
+			let res = match cmd {
+				// the old module may take a while to go away, so wait for it elsewhere
+				LoaderCommand::Reload { module } => match Loader::reload_unload(module) {
+					Ok(next) => {
+						Self::lock().resume(id, next);
+						continue
+					},
+					Err(e) => Err(e),
+				},
+				cmd => Loader::send_command(cmd),
+			};
+			let status = match res {
+				Ok(()) => LoaderStatus::Ok,
+				Err(e) => {
+					error!("loader request {id} failed: {e}");
+					LoaderStatus::Err(e.message())
+				},
+			};
+			let exiting = exiting && status == LoaderStatus::Ok;
+			Self::lock().finish(id, status);
+
+			// nothing is left to run the rest, or to refresh for
+			if exiting {
+				let mut queue = Self::lock();
+				for QueuedCommand { id, .. } in pending {
+					queue.finish(id, LoaderStatus::Err("arcloader is exiting".into()));
+				}
+				return
+			}
+		}
+
+		let _ = Supervisor::send_command(SupervisorCommand::RefreshArcdps);
+	}
+}
@@ -0,0 +1,230 @@
+use crate::{extensions::{unix_now, Loader, ShadowCache}, util::{arc::config_dir, win::{get_module_path, WinResult}}};
+#[cfg(feature = "host-addonapi")]
+use crate::util::nexus::Keybind;
+use std::{collections::BTreeMap, fs, io, path::{Path, PathBuf}, process, sync::atomic::{AtomicBool, Ordering}};
+use windows::{core::Owned, Win32::{Foundation::HANDLE, System::Threading::{CreateEventW, ResetEvent, SetEvent}}};
+use windows_strings::HSTRING;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Whatever arcloader wants to remember across [LoaderReload](super::LoaderCommand::LoaderReload)
+///
+/// Extensions loaded into arcdps outlive arcloader, so only what we host ourselves needs recording.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct ReloadState {
+	/// The process this was saved by, since it means nothing to any other
+	pub pid: u32,
+	/// When this was saved, in seconds since the unix epoch
+	pub saved_at: u64,
+	/// Where the running arcloader was originally loaded from,
+	/// since the reloaded copy lives in the shadow cache
+	pub origin: Option<PathBuf>,
+	pub nexus: Vec<ReloadAddon>,
+	#[cfg(feature = "host-addonapi")]
+	pub keybinds: BTreeMap<String, Keybind>,
+	pub quick_access: QuickAccessLayout,
+}
+
+/// What the quick access bar looked like
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct QuickAccessLayout {
+	/// Item ids, in the order they were drawn
+	pub items: Vec<String>,
+	pub notifications: BTreeMap<String, u32>,
+}
+
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct ReloadAddon {
+	pub path: PathBuf,
+	/// Enumerated only, or fully loaded
+	pub loaded: bool,
+}
+
+/// Set while a freshly reloaded instance still owes its predecessor a [ReloadState::takeover_event]
+static TAKEOVER_PENDING: AtomicBool = AtomicBool::new(false);
+
+impl ReloadState {
+	pub const FILE_NAME: &'static str = "arcloader-reload.json";
+	/// Anything older was left behind by a reload that never finished
+	pub const STALE_AFTER_SECS: u64 = 60;
+
+	pub fn path() -> Option<PathBuf> {
+		config_dir()
+			.map(|dir| dir.join(Self::FILE_NAME))
+	}
+
+	/// Record the current state of everything that's about to be torn down
+	pub fn capture(origin: Option<PathBuf>) -> Self {
+		#[allow(unused_mut)]
+		let mut state = Self {
+			pid: process::id(),
+			saved_at: unix_now(),
+			origin,
+			..Default::default()
+		};
+
+		#[cfg(feature = "host-addonapi")] {
+			use crate::{extensions::original_module_path, host::addonapi::{input::InputBinds, quick_access::QUICK_ACCESS, NexusHost}};
+
+			state.nexus = NexusHost::lock_read().addons.values()
+				.filter_map(|addon| Some(ReloadAddon {
+					path: original_module_path(addon.module()).ok()?,
+					loaded: addon.is_loaded(),
+				})).collect();
+
+			state.keybinds = InputBinds::lock_read().binds.values()
+				.flatten()
+				.map(|reg| (reg.id.to_string_lossy().into_owned(), reg.bind))
+				.collect();
+
+			if let Ok(menu) = QUICK_ACCESS.read() {
+				state.quick_access = QuickAccessLayout {
+					items: menu.layout().into_iter()
+						.map(|id| id.to_string_lossy().into_owned())
+						.collect(),
+					notifications: menu.notifications.iter()
+						.map(|(id, &count)| (id.to_string_lossy().into_owned(), count))
+						.collect(),
+				};
+			}
+		}
+
+		state
+	}
+
+	pub fn save(&self) -> io::Result<()> {
+		let path = Self::path()
+			.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "arcdps config dir unavailable"))?;
+		self.save_to(&path)
+	}
+
+	/// Pick up where a previous instance left off, if it asked us to
+	pub fn take() -> Option<Self> {
+		let path = Self::path()?;
+		let state = match Self::load_from(&path) {
+			Ok(state) => state,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+			Err(_e) => {
+				error!("failed to load {}: {_e}", path.display());
+				return None
+			},
+		};
+
+		// only good for one reload
+		Self::discard();
+
+		if state.pid != process::id() {
+			warn!("ignoring reload state left behind by process {}", state.pid);
+			return None
+		}
+		let _age = unix_now().saturating_sub(state.saved_at);
+		if _age > Self::STALE_AFTER_SECS {
+			warn!("ignoring reload state from {_age}s ago");
+			return None
+		}
+
+		Some(state)
+	}
+
+	/// Signalled by the new instance once it has drawn a frame,
+	/// after which the render thread is done with the old one
+	pub fn takeover_event() -> WinResult<Owned<HANDLE>> {
+		let event = Self::open_takeover_event()?;
+		// some earlier reload may have left it signalled
+		unsafe { ResetEvent(*event) }?;
+		Ok(event)
+	}
+
+	fn open_takeover_event() -> WinResult<Owned<HANDLE>> {
+		let name = HSTRING::from(format!("Local\\arcloader-takeover-{}", process::id()));
+		unsafe {
+			CreateEventW(None, true, false, &name)
+				.map(|event| Owned::new(event))
+		}
+	}
+
+	/// Let the previous instance go, if it's waiting on us
+	pub fn imgui_present() {
+		if !TAKEOVER_PENDING.swap(false, Ordering::Relaxed) {
+			return
+		}
+
+		let res = Self::open_takeover_event()
+			.and_then(|event| unsafe { SetEvent(*event) });
+		if let Err(_e) = res {
+			warn!("failed to signal takeover: {_e}");
+		}
+	}
+
+	pub fn discard() {
+		if let Some(path) = Self::path() {
+			let _ = fs::remove_file(path);
+		}
+	}
+
+	#[cfg(feature = "serde")]
+	pub fn load_from(path: &Path) -> io::Result<Self> {
+		let f = fs::File::open(path)?;
+		serde_json::from_reader(io::BufReader::new(f))
+			.map_err(Into::into)
+	}
+
+	#[cfg(feature = "serde")]
+	pub fn save_to(&self, path: &Path) -> io::Result<()> {
+		let data = serde_json::to_vec_pretty(self)?;
+		fs::write(path, &data)
+	}
+
+	#[cfg(not(feature = "serde"))]
+	pub fn load_from(_path: &Path) -> io::Result<Self> {
+		Err(io::Error::new(io::ErrorKind::Unsupported, "arcloader built without serde support"))
+	}
+
+	#[cfg(not(feature = "serde"))]
+	pub fn save_to(&self, _path: &Path) -> io::Result<()> {
+		Err(io::Error::new(io::ErrorKind::Unsupported, "arcloader built without serde support"))
+	}
+
+	/// Hand everything back to the new instance, as part of init
+	pub fn restore(self) {
+		TAKEOVER_PENDING.store(true, Ordering::Relaxed);
+
+		if let Some(origin) = self.origin {
+			info!("arcloader reloaded from {}", origin.display());
+			// we're running from a shadow copy, but should still look like the original
+			let copy = Loader::self_module()
+				.and_then(|module| get_module_path(Some(module)));
+			match copy {
+				Ok(copy) => ShadowCache::insert_original(Path::new(&copy), origin),
+				Err(_e) => warn!("failed to find our own path: {_e}"),
+			}
+		}
+
+		#[cfg(feature = "host-addonapi")] {
+			use crate::host::addonapi::{input::InputBinds, quick_access::{ui::QuickAccessMenuUi, QuickAccessMenu}, NexusHost};
+			use std::ffi::CString;
+
+			InputBinds::lock_write().overrides.extend(self.keybinds.into_iter()
+				.filter_map(|(id, bind)| Some((CString::new(id).ok()?, bind)))
+			);
+
+			{
+				let mut menu = QuickAccessMenu::lock_write();
+				let QuickAccessLayout { items, notifications } = self.quick_access;
+				menu.notifications.extend(notifications.into_iter()
+					.filter_map(|(id, count)| Some((CString::new(id).ok()?.into(), count)))
+				);
+				menu.order = items.into_iter()
+					.filter_map(|id| Some(CString::new(id).ok()?.into()))
+					.collect();
+			}
+			QuickAccessMenuUi::mark_dirty();
+
+			let mut host = NexusHost::lock_write();
+			host.restore.extend(self.nexus);
+		}
+	}
+}
@@ -0,0 +1,167 @@
+use crate::util::win::{get_module_path, WinResult};
+use std::{collections::BTreeMap, env, fs, io, path::{Path, PathBuf}, process, sync::{Mutex, MutexGuard}, time::{Duration, SystemTime}};
+use windows::{core::Owned, Win32::{Foundation::{ERROR_INVALID_PARAMETER, HMODULE, STILL_ACTIVE}, System::Threading::{GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION}}};
+
+static SHADOW_CACHE: Mutex<ShadowCache> = Mutex::new(ShadowCache::empty());
+
+/// Copies of extension DLLs, loaded in place of the originals so those can
+/// still be replaced while the game is running
+#[derive(Debug)]
+pub struct ShadowCache {
+	/// copy -> original, keyed by [ShadowCache::path_key]
+	originals: BTreeMap<String, PathBuf>,
+}
+
+impl ShadowCache {
+	pub const DIR_NAME: &'static str = "arcloader-shadow";
+
+	pub const fn empty() -> Self {
+		Self {
+			originals: BTreeMap::new(),
+		}
+	}
+
+	fn lock() -> MutexGuard<'static, Self> {
+		SHADOW_CACHE.lock()
+			.unwrap_or_else(|e| e.into_inner())
+	}
+
+	pub fn root_dir() -> PathBuf {
+		env::temp_dir().join(Self::DIR_NAME)
+	}
+
+	pub fn session_dir() -> PathBuf {
+		Self::root_dir().join(process::id().to_string())
+	}
+
+	/// Anything that isn't a session dir gets this long before it's cleaned up
+	const STALE_AGE: Duration = Duration::from_secs(60 * 60 * 24);
+
+	/// Clean up after previous sessions
+	pub fn init() {
+		let sessions = match fs::read_dir(Self::root_dir()) {
+			Ok(sessions) => sessions,
+			Err(_) => return,
+		};
+		let session = Self::session_dir();
+		for dir in sessions.filter_map(|d| d.ok()).map(|d| d.path()) {
+			if dir == session || !Self::is_abandoned(&dir) {
+				continue
+			}
+			match fs::remove_dir_all(&dir) {
+				Ok(()) => debug!("removed stale shadow copies in {}", dir.display()),
+				Err(_e) => trace!("leaving {} alone: {_e}", dir.display()),
+			}
+		}
+	}
+
+	/// Whether `dir` belongs to a client that has since exited
+	///
+	/// Other clients running alongside us are still using theirs.
+	fn is_abandoned(dir: &Path) -> bool {
+		let pid = dir.file_name()
+			.and_then(|name| name.to_str())
+			.and_then(|name| name.parse::<u32>().ok());
+		match pid {
+			Some(pid) => !Self::is_running(pid),
+			None => fs::metadata(dir)
+				.and_then(|meta| meta.modified())
+				.ok()
+				.and_then(|modified| SystemTime::now().duration_since(modified).ok())
+				.map(|age| age > Self::STALE_AGE)
+				.unwrap_or(false),
+		}
+	}
+
+	fn is_running(pid: u32) -> bool {
+		let process = match unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) } {
+			Ok(process) => unsafe { Owned::new(process) },
+			// no such process
+			Err(e) if e.code() == ERROR_INVALID_PARAMETER.to_hresult() => return false,
+			// someone else's, or otherwise out of reach, but there
+			Err(_) => return true,
+		};
+		let mut status = 0u32;
+		match unsafe { GetExitCodeProcess(*process, &mut status) } {
+			Ok(()) => status == STILL_ACTIVE.0 as u32,
+			Err(_) => true,
+		}
+	}
+
+	pub fn unload() {
+		*Self::lock() = Self::empty();
+
+		// most copies will still be loaded at this point, so the next session finishes the job
+		let _ = fs::remove_dir_all(Self::session_dir());
+	}
+
+	/// Copy `path` into the session cache, returning the path to load instead
+	pub fn shadow(path: &Path) -> io::Result<PathBuf> {
+		Self::shadow_with(path, false)
+	}
+
+	/// Like [ShadowCache::shadow], but never reuses an existing copy
+	///
+	/// Loading the same path twice just hands back the module that's already loaded.
+	pub fn shadow_fresh(path: &Path) -> io::Result<PathBuf> {
+		Self::shadow_with(path, true)
+	}
+
+	fn shadow_with(path: &Path, fresh: bool) -> io::Result<PathBuf> {
+		let data = fs::read(path)?;
+		let fname = path.file_name()
+			.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file"))?;
+
+		// keeping the original file name means anything keyed by it keeps working,
+		// and the hash keeps different builds apart
+		let hash = format!("{:016x}", content_hash(&data));
+		let mut dir = Self::session_dir().join(&hash);
+		if fresh {
+			let mut generation = 0u32;
+			while dir.try_exists()? {
+				generation += 1;
+				dir = Self::session_dir().join(format!("{hash}.{generation}"));
+			}
+		}
+		let copy = dir.join(fname);
+		if !copy.try_exists()? {
+			fs::create_dir_all(&dir)?;
+			let tmp = copy.with_extension("tmp");
+			fs::write(&tmp, &data)?;
+			fs::rename(&tmp, &copy)?;
+		}
+
+		debug!("shadowing {} as {}", path.display(), copy.display());
+		Self::insert_original(&copy, path.to_owned());
+
+		Ok(copy)
+	}
+
+	pub fn insert_original(copy: &Path, original: PathBuf) {
+		Self::lock().originals.insert(Self::path_key(copy), original);
+	}
+
+	/// Where a shadow copy came from
+	pub fn original_path(path: &Path) -> Option<PathBuf> {
+		Self::lock().originals.get(&Self::path_key(path))
+			.cloned()
+	}
+
+	fn path_key(path: &Path) -> String {
+		path.to_string_lossy().to_lowercase()
+	}
+}
+
+/// Like [get_module_path], but sees through shadow copies
+pub fn original_module_path(module: HMODULE) -> WinResult<PathBuf> {
+	let path = PathBuf::from(get_module_path(Some(module))?);
+	Ok(ShadowCache::original_path(&path).unwrap_or(path))
+}
+
+/// FNV-1a, only needs to tell builds apart within a session
+pub fn content_hash(data: &[u8]) -> u64 {
+	const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+	const PRIME: u64 = 0x100000001b3;
+
+	data.iter().fold(OFFSET_BASIS, |hash, &b| (hash ^ b as u64).wrapping_mul(PRIME))
+}
@@ -0,0 +1,194 @@
+use crate::{extensions::unix_now, settings::{settings_key, Settings}, util::win::{WinError, WinResult}};
+use std::{collections::BTreeMap, fmt, io, path::{Path, PathBuf}, sync::{Mutex, MutexGuard}};
+#[cfg(feature = "verify")]
+use sha2::{Digest, Sha256};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use windows::Win32::Foundation::ERROR_ACCESS_DENIED;
+
+static TRUST_STORE: Mutex<TrustStore> = Mutex::new(TrustStore::empty());
+
+/// SHA-256 of an extension binary
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FileHash(pub [u8; 32]);
+
+/// A binary the user has vouched for, see [Settings::trusted]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct TrustedHash {
+	/// [settings_key] of the file it was trusted as
+	pub name: String,
+	/// Seconds since the unix epoch
+	pub since: u64,
+}
+
+/// A load that was refused until the user decides whether to trust it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrustPrompt {
+	pub path: PathBuf,
+	pub hash: FileHash,
+	/// Something else was trusted under the same name,
+	/// so this is either an update or a replacement
+	pub changed: bool,
+}
+
+/// Hashes of what we've loaded, and loads waiting on a [TrustPrompt]
+#[derive(Debug)]
+pub struct TrustStore {
+	pending: Vec<TrustPrompt>,
+	/// keyed by original path, lowercased
+	loaded: BTreeMap<String, FileHash>,
+}
+
+impl FileHash {
+	#[cfg(feature = "verify")]
+	pub fn of_file(path: &Path) -> io::Result<Self> {
+		let mut f = std::fs::File::open(path)?;
+		let mut hasher = Sha256::new();
+		io::copy(&mut f, &mut hasher)?;
+		Ok(Self(hasher.finalize().into()))
+	}
+
+	#[cfg(not(feature = "verify"))]
+	pub fn of_file(path: &Path) -> io::Result<Self> {
+		let _ = path;
+		Err(io::Error::new(io::ErrorKind::Unsupported, "built without sha2"))
+	}
+}
+
+impl fmt::Display for FileHash {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		for b in &self.0 {
+			write!(f, "{b:02x}")?;
+		}
+		Ok(())
+	}
+}
+
+impl fmt::Debug for FileHash {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "FileHash({self})")
+	}
+}
+
+impl TrustStore {
+	pub const fn empty() -> Self {
+		Self {
+			pending: Vec::new(),
+			loaded: BTreeMap::new(),
+		}
+	}
+
+	fn lock() -> MutexGuard<'static, Self> {
+		TRUST_STORE.lock()
+			.unwrap_or_else(|e| e.into_inner())
+	}
+
+	pub fn unload() {
+		*Self::lock() = Self::empty();
+	}
+
+	fn path_key(path: &Path) -> String {
+		path.to_string_lossy().to_lowercase()
+	}
+
+	/// Hash `loading`, refusing it unless the user already trusts it
+	///
+	/// `original` is where `loading` came from, if it's a [ShadowCache](super::ShadowCache) copy.
+	/// Returns `None` if it couldn't be hashed, but isn't required to be.
+	pub fn verify(original: &Path, loading: &Path) -> WinResult<Option<FileHash>> {
+		let hash = FileHash::of_file(loading);
+
+		let settings = Settings::lock_read();
+		let hash = match hash {
+			Ok(hash) => hash,
+			Err(_e) if !settings.verify_trust => {
+				debug!("failed to hash {}: {_e}", loading.display());
+				return Ok(None)
+			},
+			Err(e) =>
+				return Err(WinError::new(ERROR_ACCESS_DENIED.to_hresult(), format!("failed to hash {}: {e}", loading.display()))),
+		};
+		if !settings.verify_trust || settings.is_trusted(&hash) {
+			return Ok(Some(hash))
+		}
+		let key = settings_key(original);
+		let changed = key.is_some() && settings.trusted.values()
+			.any(|trusted| Some(&trusted.name) == key.as_ref());
+		drop(settings);
+
+		info!("{} ({hash}) is not trusted, asking first", original.display());
+		let mut store = Self::lock();
+		if !store.pending.iter().any(|prompt| prompt.hash == hash) {
+			store.pending.push(TrustPrompt {
+				path: original.to_owned(),
+				hash,
+				changed,
+			});
+		}
+
+		let reason = match changed {
+			true => "has changed since it was trusted",
+			false => "is not trusted yet",
+		};
+		Err(WinError::new(ERROR_ACCESS_DENIED.to_hresult(), format!("{} {reason}", original.display())))
+	}
+
+	/// Remember what `original` looked like when it was loaded
+	pub fn loaded(original: &Path, hash: FileHash) {
+		Self::lock().loaded.insert(Self::path_key(original), hash);
+	}
+
+	pub fn loaded_hash(original: &Path) -> Option<FileHash> {
+		Self::lock().loaded.get(&Self::path_key(original))
+			.copied()
+	}
+
+	/// The module loaded from `original` no longer matches the file on disk
+	pub fn is_stale(original: &Path, on_disk: Option<FileHash>) -> bool {
+		match (Self::loaded_hash(original), on_disk) {
+			(Some(loaded), Some(on_disk)) => loaded != on_disk,
+			_ => false,
+		}
+	}
+
+	pub fn pending() -> Vec<TrustPrompt> {
+		Self::lock().pending.clone()
+	}
+
+	/// Stop asking about `prompt`, whichever way the user went
+	pub fn dismiss(prompt: &TrustPrompt) {
+		Self::lock().pending.retain(|p| p.hash != prompt.hash);
+	}
+
+	/// Vouch for everything that's loaded already,
+	/// so that turning [Settings::verify_trust] on doesn't refuse it all next time
+	pub fn trust_loaded() -> usize {
+		let loaded: Vec<(String, FileHash)> = Self::lock().loaded.iter()
+			.filter_map(|(path, &hash)| Some((settings_key(Path::new(path))?, hash)))
+			.collect();
+		let since = unix_now();
+		let mut trusted = 0;
+		Settings::update_with(|settings| {
+			for (name, hash) in loaded {
+				if !settings.is_trusted(&hash) && settings.trust(hash, name, since) {
+					trusted += 1;
+				}
+			}
+			trusted > 0
+		});
+		trusted
+	}
+
+	/// Add `prompt` to the allowlist, so that the next attempt goes through
+	pub fn trust(prompt: &TrustPrompt) -> bool {
+		Self::dismiss(prompt);
+		let name = match settings_key(&prompt.path) {
+			Some(name) => name,
+			None => return false,
+		};
+		info!("trusting {} ({})", prompt.path.display(), prompt.hash);
+		Settings::update_with(|settings| settings.trust(prompt.hash, name, unix_now()))
+	}
+}
+
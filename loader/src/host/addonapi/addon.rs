@@ -1,7 +1,7 @@
 use std::{collections::{HashMap, HashSet}, ffi::{c_char, CString}, fmt, ops::Deref, sync::{atomic::{AtomicBool, Ordering}, Arc, RwLock, RwLockReadGuard, RwLockWriteGuard}};
 use nexus::{event::RawEventConsumeUnknown, gui::{RawGuiRender, RenderType}};
 use windows::{core::{Error as WinError, Owned}, Win32::Foundation::{ERROR_CALL_NOT_IMPLEMENTED, HMODULE}};
-use crate::host::addonapi::{NexusHost, AddonApiV};
+use crate::host::addonapi::{NexusHost, AddonApiV, Localization, RegistrationSource, SharedData};
 use crate::util::{nexus::{get_addon_def, AddonDesc}, win::WinResult};
 
 pub struct NexusAddon {
@@ -35,9 +35,12 @@ impl NexusAddon {
 			return Ok(())
 		}
 
-		let () = unsafe {
+		Localization::load_addon(self);
+
+		// anything it registers during load that we can't otherwise trace back to it
+		let () = RegistrationSource::with_loading(self.module(), || unsafe {
 			(self.def().load)(self.api.as_ptr())
-		};
+		});
 		// TODO: how is failure signalled?
 		self.loaded.store(true, Ordering::SeqCst);
 		Ok(())
@@ -117,7 +120,11 @@ pub struct NexusAddonCache {
 	pub cstrings: HashMap<Arc<CString>, *const c_char>,
 	pub event_handlers: HashMap<CString, HashSet<RawEventConsumeUnknown>>,
 	pub renderers: HashMap<RenderType, HashSet<RawGuiRender>>,
-	pub shared_data: HashMap<CString, Box<[u8]>>,
+	pub shared_data: HashMap<CString, SharedData>,
+	/// Who registered each event handler and renderer, by address
+	///
+	/// Recorded up front, since it can't be looked up once the module is unloaded.
+	pub sources: HashMap<usize, RegistrationSource>,
 }
 
 impl NexusAddonCache {
@@ -128,6 +135,71 @@ impl NexusAddonCache {
 	pub fn lock_write(rw: &RwLock<Self>) -> RwLockWriteGuard<Self> {
 		rw.write().unwrap_or_else(|e| e.into_inner())
 	}
+
+	/// Empty out a cache whose owner is unloading, returning how much was left in it
+	pub fn release_all(&mut self, source: RegistrationSource) -> usize {
+		let mut leaked = self.release(source);
+		for (_id, handlers) in self.event_handlers.drain() {
+			for _handler in handlers {
+				warn!("{source} never unsubscribed {_handler:?} from {_id:?}");
+				leaked += 1;
+			}
+		}
+		for (_ty, renderers) in self.renderers.drain() {
+			for _renderer in renderers {
+				warn!("{source} never deregistered {_ty:?} renderer {_renderer:?}");
+				leaked += 1;
+			}
+		}
+		self.sources.clear();
+		leaked
+	}
+
+	/// Remember who registered the callback at `p`
+	pub fn register_source(&mut self, p: *const (), source: RegistrationSource) {
+		self.sources.insert(p as usize, source);
+	}
+
+	/// Whether `p` was registered by `source`
+	fn registered_by(sources: &HashMap<usize, RegistrationSource>, p: *const (), source: RegistrationSource) -> bool {
+		match sources.get(&(p as usize)) {
+			Some(&owner) => owner == source,
+			None => source.owns(p),
+		}
+	}
+
+	/// Remove whatever belongs to `source` from a cache shared with others
+	pub fn release(&mut self, source: RegistrationSource) -> usize {
+		let mut leaked = 0;
+		// data links stay where they are, since others may still be reading them
+		for (_id, _data) in self.shared_data.iter().filter(|(_, data)| data.source == source) {
+			debug!("{source} leaves data link {_id:?} behind");
+		}
+		let sources = &self.sources;
+		let registered_by = |p: *const ()| Self::registered_by(sources, p, source);
+		for (_id, handlers) in &mut self.event_handlers {
+			handlers.retain(|&handler| match registered_by(handler as *const ()) {
+				true => {
+					warn!("{source} never unsubscribed {handler:?} from {_id:?}");
+					leaked += 1;
+					false
+				},
+				false => true,
+			});
+		}
+		for (_ty, renderers) in &mut self.renderers {
+			renderers.retain(|&renderer| match registered_by(renderer as *const ()) {
+				true => {
+					warn!("{source} never deregistered {_ty:?} renderer {renderer:?}");
+					leaked += 1;
+					false
+				},
+				false => true,
+			});
+		}
+		self.sources.retain(|_, owner| *owner != source);
+		leaked
+	}
 }
 
 unsafe impl Sync for NexusAddonCache {
@@ -1,28 +1,161 @@
+use std::{sync::{mpsc, Mutex, MutexGuard}, thread::{self, JoinHandle}, time::{Duration, Instant}};
 use nexus::gamebind::GameBind;
-use crate::host::addonapi::NexusHost;
+use windows::Win32::{Foundation::{ERROR_INVALID_WINDOW_HANDLE, ERROR_NOT_FOUND, LPARAM, WPARAM}, UI::WindowsAndMessaging::PostMessageA};
+use crate::{
+	host::addonapi::{game_binds::{GameBinds, InputMessage}, NexusHost},
+	util::win::{get_vk, WinError, WinResult},
+};
+
+static GAME_BIND_WORKER: Mutex<Option<GameBindWorker>> = Mutex::new(None);
+
+/// Lets go of [invoked](NexusHost::addonapi_game_bind_invoke_async) binds once they've been held long enough
+pub struct GameBindWorker {
+	sender: mpsc::Sender<(GameBind, Instant)>,
+	thread: JoinHandle<()>,
+}
+
+impl GameBindWorker {
+	const THREAD_NAME: &'static str = "arcloader-gamebind";
+
+	fn lock() -> MutexGuard<'static, Option<Self>> {
+		GAME_BIND_WORKER.lock()
+			.unwrap_or_else(|e| e.into_inner())
+	}
+
+	fn start() -> Option<Self> {
+		let (sender, receiver) = mpsc::channel();
+		match thread::Builder::new().name(Self::THREAD_NAME.into()).spawn(move || Self::run(receiver)) {
+			Ok(thread) => Some(Self {
+				sender,
+				thread,
+			}),
+			Err(_e) => {
+				warn!("failed to start game bind worker: {_e}");
+				None
+			},
+		}
+	}
+
+	pub fn unload() {
+		let worker = Self::lock().take();
+		if let Some(Self { sender, thread }) = worker {
+			drop(sender);
+			if let Err(_e) = thread.join() {
+				error!("game bind worker panicked: {_e:?}");
+			}
+		}
+	}
+
+	/// Press `bind` now, and release it after `duration`
+	pub fn invoke(bind: GameBind, duration: Duration) {
+		NexusHost::game_bind_send_logged(bind, true);
+
+		let release = (bind, Instant::now() + duration);
+		let sent = {
+			let mut worker = Self::lock();
+			if worker.is_none() {
+				*worker = Self::start();
+			}
+			worker.as_ref()
+				.map(|worker| worker.sender.send(release).is_ok())
+				.unwrap_or(false)
+		};
+		if !sent {
+			NexusHost::game_bind_send_logged(bind, false);
+		}
+	}
+
+	fn run(receiver: mpsc::Receiver<(GameBind, Instant)>) {
+		let mut held: Vec<(GameBind, Instant)> = Vec::new();
+		loop {
+			let received = match held.iter().map(|&(_, at)| at).min() {
+				Some(at) => receiver.recv_timeout(at.saturating_duration_since(Instant::now())),
+				None => receiver.recv()
+					.map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+			};
+			match received {
+				Ok(release) => held.push(release),
+				Err(mpsc::RecvTimeoutError::Timeout) => (),
+				Err(mpsc::RecvTimeoutError::Disconnected) => break,
+			}
+
+			let now = Instant::now();
+			held.retain(|&(bind, at)| match at <= now {
+				true => {
+					NexusHost::game_bind_send_logged(bind, false);
+					false
+				},
+				false => true,
+			});
+		}
+
+		// don't leave anything stuck down
+		for (bind, _) in held {
+			NexusHost::game_bind_send_logged(bind, false);
+		}
+	}
+}
 
 impl NexusHost {
+	/// Pretend the user pressed (or let go of) whatever `bind` is bound to
+	///
+	/// The messages are posted to the game window rather than waited on,
+	/// so this can't block on (or from within) the game's own message loop.
+	pub fn game_bind_send(bind: GameBind, down: bool) -> WinResult<()> {
+		let keybind = GameBinds::lock_read().get(bind as i32)
+			.ok_or_else(|| WinError::new(ERROR_NOT_FOUND.to_hresult(), format!("{bind:?} is not bound")))?;
+		let window = Self::wndproc_window();
+		if window.0.is_null() || window.is_invalid() {
+			return Err(WinError::new(ERROR_INVALID_WINDOW_HANDLE.to_hresult(), "game window not found yet"))
+		}
+
+		for msg in InputMessage::for_keybind(keybind, down, |scan| get_vk(scan).map(|vk| vk.0)) {
+			unsafe {
+				PostMessageA(Some(window), msg.message, WPARAM(msg.param_w), LPARAM(msg.param_l))?;
+			}
+		}
+		Ok(())
+	}
+
+	fn game_bind_send_logged(bind: GameBind, down: bool) {
+		if let Err(_e) = Self::game_bind_send(bind, down) {
+			debug!("failed to {} {bind:?}: {_e}", if down { "press" } else { "release" });
+		}
+	}
+
 	pub unsafe extern "C-unwind" fn addonapi_game_bind_press_async(bind: GameBind) {
-		addonapi_stub!(game_bind::press_async("{:?}", bind) => ())
+		addonapi_stub!(game_bind::press_async("{:?}", bind));
+
+		Self::game_bind_send_logged(bind, true)
 	}
 
 	pub unsafe extern "C-unwind" fn addonapi_game_bind_release_async(bind: GameBind) {
-		addonapi_stub!(game_bind::release_async("{:?}", bind) => ())
+		addonapi_stub!(game_bind::release_async("{:?}", bind));
+
+		Self::game_bind_send_logged(bind, false)
 	}
 
 	pub unsafe extern "C-unwind" fn addonapi_game_bind_invoke_async(bind: GameBind, duration: i32) {
-		addonapi_stub!(game_bind::invoke_async("{:?}, {:?}", bind, duration) => ())
+		addonapi_stub!(game_bind::invoke_async("{:?}, {:?}", bind, duration));
+
+		GameBindWorker::invoke(bind, Duration::from_millis(duration.max(0) as u64))
 	}
 
 	pub unsafe extern "C-unwind" fn addonapi_game_bind_press(bind: GameBind) {
-		addonapi_stub!(game_bind::press("{:?}", bind) => ())
+		addonapi_stub!(game_bind::press("{:?}", bind));
+
+		Self::game_bind_send_logged(bind, true)
 	}
 
 	pub unsafe extern "C-unwind" fn addonapi_game_bind_release(bind: GameBind) {
-		addonapi_stub!(game_bind::release("{:?}", bind) => ())
+		addonapi_stub!(game_bind::release("{:?}", bind));
+
+		Self::game_bind_send_logged(bind, false)
 	}
 
 	pub unsafe extern "C-unwind" fn addonapi_game_bind_is_bound(bind: GameBind) -> bool {
-		addonapi_stub!(game_bind::is_bound("{:?}", bind) => false)
+		addonapi_stub!(game_bind::is_bound("{:?}", bind));
+
+		GameBinds::lock_read().is_bound(bind as i32)
 	}
 }
@@ -0,0 +1,246 @@
+use crate::{
+	host::addonapi::NexusHost,
+	settings::settings_key,
+	util::{arc::game_dir, win::{get_module_from_name, get_module_path, pe::PeInfo}},
+};
+use std::{collections::BTreeMap, fs, io, path::{Path, PathBuf}, sync::{RwLock, RwLockReadGuard}};
+use windows_strings::HSTRING;
+
+static CORESIDENT_NEXUS: RwLock<Option<CoresidentNexus>> = RwLock::new(None);
+
+/// The real Nexus addon loader, found running alongside us
+///
+/// Whatever it manages is left alone, so nothing gets loaded twice.
+#[derive(Clone, Debug)]
+pub struct CoresidentNexus {
+	pub path: PathBuf,
+	pub version: Option<String>,
+	/// Where it loads addons from
+	pub addons_dir: PathBuf,
+	/// Its own idea of which addons are enabled, keyed by [settings_key]
+	pub addons: BTreeMap<String, CoresidentAddon>,
+}
+
+/// An addon entry from Nexus' `AddonConfig.json`, which we only ever read
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CoresidentAddon {
+	pub signature: Option<i32>,
+	pub disabled: bool,
+}
+
+impl CoresidentNexus {
+	/// Nexus proxies one of these, so it has to be named after it
+	pub const MODULE_NAMES: &'static [&'static str] = &["d3d11.dll", "dxgi.dll", "d3d11_chainload.dll"];
+	pub const PRODUCT_NAME: &'static str = "Nexus";
+	pub const CONFIG_FILE: &'static str = "AddonConfig.json";
+
+	pub fn lock_read() -> RwLockReadGuard<'static, Option<Self>> {
+		CORESIDENT_NEXUS.read()
+			.unwrap_or_else(|e| e.into_inner())
+	}
+
+	/// Look for Nexus among the modules already loaded into the game
+	pub fn init() {
+		Self::refresh();
+	}
+
+	/// Look again, since Nexus may have shown up or changed its config since last time
+	pub fn refresh() {
+		let nexus = Self::detect();
+		let mut current = CORESIDENT_NEXUS.write()
+			.unwrap_or_else(|e| e.into_inner());
+		match (&*current, &nexus) {
+			(None, Some(_nexus)) => info!("found Nexus {} at {}, leaving its addons to it",
+				_nexus.version.as_deref().unwrap_or("(unknown version)"),
+				_nexus.path.display(),
+			),
+			(Some(_prev), None) => info!("Nexus at {} is gone", _prev.path.display()),
+			(None, None) => debug!("Nexus is not loaded"),
+			(Some(..), Some(..)) => (),
+		}
+		*current = nexus;
+	}
+
+	pub fn unload() {
+		*CORESIDENT_NEXUS.write().unwrap_or_else(|e| e.into_inner()) = None;
+	}
+
+	pub fn detect() -> Option<Self> {
+		let game_dir = game_dir()?;
+		Self::MODULE_NAMES.iter()
+			.filter_map(|&name| get_module_from_name(&HSTRING::from(name)).ok().flatten())
+			.filter_map(|module| get_module_path(Some(module)).ok().map(PathBuf::from))
+			// the system copies of these live elsewhere, and arcdps may well be one of them
+			.filter(|path| Self::is_in_dir(path, game_dir))
+			.find_map(|path| Self::with_module_path(path, game_dir))
+	}
+
+	fn with_module_path(path: PathBuf, game_dir: &Path) -> Option<Self> {
+		let info = PeInfo::read(&path).ok()?;
+		if !Self::looks_like_nexus(&info) {
+			return None
+		}
+
+		let addons_dir = game_dir.join("addons");
+		let addons = match Self::read_config(&addons_dir.join("Nexus").join(Self::CONFIG_FILE)) {
+			Ok(addons) => addons,
+			Err(_e) => {
+				debug!("failed to read Nexus addon config: {_e}");
+				Default::default()
+			},
+		};
+
+		Some(Self {
+			version: info.version.as_ref().and_then(|v| v.version()),
+			path,
+			addons_dir,
+			addons,
+		})
+	}
+
+	fn looks_like_nexus(info: &PeInfo) -> bool {
+		let by_version = info.version.as_ref()
+			.map(|v| [v.product_name(), v.file_description()].into_iter().flatten().any(|name| name.contains(Self::PRODUCT_NAME)))
+			.unwrap_or(false);
+		let by_exports = info.exports.iter()
+			.filter_map(|export| export.name.as_deref())
+			.any(|name| name.starts_with(Self::PRODUCT_NAME));
+		by_version || by_exports
+	}
+
+	/// Read-only import of which addons Nexus has enabled
+	pub fn read_config(path: &Path) -> io::Result<BTreeMap<String, CoresidentAddon>> {
+		let data = fs::read(path)?;
+		Self::parse_config_data(&data)
+	}
+
+	pub fn parse_config_data(data: &[u8]) -> io::Result<BTreeMap<String, CoresidentAddon>> {
+		let config: serde_json::Value = serde_json::from_slice(data)?;
+		Ok(Self::parse_config(&config))
+	}
+
+	/// Tolerant of the layout changing between Nexus versions, since it isn't ours
+	///
+	/// Takes either the whole document or just its `Addons`.
+	pub fn parse_config(config: &serde_json::Value) -> BTreeMap<String, CoresidentAddon> {
+		let config = config.get("Addons").unwrap_or(config);
+		let entries: Vec<&serde_json::Value> = match config {
+			serde_json::Value::Array(entries) => entries.iter().collect(),
+			serde_json::Value::Object(map) => map.values().collect(),
+			_ => Vec::new(),
+		};
+		entries.into_iter()
+			.filter_map(|entry| {
+				let fname = entry.get("Filename").or(entry.get("Path"))?.as_str()?;
+				let key = settings_key(Path::new(fname))?;
+				Some((key, CoresidentAddon {
+					signature: entry.get("Signature")
+						.and_then(|sig| sig.as_i64())
+						.map(|sig| sig as i32),
+					disabled: entry.get("IsDisabled")
+						.and_then(|d| d.as_bool())
+						.unwrap_or(false),
+				}))
+			}).collect()
+	}
+
+	/// Windows paths compare case-insensitively, and can disagree about separators
+	pub fn path_key(path: &Path) -> String {
+		let path = path.to_string_lossy();
+		let path = path.strip_prefix(r"\\?\").unwrap_or(&path);
+		path.replace('/', "\\")
+			.trim_end_matches('\\')
+			.to_lowercase()
+	}
+
+	/// `path` sits directly inside `dir`
+	pub fn is_in_dir(path: &Path, dir: &Path) -> bool {
+		let path = Self::path_key(path);
+		path.rsplit_once('\\')
+			.map(|(parent, _)| parent == Self::path_key(dir))
+			.unwrap_or(false)
+	}
+
+	/// Nexus will load (or already has loaded) `path` on its own
+	pub fn manages(&self, path: &Path) -> bool {
+		if !Self::is_in_dir(path, &self.addons_dir) {
+			return false
+		}
+		let disabled = settings_key(path)
+			.and_then(|key| self.addons.get(&key))
+			.map(|addon| addon.disabled)
+			.unwrap_or(false);
+		!disabled
+	}
+
+	pub fn addon(&self, path: &Path) -> Option<&CoresidentAddon> {
+		settings_key(path)
+			.and_then(|key| self.addons.get(&key))
+	}
+}
+
+impl NexusHost {
+	/// Whether a co-resident Nexus owns `path`
+	pub fn managed_by_nexus(path: &Path) -> bool {
+		CoresidentNexus::lock_read().as_ref()
+			.map(|nexus| nexus.manages(path))
+			.unwrap_or(false)
+	}
+}
+
+#[test]
+fn coresident_paths() {
+	assert_eq!(CoresidentNexus::path_key(Path::new(r"C:\Games\Guild Wars 2\")), r"c:\games\guild wars 2");
+	assert_eq!(CoresidentNexus::path_key(Path::new(r"\\?\C:/Games/GW2/addons")), r"c:\games\gw2\addons");
+
+	let nexus = CoresidentNexus {
+		path: r"C:\Games\GW2\d3d11.dll".into(),
+		version: None,
+		addons_dir: r"C:\Games\GW2\addons".into(),
+		addons: [("disabled.dll".to_owned(), CoresidentAddon { signature: None, disabled: true })].into_iter().collect(),
+	};
+	assert!(nexus.manages(Path::new(r"c:\games\gw2\ADDONS\nexus_thing.dll")));
+	assert!(nexus.manages(Path::new(r"C:/Games/GW2/addons/nexus_thing.dll")));
+	assert!(!nexus.manages(Path::new(r"C:/Games/GW2/addons/Disabled.dll")));
+	assert!(!nexus.manages(Path::new(r"C:\Games\GW2\addons\arcdps\arcdps_thing.dll")));
+	assert!(!nexus.manages(Path::new(r"C:\Games\GW2\nexus_thing.dll")));
+}
+
+#[test]
+fn coresident_config() {
+	let parse = |json: &str| CoresidentNexus::parse_config_data(json.as_bytes()).unwrap();
+
+	assert!(parse(r#"{ "Version": "2024.1", "Addons": [] }"#).is_empty());
+	// neither an array nor an object of entries
+	assert!(parse(r#""nope""#).is_empty());
+	assert!(parse(r#"{ "Addons": "nope" }"#).is_empty());
+	assert!(CoresidentNexus::parse_config_data(b"{ not json").is_err());
+
+	let addons = parse(r#"{
+		"Version": "2024.1",
+		"Addons": [
+			{ "Signature": -12345, "Filename": "Nexus_Thing.dll", "IsDisabled": true },
+			{ "Signature": 17, "Path": "addons/other.dll" },
+			{ "Signature": 18 },
+			{ "Filename": 5 }
+		]
+	}"#);
+	assert_eq!(addons.len(), 2);
+	assert_eq!(addons["nexus_thing.dll"], CoresidentAddon { signature: Some(-12345), disabled: true });
+	assert_eq!(addons["other.dll"], CoresidentAddon { signature: Some(17), disabled: false });
+
+	// a bare list of entries, without the document around it
+	let addons = parse(r#"[{ "Filename": "bare.dll" }]"#);
+	assert_eq!(addons["bare.dll"], CoresidentAddon { signature: None, disabled: false });
+
+	// keyed by signature, as some versions do
+	let addons = parse(r#"{
+		"Addons": {
+			"17": { "Filename": "keyed.dll", "IsDisabled": false },
+			"18": { "Filename": "keyed2.dll", "Signature": "not a number" }
+		}
+	}"#);
+	assert_eq!(addons.len(), 2);
+	assert_eq!(addons["keyed.dll"], CoresidentAddon { signature: None, disabled: false });
+	assert_eq!(addons["keyed2.dll"].signature, None);
+}
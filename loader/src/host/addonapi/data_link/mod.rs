@@ -12,7 +12,6 @@ pub use self::{
 	nexus::NexusLinkProvider,
 };
 
-#[cfg(todo2)]
 use super::host::RegistrationSource;
 
 pub unsafe trait DataLinkShare {
@@ -27,6 +26,14 @@ pub unsafe trait DataLinkShare {
 	unsafe fn get_data_share_pinned(&mut self) -> NonNull<[u8]>;
 }
 
+/// Memory handed out by [NexusHost::addonapi_data_link_share]
+#[derive(Debug)]
+pub struct SharedData {
+	pub source: RegistrationSource,
+	/// Never freed, since anyone who ever got a pointer to it may still be using it
+	pub data: &'static mut [u8],
+}
+
 #[cfg(todo2)]
 pub struct DataLinkRegistration {
 	pub source: RegistrationSource,
@@ -56,7 +63,7 @@ impl NexusHost {
 		}
 
 		id.and_then(|id| Self::cache_read_with(ptr::null(), |cache|
-			cache.shared_data.get(id).map(|d| d.as_ptr())
+			cache.shared_data.get(id).map(|d| d.data.as_ptr())
 		)).unwrap_or(ptr::null()) as *const c_void
 	}
 
@@ -72,13 +79,24 @@ impl NexusHost {
 			},
 		};
 
-		let mut data = vec![0u8; resource_size].into_boxed_slice();
-		let ptr = data.as_mut_ptr();
-		Self::cache_write_with(ptr::null(), |mut cache|
-			cache.shared_data.insert(id.to_owned(), data)
-		);
-		// TODO: use caches to clean up when owner dies!
-
-		ptr as *mut c_void
+		let source = RegistrationSource::of_ptr(identifier as *const ());
+		Self::cache_write_with(ptr::null(), |mut cache| {
+			match cache.shared_data.get_mut(id) {
+				// shared already, possibly by an earlier load of the same addon
+				Some(shared) if shared.data.len() >= resource_size => {
+					shared.source = source;
+					return shared.data.as_mut_ptr()
+				},
+				Some(_shared) => warn!("data link {id:?} grew from {} to {resource_size} bytes", _shared.data.len()),
+				None => (),
+			}
+			let data = SharedData {
+				source,
+				data: Box::leak(vec![0u8; resource_size].into_boxed_slice()),
+			};
+			let ptr = data.data.as_mut_ptr();
+			cache.shared_data.insert(id.to_owned(), data);
+			ptr
+		}) as *mut c_void
 	}
 }
@@ -1,6 +1,6 @@
 use nexus::event::RawEventConsumeUnknown;
 use crate::{
-	host::addonapi::{NexusAddonCache, NexusHost, NEXUS_HOST},
+	host::addonapi::{NexusAddonCache, NexusHost, RegistrationSource, NEXUS_HOST},
 	util::ffi::cstr_opt,
 };
 use std::{ffi::{c_char, c_void, CStr}, ptr};
@@ -34,10 +34,12 @@ impl NexusHost {
 			},
 		};
 
+		let source = RegistrationSource::of_ptr(consume_callback as *const ());
 		Self::cache_write_with(consume_callback as *const _, move |mut cache| {
 			let handlers = cache.event_handlers.entry(id.to_owned())
 				.or_default();
 			handlers.insert(consume_callback);
+			cache.register_source(consume_callback as *const (), source);
 		});
 
 		// TODO: broadcast a normal event informing everyone about the new subscriber instead of this
@@ -0,0 +1,565 @@
+//! Guild Wars 2's own keybinds, as exported to its InputBinds XML
+
+use crate::util::nexus::Keybind;
+use std::{borrow::Cow, collections::BTreeMap, env, fs, io, num::NonZeroU16, path::{Path, PathBuf}, sync::{RwLock, RwLockReadGuard}};
+use windows::Win32::{System::SystemServices as mk, UI::{Input::KeyboardAndMouse as vk, WindowsAndMessaging as wnd}};
+
+static GAME_BINDS: RwLock<GameBinds> = RwLock::new(GameBinds::new());
+
+/// The game's binds, keyed by the same action ids as [GameBind](nexus::gamebind::GameBind)
+#[derive(Debug, Clone, Default)]
+pub struct GameBinds {
+	/// Primary and secondary bind for each action
+	pub binds: BTreeMap<i32, [Keybind; 2]>,
+	/// Where they were read from
+	pub path: Option<PathBuf>,
+}
+
+/// An `<action>` element, before its keys are translated
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct GameAction {
+	pub id: i32,
+	pub name: String,
+	pub inputs: [Option<GameInput>; 2],
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GameInput {
+	pub device: GameDevice,
+	/// One of the game's own key codes, or a zero-based mouse button
+	///
+	/// See [GameInput::key_scan] for what the keys are.
+	pub code: u16,
+	/// See [GameBinds::MOD_ALT] and friends
+	pub mods: u8,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GameDevice {
+	Keyboard,
+	Mouse,
+}
+
+impl GameBinds {
+	pub const MOD_SHIFT: u8 = 1;
+	pub const MOD_CTRL: u8 = 2;
+	pub const MOD_ALT: u8 = 4;
+
+	pub const fn new() -> Self {
+		Self {
+			binds: BTreeMap::new(),
+			path: None,
+		}
+	}
+
+	pub fn lock_read() -> RwLockReadGuard<'static, Self> {
+		GAME_BINDS.read()
+			.unwrap_or_else(|e| e.into_inner())
+	}
+
+	/// Pick up whichever profile the game exported last
+	pub fn init() {
+		let path = match Self::default_dir().as_deref().and_then(Self::find_latest) {
+			Some(path) => path,
+			None => {
+				info!("no exported game binds found, GameBind API will be unavailable");
+				return
+			},
+		};
+		let binds = match Self::read(&path) {
+			Ok(binds) => binds,
+			Err(_e) => {
+				warn!("failed to read game binds from {}: {_e}", path.display());
+				return
+			},
+		};
+		debug!("read {} game binds from {}", binds.binds.len(), path.display());
+		*GAME_BINDS.write().unwrap_or_else(|e| e.into_inner()) = binds;
+	}
+
+	pub fn unload() {
+		*GAME_BINDS.write().unwrap_or_else(|e| e.into_inner()) = Self::new();
+	}
+
+	pub fn get(&self, id: i32) -> Option<Keybind> {
+		self.binds.get(&id)?
+			.iter().copied()
+			.find(|bind| !bind.is_empty())
+	}
+
+	pub fn is_bound(&self, id: i32) -> bool {
+		self.get(id).is_some()
+	}
+
+	pub fn with_actions(actions: &[GameAction]) -> Self {
+		let binds = actions.iter()
+			.map(|action| (action.id, action.inputs.map(|input| input
+				.and_then(|input| input.to_keybind().or_else(|| {
+					debug!("{:?} uses unknown game key {input:?}", action.name);
+					None
+				}))
+				.unwrap_or_default()
+			)))
+			.collect();
+		Self {
+			binds,
+			path: None,
+		}
+	}
+
+	pub fn parse_xml(xml: &str) -> Self {
+		Self::with_actions(&GameAction::parse_xml(xml))
+	}
+
+	pub fn read(path: &Path) -> io::Result<Self> {
+		let xml = fs::read_to_string(path)?;
+		let mut binds = Self::parse_xml(&xml);
+		binds.path = Some(path.into());
+		Ok(binds)
+	}
+
+	/// Where the game exports binds to
+	pub fn default_dir() -> Option<PathBuf> {
+		env::var_os("USERPROFILE")
+			.map(|home| Path::new(&home).join("Documents").join("Guild Wars 2").join("InputBinds"))
+	}
+
+	/// The most recently exported bind profile
+	pub fn find_latest(dir: &Path) -> Option<PathBuf> {
+		fs::read_dir(dir).ok()?
+			.filter_map(|entry| entry.ok())
+			.filter(|entry| entry.path().extension().map(|ext| ext.eq_ignore_ascii_case("xml")).unwrap_or(false))
+			.filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+			.max_by_key(|(modified, _)| *modified)
+			.map(|(_, path)| path)
+	}
+}
+
+impl GameAction {
+	/// Every `<action>` in an exported bind profile
+	///
+	/// Not a real XML parser, the game's exports are simple enough not to need one.
+	pub fn parse_xml(xml: &str) -> Vec<Self> {
+		const TAG: &str = "<action";
+
+		let mut actions = Vec::new();
+		let mut rest = xml;
+		while let Some(start) = rest.find(TAG) {
+			rest = &rest[start + TAG.len()..];
+			let end = xml_tag_end(rest);
+			let tag = &rest[..end];
+			rest = &rest[end..];
+			// not <actions> or anything else that happens to start the same
+			if !tag.starts_with(|c: char| c.is_ascii_whitespace() || c == '/') {
+				continue
+			}
+			match Self::with_attributes(xml_attributes(tag)) {
+				Some(action) => actions.push(action),
+				None => debug!("skipping unrecognized action <action{tag}>"),
+			}
+		}
+		actions
+	}
+
+	fn with_attributes<'a, I: IntoIterator<Item = (&'a str, Cow<'a, str>)>>(attrs: I) -> Option<Self> {
+		let mut id = None;
+		let mut name = String::new();
+		let mut devices = [None, None];
+		let mut codes = [None, None];
+		let mut mods = [0u8, 0];
+		for (key, value) in attrs {
+			let (key, i) = match key.strip_suffix('2') {
+				Some(key) => (key, 1),
+				None => (key, 0),
+			};
+			match key {
+				"id" if i == 0 => id = value.parse().ok(),
+				"name" if i == 0 => name = value.into_owned(),
+				"device" => devices[i] = GameDevice::parse(&value),
+				"button" | "key" => codes[i] = value.parse().ok(),
+				"mod" => mods[i] = value.parse().unwrap_or(0),
+				_ => (),
+			}
+		}
+
+		let input = |i: usize| Some(GameInput {
+			device: devices[i]?,
+			code: codes[i]?,
+			mods: mods[i],
+		});
+		Some(Self {
+			id: id?,
+			name,
+			inputs: [input(0), input(1)],
+		})
+	}
+}
+
+impl GameDevice {
+	pub fn parse(s: &str) -> Option<Self> {
+		match s {
+			s if s.eq_ignore_ascii_case("keyboard") => Some(Self::Keyboard),
+			s if s.eq_ignore_ascii_case("mouse") => Some(Self::Mouse),
+			_ => None,
+		}
+	}
+}
+
+impl GameInput {
+	/// Letters A to Z, in the order of their game key codes
+	const SCAN_LETTERS: [u16; 26] = [
+		0x1e, 0x30, 0x2e, 0x20, 0x12, 0x21, 0x22, 0x23, 0x17, 0x24, 0x25, 0x26, 0x32,
+		0x31, 0x18, 0x19, 0x10, 0x13, 0x1f, 0x14, 0x16, 0x2f, 0x11, 0x2d, 0x15, 0x2c,
+	];
+
+	/// The scan code of a game key code, as if on a US layout
+	///
+	/// The game doesn't use virtual keys, so this follows the table Nexus uses.
+	/// Extended keys have `0x100` set, like [Keybind] expects.
+	pub fn key_scan(code: u16) -> Option<NonZeroU16> {
+		let scan = match code {
+			0 => 0x38, // left alt
+			1 => 0x1d, // left ctrl
+			2 => 0x2a, // left shift
+			3 => 0x28, // quote
+			4 => 0x2b, // hash
+			5 => 0x3a, // caps lock
+			6 => 0x33, // comma
+			7 => 0x0c, // minus
+			8 => 0x0d, // equals
+			9 => 0x01, // escape
+			10 => 0x1a, // open bracket
+			11 => 0x45, // num lock
+			12 => 0x34, // period
+			13 => 0x1b, // close bracket
+			14 => 0x27, // semicolon
+			15 => 0x35, // slash
+			16 => 0x137, // print screen
+			17 => 0x29, // tilde
+			18 => 0x0e, // backspace
+			19 => 0x153, // delete
+			20 => 0x1c, // enter
+			21 => 0x39, // space
+			22 => 0x0f, // tab
+			23 => 0x14f, // end
+			24 => 0x147, // home
+			25 => 0x152, // insert
+			26 => 0x151, // page down
+			27 => 0x149, // page up
+			28 => 0x150, // down
+			29 => 0x14b, // left
+			30 => 0x14d, // right
+			31 => 0x148, // up
+			// F1 to F10, then F11 and F12
+			32..=41 => 0x3b + (code - 32),
+			42 => 0x57,
+			43 => 0x58,
+			// 0 comes after 9 on the keyboard
+			48 => 0x0b,
+			49..=57 => 0x02 + (code - 49),
+			65..=90 => Self::SCAN_LETTERS[(code - 65) as usize],
+			91 => 0x52, // numpad 0
+			92 => 0x4f,
+			93 => 0x50,
+			94 => 0x51,
+			95 => 0x4b,
+			96 => 0x4c,
+			97 => 0x4d,
+			98 => 0x47,
+			99 => 0x48,
+			100 => 0x49, // numpad 9
+			101 => 0x53, // numpad decimal
+			102 => 0x135, // numpad divide
+			103 => 0x37, // numpad multiply
+			104 => 0x4a, // numpad minus
+			105 => 0x4e, // numpad plus
+			106 => 0x11c, // numpad enter
+			_ => return None,
+		};
+		NonZeroU16::new(scan)
+	}
+
+	pub fn to_keybind(&self) -> Option<Keybind> {
+		match self.device {
+			GameDevice::Keyboard => {
+				let scan = Self::key_scan(self.code)?;
+				Some(Keybind::new_key(
+					scan.get(),
+					self.mods & GameBinds::MOD_ALT != 0,
+					self.mods & GameBinds::MOD_CTRL != 0,
+					self.mods & GameBinds::MOD_SHIFT != 0,
+				))
+			},
+			GameDevice::Mouse => match self.code {
+				button @ 0..=4 => Some(Keybind::new_button(button as u8 + 1)),
+				_ => None,
+			},
+		}
+	}
+}
+
+/// Where the tag starting at `s` ends, ignoring any `>` inside quotes
+fn xml_tag_end(s: &str) -> usize {
+	let mut quote = None;
+	for (i, c) in s.char_indices() {
+		match (quote, c) {
+			(None, '>') => return i,
+			(None, '"' | '\'') => quote = Some(c),
+			(Some(q), c) if q == c => quote = None,
+			_ => (),
+		}
+	}
+	s.len()
+}
+
+fn xml_attributes(mut tag: &str) -> impl Iterator<Item = (&str, Cow<'_, str>)> {
+	std::iter::from_fn(move || {
+		tag = tag.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == '/');
+		let (key, rest) = tag.split_once('=')?;
+		let rest = rest.trim_start();
+		let quote = rest.chars().next().filter(|&c| c == '"' || c == '\'')?;
+		let (value, rest) = rest[1..].split_once(quote)?;
+		tag = rest;
+		Some((key.trim(), xml_unescape(value)))
+	})
+}
+
+fn xml_unescape(s: &str) -> Cow<'_, str> {
+	if !s.contains('&') {
+		return Cow::Borrowed(s)
+	}
+
+	let mut res = String::with_capacity(s.len());
+	let mut rest = s;
+	while let Some(start) = rest.find('&') {
+		res.push_str(&rest[..start]);
+		rest = &rest[start..];
+		let entity = rest.find(';')
+			.map(|end| (&rest[1..end], end + 1));
+		let c = entity.and_then(|(entity, _)| match entity {
+			"amp" => Some('&'),
+			"lt" => Some('<'),
+			"gt" => Some('>'),
+			"quot" => Some('"'),
+			"apos" => Some('\''),
+			num => match num.strip_prefix("#x") {
+				Some(hex) => u32::from_str_radix(hex, 16).ok(),
+				None => num.strip_prefix('#').and_then(|dec| dec.parse().ok()),
+			}.and_then(char::from_u32),
+		});
+		match (c, entity) {
+			(Some(c), Some((_, len))) => {
+				res.push(c);
+				rest = &rest[len..];
+			},
+			_ => {
+				res.push('&');
+				rest = &rest[1..];
+			},
+		}
+	}
+	res.push_str(rest);
+	Cow::Owned(res)
+}
+
+/// A window message that pretends the user pressed a bind
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InputMessage {
+	pub message: u32,
+	pub param_w: usize,
+	pub param_l: isize,
+}
+
+impl InputMessage {
+	const SCAN_ALT: u16 = 0x38;
+	const SCAN_CTRL: u16 = 0x1d;
+	const SCAN_SHIFT: u16 = 0x2a;
+
+	const LPARAM_EXTENDED: isize = 1 << 24;
+	const LPARAM_CONTEXT: isize = 1 << 29;
+	const LPARAM_PREVIOUS: isize = 1 << 30;
+	const LPARAM_TRANSITION: isize = 1 << 31;
+
+	/// Everything the game would see for `bind` going down or coming back up
+	///
+	/// `key_vk` turns a scan code back into its virtual key.
+	/// Mouse messages don't carry a position, the game tracks the cursor on its own.
+	pub fn for_keybind<F: Fn(u16) -> Option<u16>>(bind: Keybind, down: bool, key_vk: F) -> Vec<Self> {
+		if let Some(button) = bind.mouse_button() {
+			// mouse binds can't carry modifiers
+			return Self::button(button.get(), down, false, false)
+				.into_iter().collect()
+		}
+		let input = match bind.key_scan().and_then(|scan| Some((key_vk(scan.get())?, scan.get()))) {
+			Some((vk, scan)) => Self::key(vk, scan, down, bind.alt()),
+			None => return Vec::new(),
+		};
+
+		let mods = [
+			(bind.ctrl(), vk::VK_CONTROL, Self::SCAN_CTRL),
+			(bind.shift(), vk::VK_SHIFT, Self::SCAN_SHIFT),
+			(bind.alt(), vk::VK_MENU, Self::SCAN_ALT),
+		];
+		// alt goes down last, so only it and the key itself are sys keys
+		let mut messages: Vec<Self> = mods.into_iter()
+			.filter(|&(held, ..)| held)
+			.map(|(_, vk, scan)| Self::key(vk.0, scan, down, down && vk == vk::VK_MENU))
+			.collect();
+		match down {
+			true => messages.push(input),
+			false => {
+				messages.reverse();
+				messages.insert(0, input);
+			},
+		}
+		messages
+	}
+
+	pub fn key(vk: u16, scan: u16, down: bool, alt: bool) -> Self {
+		let mut param_l = 1 | ((scan & 0xff) as isize) << 16;
+		if scan > 0xff {
+			param_l |= Self::LPARAM_EXTENDED;
+		}
+		if alt {
+			param_l |= Self::LPARAM_CONTEXT;
+		}
+		if !down {
+			param_l |= Self::LPARAM_PREVIOUS | Self::LPARAM_TRANSITION;
+		}
+		let message = match (down, alt) {
+			(true, false) => wnd::WM_KEYDOWN,
+			(false, false) => wnd::WM_KEYUP,
+			(true, true) => wnd::WM_SYSKEYDOWN,
+			(false, true) => wnd::WM_SYSKEYUP,
+		};
+		Self {
+			message,
+			param_w: vk as usize,
+			param_l,
+		}
+	}
+
+	pub fn button(button: u8, down: bool, ctrl: bool, shift: bool) -> Option<Self> {
+		let (message, held, xbutton) = match (button, down) {
+			(1, true) => (wnd::WM_LBUTTONDOWN, mk::MK_LBUTTON, 0),
+			(1, false) => (wnd::WM_LBUTTONUP, mk::MK_LBUTTON, 0),
+			(2, true) => (wnd::WM_RBUTTONDOWN, mk::MK_RBUTTON, 0),
+			(2, false) => (wnd::WM_RBUTTONUP, mk::MK_RBUTTON, 0),
+			(3, true) => (wnd::WM_MBUTTONDOWN, mk::MK_MBUTTON, 0),
+			(3, false) => (wnd::WM_MBUTTONUP, mk::MK_MBUTTON, 0),
+			(4, true) => (wnd::WM_XBUTTONDOWN, mk::MK_XBUTTON1, wnd::XBUTTON1),
+			(4, false) => (wnd::WM_XBUTTONUP, mk::MK_XBUTTON1, wnd::XBUTTON1),
+			(5, true) => (wnd::WM_XBUTTONDOWN, mk::MK_XBUTTON2, wnd::XBUTTON2),
+			(5, false) => (wnd::WM_XBUTTONUP, mk::MK_XBUTTON2, wnd::XBUTTON2),
+			_ => return None,
+		};
+
+		let mut param_w = (xbutton as usize) << 16;
+		if down {
+			param_w |= held.0 as usize;
+		}
+		if ctrl {
+			param_w |= mk::MK_CONTROL.0 as usize;
+		}
+		if shift {
+			param_w |= mk::MK_SHIFT.0 as usize;
+		}
+		Some(Self {
+			message,
+			param_w,
+			param_l: 0,
+		})
+	}
+}
+
+#[cfg(test)]
+const FIXTURE_XML: &str = include_str!("../../../fixtures/input_binds/Default.xml");
+
+#[test]
+fn game_binds_parse() {
+	let actions = GameAction::parse_xml(FIXTURE_XML);
+	assert_eq!(actions.len(), 10);
+	assert_eq!(actions[0], GameAction {
+		id: 0,
+		name: "Move Forward".into(),
+		inputs: [
+			Some(GameInput { device: GameDevice::Keyboard, code: 87, mods: 0 }),
+			Some(GameInput { device: GameDevice::Keyboard, code: 31, mods: 0 }),
+		],
+	});
+	assert_eq!(actions[2].inputs[1], None);
+
+	let binds = GameBinds::parse_xml(FIXTURE_XML);
+	// W and up
+	assert_eq!(binds.binds[&0], [Keybind::new_key(0x11, false, false, false), Keybind::new_key(0x148, false, false, false)]);
+	// A and left
+	assert_eq!(binds.binds[&4], [Keybind::new_key(0x1e, false, false, false), Keybind::new_key(0x14b, false, false, false)]);
+	assert_eq!(binds.binds[&6][1], Keybind::new_button(4));
+	// R and num lock
+	assert_eq!(binds.binds[&7][1], Keybind::new_key(0x45, false, false, false));
+	assert_eq!(binds.get(8), Some(Keybind::new_key(0x13, true, true, false)));
+	assert_eq!(binds.get(9), Some(Keybind::new_key(0x39, false, false, false)));
+}
+
+#[test]
+fn game_binds_parse_edges() {
+	let xml = r#"<InputBindings>
+<action name="Weapon &quot;Swap&quot;" id="17" device="Keyboard" button="17" mod="1"/>
+<action name="Unbound &gt; Nothing" id="42"/>
+<action name="Mystery Key" id="43" device="Keyboard" button="250"/>
+<actions name="not an action" id="99"/>
+</InputBindings>"#;
+	let actions = GameAction::parse_xml(xml);
+	assert_eq!(actions.len(), 3);
+	assert_eq!(actions[0].name, "Weapon \"Swap\"");
+	assert_eq!(actions[1].name, "Unbound > Nothing");
+	assert_eq!(actions[1].inputs, [None, None]);
+
+	let binds = GameBinds::parse_xml(xml);
+	assert_eq!(binds.get(17), Some(Keybind::new_key(0x29, false, false, true)));
+	assert!(!binds.is_bound(42));
+	assert!(!binds.is_bound(43));
+	assert!(!binds.is_bound(99));
+}
+
+#[test]
+fn game_binds_key_scan() {
+	let scan = |code| GameInput::key_scan(code).map(NonZeroU16::get);
+	assert_eq!(scan(48), Some(0x0b));
+	assert_eq!(scan(49), Some(0x02));
+	assert_eq!(scan(57), Some(0x0a));
+	assert_eq!(scan(32), Some(0x3b));
+	assert_eq!(scan(41), Some(0x44));
+	assert_eq!(scan(43), Some(0x58));
+	assert_eq!(scan(65), Some(0x1e));
+	assert_eq!(scan(73), Some(0x17));
+	assert_eq!(scan(90), Some(0x2c));
+	assert_eq!(scan(44), None);
+	assert_eq!(scan(107), None);
+}
+
+#[test]
+fn game_binds_messages() {
+	let key_vk = |scan: u16| Some(scan + 0x100);
+
+	let down = InputMessage::for_keybind(Keybind::new_key(0x2f, false, true, false), true, key_vk);
+	assert_eq!(down, [
+		InputMessage { message: wnd::WM_KEYDOWN, param_w: vk::VK_CONTROL.0 as usize, param_l: 0x001d_0001 },
+		InputMessage { message: wnd::WM_KEYDOWN, param_w: 0x12f, param_l: 0x002f_0001 },
+	]);
+	let up = InputMessage::for_keybind(Keybind::new_key(0x2f, false, true, false), false, key_vk);
+	assert_eq!(up, [
+		InputMessage { message: wnd::WM_KEYUP, param_w: 0x12f, param_l: 0xc02f_0001u32 as isize },
+		InputMessage { message: wnd::WM_KEYUP, param_w: vk::VK_CONTROL.0 as usize, param_l: 0xc01d_0001u32 as isize },
+	]);
+
+	let alt = InputMessage::for_keybind(Keybind::new_key(0x10, true, false, false), true, key_vk);
+	assert_eq!(alt.iter().map(|m| m.message).collect::<Vec<_>>(), [wnd::WM_SYSKEYDOWN, wnd::WM_SYSKEYDOWN]);
+
+	let m4 = InputMessage::for_keybind(Keybind::M4, true, key_vk);
+	assert_eq!(m4, [
+		InputMessage { message: wnd::WM_XBUTTONDOWN, param_w: 0x0001_0020, param_l: 0 },
+	]);
+
+	assert!(InputMessage::for_keybind(Keybind::EMPTY, true, key_vk).is_empty());
+}
@@ -0,0 +1,116 @@
+use crate::{
+	extensions::Loader,
+	host::addonapi::{module_index::{ModuleOwner, ModuleRange}, AddonApiV, NexusAddonCache, NexusHost, RegistrationSource},
+	util::{nexus::AddonApiVersion, win::{get_module_from_ptr, WinResult}},
+};
+use std::{ffi::c_void, ptr, sync::{Arc, RwLock}};
+use nexus::AddonApi;
+use windows::Win32::{Foundation::HMODULE, System::Diagnostics::Debug::RtlCaptureStackBackTrace};
+
+/// An arcdps extension using the AddonAPI through [arcloader_get_addon_api]
+///
+/// It isn't a nexus addon, so whatever it registers is tracked
+/// by the module its callbacks live in instead.
+pub struct NexusGuest {
+	module: HMODULE,
+	pub cache: Arc<RwLock<NexusAddonCache>>,
+}
+
+impl NexusGuest {
+	pub fn module(&self) -> HMODULE {
+		self.module
+	}
+}
+
+impl NexusHost {
+	/// The API handed to arcdps extensions, shared by all of them
+	pub fn guest_api(&mut self, api_version: AddonApiVersion) -> WinResult<*const AddonApi> {
+		if let Some(api) = self.guest_apis.get(&api_version) {
+			return Ok(api.as_ptr())
+		}
+
+		let api = Box::new(self.api_with_version(api_version)?);
+		let res = api.as_ptr();
+		self.guest_apis.insert(api_version, api);
+		Ok(res)
+	}
+
+	pub fn guest_for_ptr(&self, p: *const ()) -> Option<&NexusGuest> {
+		match self.module_for_ptr(p)? {
+			ModuleRange { owner: ModuleOwner::Guest, source, .. } => self.guests.get(source),
+			_ => None,
+		}
+	}
+
+	/// Start tracking whichever module `p` belongs to, unless it's
+	/// an addon we're already hosting or arcloader itself
+	pub(crate) fn guest_register(&mut self, p: *const ()) {
+		if p.is_null() || self.module_for_ptr(p).is_some() {
+			return
+		}
+		let module = match get_module_from_ptr(p as *const _) {
+			Ok(Some(module)) => module,
+			_ => return,
+		};
+		if Loader::self_module().ok() == Some(module) {
+			return
+		}
+
+		let source = RegistrationSource::of_module(module);
+		if let Err(_e) = self.modules.insert_module(module, ModuleOwner::Guest, source) {
+			warn!("failed to index {module:?}: {_e}");
+			return
+		}
+		debug!("tracking AddonAPI callbacks from {module:?}");
+		self.guests.insert(source, NexusGuest {
+			module,
+			cache: Default::default(),
+		});
+	}
+
+	/// Extensions that could still call into arcloader's AddonAPI,
+	/// and would be left calling into nothing if arcloader went away
+	pub fn guest_modules(&self) -> Vec<HMODULE> {
+		self.guests.values()
+			.map(|guest| guest.module)
+			.collect()
+	}
+
+	/// Forget everything an unloaded arcdps extension registered
+	pub fn guest_release(module: HMODULE) {
+		let source = RegistrationSource::of_module(module);
+		let (guest, fallback) = {
+			let mut host = Self::lock_write();
+			(host.guests.remove(&source), host.fallback_cache.clone())
+		};
+		if let Some(guest) = guest {
+			debug!("releasing AddonAPI callbacks from {:?}", guest.module);
+			NexusHost::release_registrations(source, &guest.cache, &fallback);
+		}
+		// the module is already gone, so the index is all that still knows its range
+		Self::lock_write().modules.remove(ModuleOwner::Guest, source);
+	}
+}
+
+/// Lets arcdps extensions use the AddonAPI while arcloader hosts it
+///
+/// Returns null when `api_version` isn't supported.
+#[no_mangle]
+pub unsafe extern "C-unwind" fn arcloader_get_addon_api(api_version: AddonApiVersion) -> *const AddonApi {
+	// whoever asks is a consumer from now on, registered callbacks or not
+	let mut caller = [ptr::null_mut::<c_void>()];
+	let caller = match RtlCaptureStackBackTrace(1, &mut caller, None) {
+		1 => caller[0] as *const (),
+		_ => ptr::null(),
+	};
+
+	let mut host = NexusHost::lock_write();
+	host.guest_register(caller);
+	match host.guest_api(api_version) {
+		Ok(api) => api,
+		Err(_e) => {
+			warn!("arcloader_get_addon_api({api_version}) failed: {_e}");
+			ptr::null()
+		},
+	}
+}
@@ -1,21 +1,187 @@
 use nexus::hook::HookStatus;
-use crate::host::addonapi::NexusHost;
-use std::ffi::c_void;
+use dyload::{hook::HookError, windows::Hook};
+use crate::host::addonapi::{NexusHost, RegistrationSource};
+use std::{collections::BTreeMap, ffi::c_void, sync::{Mutex, MutexGuard}};
+
+static MIN_HOOKS: Mutex<MinHooks> = Mutex::new(MinHooks::new());
+
+/// Hooks created through the AddonAPI, keyed by their target
+#[derive(Default)]
+pub struct MinHooks {
+	hooks: BTreeMap<usize, MinHook>,
+}
+
+pub struct MinHook {
+	/// Whoever owns the detour
+	pub source: RegistrationSource,
+	pub hook: Hook,
+}
+
+impl MinHooks {
+	pub const fn new() -> Self {
+		Self {
+			hooks: BTreeMap::new(),
+		}
+	}
+
+	pub fn lock() -> MutexGuard<'static, Self> {
+		MIN_HOOKS.lock()
+			.unwrap_or_else(|e| e.into_inner())
+	}
+
+	pub fn unload() {
+		let hooks = {
+			let mut hooks = Self::lock();
+			Self::disable_all(hooks.hooks.values_mut());
+			std::mem::take(&mut hooks.hooks)
+		};
+		if !hooks.is_empty() {
+			debug!("removing {} leftover hooks", hooks.len());
+		}
+	}
+
+	pub fn status(e: HookError) -> HookStatus {
+		match e {
+			HookError::NotExecutable => HookStatus::ErrorNotExecutable,
+			HookError::MemoryAlloc => HookStatus::ErrorMemoryAlloc,
+			HookError::MemoryProtect => HookStatus::ErrorMemoryProtect,
+			HookError::Truncated | HookError::Unknown { .. } | HookError::TooShort
+				| HookError::Unrelocatable { .. } | HookError::OutOfRange { .. } => HookStatus::ErrorUnsupportedFunction,
+		}
+	}
+
+	/// Returns the trampoline that calls the original `target`
+	pub fn create(&mut self, target: *const c_void, detour: *const c_void, source: RegistrationSource) -> Result<*const c_void, HookStatus> {
+		if target.is_null() || detour.is_null() {
+			return Err(HookStatus::ErrorNotExecutable)
+		}
+		if self.hooks.contains_key(&(target as usize)) {
+			return Err(HookStatus::ErrorAlreadyCreated)
+		}
+		let hook = unsafe { Hook::new(target, detour) }
+			.map_err(|e| {
+				warn!("{source} cannot hook {target:?}: {e}");
+				Self::status(e)
+			})?;
+		let trampoline = hook.trampoline();
+		self.hooks.insert(target as usize, MinHook {
+			source,
+			hook,
+		});
+		Ok(trampoline)
+	}
+
+	pub fn remove(&mut self, target: *const c_void) -> Result<(), HookStatus> {
+		let mut hook = self.hooks.remove(&(target as usize))
+			.ok_or(HookStatus::ErrorNotCreated)?;
+		if let Err(e) = Self::set_enabled_all(&mut [&mut hook], false) {
+			// keep it around rather than free a trampoline that's still patched in
+			self.hooks.insert(target as usize, hook);
+			return Err(Self::status(e))
+		}
+		Ok(())
+	}
+
+	/// Toggle the hook on `target`, or every hook if there isn't one
+	pub fn set_enabled(&mut self, target: Option<*const c_void>, enabled: bool) -> Result<(), HookStatus> {
+		let target = match target {
+			Some(target) => target,
+			None => {
+				let mut hooks: Vec<_> = self.hooks.values_mut().collect();
+				return Self::set_enabled_all(&mut hooks, enabled)
+					.map_err(Self::status)
+			},
+		};
+		let hook = self.hooks.get_mut(&(target as usize))
+			.ok_or(HookStatus::ErrorNotCreated)?;
+		match (hook.hook.is_enabled(), enabled) {
+			(true, true) => Err(HookStatus::ErrorEnabled),
+			(false, false) => Err(HookStatus::ErrorDisabled),
+			_ => Self::set_enabled_all(&mut [hook], enabled)
+				.map_err(Self::status),
+		}
+	}
+
+	fn set_enabled_all(hooks: &mut [&mut MinHook], enabled: bool) -> Result<(), HookError> {
+		let mut hooks: Vec<&mut Hook> = hooks.iter_mut()
+			.map(|hook| &mut hook.hook)
+			.collect();
+		unsafe {
+			Hook::set_enabled(&mut hooks, enabled)
+		}
+	}
+
+	fn disable_all<'h, I: IntoIterator<Item = &'h mut MinHook>>(hooks: I) {
+		let mut hooks: Vec<_> = hooks.into_iter().collect();
+		if let Err(_e) = Self::set_enabled_all(&mut hooks, false) {
+			warn!("failed to disable hooks: {_e}");
+		}
+	}
+
+	/// Unhook everything an unloading addon left patched
+	pub fn release(source: RegistrationSource) -> usize {
+		let released = {
+			let mut hooks = Self::lock();
+			Self::disable_all(hooks.hooks.values_mut().filter(|hook| hook.source == source));
+			let mut released = Vec::new();
+			hooks.hooks.retain(|&target, hook| match hook.source == source && !hook.hook.is_enabled() {
+				true => {
+					released.push(target);
+					false
+				},
+				false => true,
+			});
+			released
+		};
+		for _target in &released {
+			warn!("{source} never removed its hook on {_target:#x}");
+		}
+		released.len()
+	}
+}
 
 impl NexusHost {
 	pub unsafe extern "stdcall-unwind" fn addonapi_min_hook_create(target: *const c_void, detour: *const c_void, trampoline: *mut *const c_void) -> HookStatus {
-		addonapi_stub!(min_hook::create("{:?}, {:?}, {:?}", target, detour, trampoline) => HookStatus::ErrorUnsupportedFunction)
+		addonapi_stub!(min_hook::create("{:?}, {:?}, {:?}", target, detour, trampoline));
+
+		let source = RegistrationSource::of_ptr(detour as *const ());
+		match MinHooks::lock().create(target, detour, source) {
+			Ok(original) => {
+				if let Some(trampoline) = trampoline.as_mut() {
+					*trampoline = original;
+				}
+				HookStatus::Ok
+			},
+			Err(status) => status,
+		}
 	}
 
 	pub unsafe extern "stdcall-unwind" fn addonapi_min_hook_remove(target: *const c_void) -> HookStatus {
-		addonapi_stub!(min_hook::remove("{:?}", target) => HookStatus::ErrorUnsupportedFunction)
+		addonapi_stub!(min_hook::remove("{:?}", target));
+
+		match MinHooks::lock().remove(target) {
+			Ok(()) => HookStatus::Ok,
+			Err(status) => status,
+		}
 	}
 
 	pub unsafe extern "stdcall-unwind" fn addonapi_min_hook_enable(target: *const c_void) -> HookStatus {
-		addonapi_stub!(min_hook::enable("{:?}", target) => HookStatus::ErrorUnsupportedFunction)
+		addonapi_stub!(min_hook::enable("{:?}", target));
+
+		let target = Some(target).filter(|target| !target.is_null());
+		match MinHooks::lock().set_enabled(target, true) {
+			Ok(()) => HookStatus::Ok,
+			Err(status) => status,
+		}
 	}
 
 	pub unsafe extern "stdcall-unwind" fn addonapi_min_hook_disable(target: *const c_void) -> HookStatus {
-		addonapi_stub!(min_hook::disable("{:?}", target) => HookStatus::ErrorUnsupportedFunction)
+		addonapi_stub!(min_hook::disable("{:?}", target));
+
+		let target = Some(target).filter(|target| !target.is_null());
+		match MinHooks::lock().set_enabled(target, false) {
+			Ok(()) => HookStatus::Ok,
+			Err(status) => status,
+		}
 	}
 }
@@ -1,15 +1,21 @@
-use std::{collections::BTreeMap, ffi::{c_void, CStr}, sync::{Arc, LazyLock, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError}};
+use std::{cell::Cell, collections::BTreeMap, ffi::{c_void, CStr}, fmt, mem, num::NonZeroU32, path::{Path, PathBuf}, ptr, sync::{Arc, LazyLock, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError}};
 use nexus::{gui::RenderType, imgui::Ui};
-use windows::{core::Owned, Win32::Foundation::{ERROR_NOT_FOUND, HMODULE}};
+use windows::{core::Owned, Win32::{Foundation::{ERROR_ALREADY_EXISTS, ERROR_NOT_FOUND, HMODULE}, System::Diagnostics::Debug::RtlCaptureStackBackTrace}};
+use windows_strings::HSTRING;
 
 use crate::{
 	host::addonapi::{
 		data_link::{MumbleIdentity, MumbleLinkProvider, NexusLinkProvider},
+		input::InputBinds,
 		texture::TextureCache,
 		quick_access::{ui::QuickAccessMenuUi, QuickAccessMenu},
-		NexusAddon, NexusAddonCache
+		module_index::ModuleRange,
+		AddonApiV, CoresidentNexus, GameBindWorker, GameBinds, Localization, MinHooks, ModuleIndex, Updates, ModuleOwner, NexusAddon, NexusAddonCache, NexusGuest,
 	},
-	util::{nexus::NexusId, win::{get_module_from_ptr, WinError, WinResult}},
+	extensions::{original_module_path, AddonLoader, LoadJournal, Loader, LoaderCommand, ReloadAddon},
+	settings::{settings_key, ExtCache, Settings},
+	supervisor::{CollisionKind, ExtCollision, SafeMode, Supervisor, SUPERVISOR},
+	util::{nexus::{AddonApiVersion, NexusId}, win::{get_module_from_ptr, get_module_path, retain_library, WinError, WinResult}},
 };
 
 pub static NEXUS_HOST: RwLock<NexusHost> = RwLock::new(NexusHost::empty());
@@ -18,6 +24,17 @@ pub struct NexusHost {
 	pub addons: BTreeMap<NexusId, Arc<NexusAddon>>,
 	pub fallback_cache: LazyLock<Arc<RwLock<NexusAddonCache>>>,
 	pub mumble_identity: Option<MumbleIdentity>,
+	pub autoload_pending: bool,
+	/// Rebuilt addons waiting to be reloaded, see [Settings::dev_mode]
+	pub dev_reload: Vec<PathBuf>,
+	/// Addons hosted by a previous arcloader instance, see [ReloadState](crate::extensions::ReloadState)
+	pub restore: Vec<ReloadAddon>,
+	/// arcdps extensions using the AddonAPI
+	pub guests: BTreeMap<RegistrationSource, NexusGuest>,
+	/// Every module whose callbacks we might be handed
+	pub modules: ModuleIndex,
+	/// Handed out by [arcloader_get_addon_api](super::guest::arcloader_get_addon_api), so must stay put
+	pub guest_apis: BTreeMap<AddonApiVersion, Box<AddonApiV>>,
 }
 
 impl NexusHost {
@@ -26,6 +43,12 @@ impl NexusHost {
 			addons: BTreeMap::new(),
 			fallback_cache: LazyLock::new(|| Default::default()),
 			mumble_identity: None,
+			autoload_pending: false,
+			dev_reload: Vec::new(),
+			restore: Vec::new(),
+			guests: BTreeMap::new(),
+			modules: ModuleIndex::new(),
+			guest_apis: BTreeMap::new(),
 		}
 	}
 
@@ -37,6 +60,20 @@ impl NexusHost {
 		})
 		}
 
+		match Loader::self_module() {
+			Ok(module) => {
+				let res = Self::lock_write().modules.insert_module(module, ModuleOwner::Host, RegistrationSource::of_module(module));
+				if let Err(_e) = res {
+					warn!("failed to index arcloader: {_e}");
+				}
+			},
+			Err(_e) => warn!("failed to find arcloader: {_e}"),
+		}
+
+		CoresidentNexus::init();
+		GameBinds::init();
+		Localization::init();
+		Updates::init();
 		MumbleLinkProvider::init();
 		MumbleIdentity::init();
 
@@ -49,11 +86,21 @@ impl NexusHost {
 		#[cfg(feature = "arcdps")] {
 			super::arcdps::ArcDpsCache::init();
 		}
+
+		Self::lock_write().autoload_pending = true;
 	}
 
 	pub fn unload() {
 		let mut host = Self::lock_write();
 		host.shutdown();
+		drop(host);
+
+		MinHooks::unload();
+		GameBindWorker::unload();
+		GameBinds::unload();
+		Localization::unload();
+		Updates::unload();
+		CoresidentNexus::unload();
 	}
 
 	pub fn lock_read() -> RwLockReadGuard<'static, Self> {
@@ -80,6 +127,10 @@ impl NexusHost {
 		TextureCache::texture_uploads();
 		NexusLinkProvider::imgui_present(not_charsel_or_loading);
 
+		Self::autoload_update();
+		Self::dev_reload_update();
+		Self::update_hotload();
+
 		MumbleIdentity::try_update();
 
 		// TODO: have it register a render callback instead
@@ -95,8 +146,10 @@ impl NexusHost {
 			let host = Self::lock_read();
 			host.addons.values()
 				.filter(|a| a.is_loaded())
-				.flat_map(|addon| {
-					NexusAddonCache::lock_read(&addon.cache).renderers.get(&ty).cloned().into_iter().flatten()
+				.map(|addon| &addon.cache)
+				.chain(host.guests.values().map(|guest| &guest.cache))
+				.flat_map(|cache| {
+					NexusAddonCache::lock_read(cache).renderers.get(&ty).cloned().into_iter().flatten()
 				})
 				.collect()
 		};
@@ -121,24 +174,192 @@ impl NexusHost {
 		}
 
 		// TODO: keep non-hotpluggable ones alive?
-		self.addons.clear();
+		for (_, addon) in mem::take(&mut self.addons) {
+			Self::release_registrations(RegistrationSource::of_module(addon.module()), &addon.cache, &self.fallback_cache);
+		}
+		for (source, guest) in mem::take(&mut self.guests) {
+			Self::release_registrations(source, &guest.cache, &self.fallback_cache);
+		}
+		self.modules.clear();
 	}
 
-	pub fn enumerate_addon(module: Owned<HMODULE>) -> WinResult<NexusId> {
+	pub fn autoload_update() {
+		// nothing to autoload until the supervisor has looked
+		if !Supervisor::external_scanned() {
+			return
+		}
+		let restore = match NEXUS_HOST.try_write() {
+			Ok(mut host) if host.autoload_pending => {
+				host.autoload_pending = false;
+				mem::take(&mut host.restore)
+			},
+			_ => return,
+		};
+
+		let restored: Vec<_> = restore.iter()
+			.filter_map(|addon| settings_key(&addon.path))
+			.collect();
+		let safe_mode = SafeMode::is_active();
+		for addon in restore {
+			info!("restoring {}", addon.path.display());
+			let res = match addon.loaded && !safe_mode {
+				true => Self::autoload_path(&addon.path),
+				false => Loader::load_library(&HSTRING::from(addon.path.as_path()))
+					.and_then(Self::enumerate_addon)
+					.map(drop),
+			};
+			if let Err(_e) = res {
+				error!("failed to restore {}: {_e}", addon.path.display());
+			}
+		}
+
+		let paths = match SUPERVISOR.read() {
+			Ok(sv) => sv.autoload_paths(AddonLoader::NexusHost),
+			Err(_e) => return,
+		};
+
+		for path in paths {
+			if settings_key(&path).map(|key| restored.contains(&key)).unwrap_or(false) {
+				continue
+			}
+			if Self::managed_by_nexus(&path) {
+				debug!("leaving {} to Nexus", path.display());
+				continue
+			}
+			info!("autoloading {}", path.display());
+			let res = Self::autoload_path(&path);
+			if let Err(_e) = res {
+				error!("failed to autoload {}: {_e}", path.display());
+			}
+		}
+	}
+
+	pub fn queue_dev_reload(path: PathBuf) {
 		let mut host = Self::lock_write();
-		let addon = Arc::new(NexusAddon::with_module(&host, module)?);
+		if !host.dev_reload.contains(&path) {
+			host.dev_reload.push(path);
+		}
+	}
+
+	pub fn dev_reload_update() {
+		let paths = match NEXUS_HOST.try_write() {
+			Ok(mut host) if !host.dev_reload.is_empty() => mem::take(&mut host.dev_reload),
+			_ => return,
+		};
+
+		for path in paths {
+			let key = settings_key(&path);
+			let addon = Self::lock_read().addons.values()
+				.find(|addon| original_module_path(addon.module()).ok()
+					.and_then(|p| settings_key(&p)) == key
+				).cloned();
+			let addon = match addon {
+				Some(addon) => addon,
+				// not loaded, so nothing to reload
+				None => continue,
+			};
+			if !addon.can_hotload() {
+				info!("{addon} changed on disk, but can't be hot-reloaded");
+				continue
+			}
+
+			info!("{addon} changed on disk, reloading...");
+			// the loader worker waits for the old module to go away before loading it again
+			match retain_library(addon.module()) {
+				Ok(module) => {
+					let _id = Loader::queue_command(LoaderCommand::Reload { module });
+				},
+				Err(_e) => error!("failed to reload {}: {_e}", path.display()),
+			}
+		}
+	}
+
+	pub fn autoload_path(path: &Path) -> WinResult<()> {
+		let module = Loader::load_library(&HSTRING::from(path))?;
+		let sig = Self::enumerate_addon(module)?;
+		Self::load_addon(sig)
+	}
+
+	pub fn enumerate_addon(module: Owned<HMODULE>) -> WinResult<NexusId> {
+		if let Ok(path) = original_module_path(*module) {
+			if Self::managed_by_nexus(&path) {
+				return Err(WinError::new(ERROR_ALREADY_EXISTS.to_hresult(), format!("{} is managed by Nexus", path.display())))
+			}
+		}
+
+		let addon = Arc::new(NexusAddon::with_module(&Self::lock_read(), module)?);
 		let sig = addon.signature;
-		host.addons.insert(sig, addon.clone());
+		if let Some(collision) = Self::collision(&addon) {
+			let err = collision.to_error();
+			Supervisor::record_collision(collision);
+			// dropping the addon lets go of the module again
+			return Err(err)
+		}
+
+		let ranges = ModuleIndex::addon_ranges(&addon);
+		{
+			let mut host = Self::lock_write();
+			host.addons.insert(sig, addon.clone());
+			host.modules.insert_new(ranges);
+		}
+
+		Self::update_settings_cache(&addon);
+
 		Ok(sig)
 	}
 
+	/// Refuse an addon that claims a signature that's already taken,
+	/// or whose DLL is already loaded into arcdps
+	fn collision(addon: &NexusAddon) -> Option<ExtCollision> {
+		let path = original_module_path(addon.module()).ok();
+		let existing = Self::lock_read().addons.get(&addon.signature)
+			.filter(|existing| existing.module() != addon.module())
+			.map(|existing| (CollisionKind::Signature(addon.signature as u32), existing.to_string()));
+		let (kind, existing) = existing.or_else(|| SUPERVISOR.read().ok()?
+			.arcdps_collision(addon.signature as u32, path.as_deref())
+		)?;
+
+		Some(ExtCollision {
+			kind,
+			existing,
+			rejected: path.unwrap_or_default(),
+			rejected_loader: AddonLoader::NexusHost,
+		})
+	}
+
+	fn update_settings_cache(addon: &NexusAddon) {
+		let path = match original_module_path(addon.module()) {
+			Ok(p) => p,
+			Err(_e) => {
+				warn!("no path found for {addon}: {_e}");
+				return
+			},
+		};
+		let path = path.as_path();
+		let key = match settings_key(path) {
+			Some(key) => key,
+			None => return,
+		};
+		let cache = ExtCache {
+			sig: NonZeroU32::new(addon.signature as u32),
+			name: addon.name().to_string_lossy().into_owned(),
+			version: Some(addon.version().to_string()),
+			path: Some(path.to_owned()),
+		};
+		Settings::update_with(|settings| settings.nexus.update_cache(&key, cache));
+	}
+
 	pub fn load_addon(sig: i32) -> WinResult<()> {
 		let addon = {
 			let host = Self::lock_read();
 			host.addons.get(&sig).cloned()
 		}.ok_or_else(|| WinError::new(ERROR_NOT_FOUND.to_hresult(), "addon not enumerated"))?;
 
-		let res = addon.load();
+		let res = {
+			let _attempt = original_module_path(addon.module()).ok()
+				.map(|path| LoadJournal::attempt(AddonLoader::NexusHost, &path));
+			addon.load()
+		};
 
 		if let Err(_e) = &res {
 			error!("{addon} failed to load: {_e}");
@@ -146,6 +367,7 @@ impl NexusHost {
 			Self::lock_write().addons.remove(&addon.signature);
 		} else {
 			Self::event_broadcast(Self::EV_ADDON_LOADED, &sig as *const _ as *const _);
+			Self::update_check(&addon);
 		}
 
 		res
@@ -156,9 +378,12 @@ impl NexusHost {
 			let host = Self::lock_read();
 			let x = NexusAddonCache::lock_read(&Self::fallback_cache()).event_handlers.get(key).cloned()
 				.into_iter().flatten()
-				.chain(host.addons.values().flat_map(|a| NexusAddonCache::lock_read(&a.cache)
-					.event_handlers.get(key).cloned().into_iter().flatten()
-				)).collect();
+				.chain(host.addons.values().map(|a| &a.cache)
+					.chain(host.guests.values().map(|g| &g.cache))
+					.flat_map(|cache| NexusAddonCache::lock_read(cache)
+						.event_handlers.get(key).cloned().into_iter().flatten()
+					)
+				).collect();
 			x
 		};
 		for cb in interest {
@@ -178,25 +403,61 @@ impl NexusHost {
 			error!("{addon} failed to unload: {_e}");
 		}
 
-		Self::lock_write().addons.remove(&addon.signature);
+		// the module is only freed once the last reference to the addon drops
+		let source = RegistrationSource::of_module(addon.module());
+		let fallback = Self::lock_read().fallback_cache.clone();
+		Self::release_registrations(source, &addon.cache, &fallback);
+
+		{
+			let mut host = Self::lock_write();
+			host.addons.remove(&addon.signature);
+			host.modules.remove(ModuleOwner::Addon(addon.signature), source);
+		}
 
 		Self::event_broadcast(Self::EV_ADDON_UNLOADED, &sig as *const _ as *const c_void);
 
 		Ok(())
 	}
 
+	/// Purge everything `source` registered, so nothing calls into it once it's gone
+	///
+	/// `cache` is the source's own. `fallback` is passed in separately,
+	/// since [NexusHost::shutdown] runs with the host already locked.
+	/// Returns how many registrations it left behind.
+	pub fn release_registrations(source: RegistrationSource, cache: &RwLock<NexusAddonCache>, fallback: &RwLock<NexusAddonCache>) -> usize {
+		if !source.is_known() {
+			return 0
+		}
+
+		let mut leaked = NexusAddonCache::lock_write(cache).release_all(source);
+		leaked += NexusAddonCache::lock_write(fallback).release(source);
+		leaked += InputBinds::lock_write().release(source);
+		leaked += Self::wndproc_release(source);
+		leaked += QuickAccessMenu::release(source);
+		leaked += TextureCache::release(source);
+		leaked += MinHooks::release(source);
+		// translations are meant to outlive their setup, so aren't leaks
+		let _released = Localization::lock_write().release(source);
+		if _released > 0 {
+			debug!("{source} took {_released} translations with it");
+		}
+
+		if leaked > 0 {
+			warn!("{source} left {leaked} registrations behind");
+		}
+		leaked
+	}
+
 	pub fn fallback_cache() -> &'static Arc<RwLock<NexusAddonCache>> {
 		static CACHE: LazyLock<Arc<RwLock<NexusAddonCache>>> = LazyLock::new(|| NexusHost::lock_read().fallback_cache.clone());
 		&*CACHE
 	}
 
 	pub fn addon_for_ptr(&self, p: *const ()) -> Option<&Arc<NexusAddon>> {
-		if p.is_null() {
-			return None
-		}
-		let res = get_module_from_ptr(p as *const _)
-			.ok().flatten()
-			.and_then(|module| self.addons.values().find(|a| a.module() == module));
+		let res = match self.module_for_ptr(p) {
+			Some(&ModuleRange { owner: ModuleOwner::Addon(sig), .. }) => self.addons.get(&sig),
+			_ => None,
+		};
 		if res.is_none() {
 			debug!("addon cache lookup failed");
 		}
@@ -206,7 +467,10 @@ impl NexusHost {
 	pub fn cache_for(&self, p: *const ()) -> &Arc<RwLock<NexusAddonCache>> {
 		match self.addon_for_ptr(p) {
 			Some(addon) => &addon.cache,
-			None => &self.fallback_cache,
+			None => match self.guest_for_ptr(p) {
+				Some(guest) => &guest.cache,
+				None => &self.fallback_cache,
+			},
 		}
 	}
 
@@ -237,7 +501,8 @@ impl NexusHost {
 
 	pub fn cache_write_with<R, F: FnOnce(RwLockWriteGuard<NexusAddonCache>) -> R>(p: *const (), f: F) -> R {
 		let cache = {
-			let host = Self::lock_write();
+			let mut host = Self::lock_write();
+			host.guest_register(p);
 			let cache = host.cache_for(p);
 
 			let write_lock = match cache.try_write() {
@@ -254,3 +519,98 @@ impl NexusHost {
 		f(NexusAddonCache::lock_write(&cache))
 	}
 }
+
+/// The module a registration came from, so it can be purged when that module unloads
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RegistrationSource {
+	module: usize,
+}
+
+thread_local! {
+	static LOADING_SOURCE: Cell<RegistrationSource> = const { Cell::new(RegistrationSource::UNKNOWN) };
+}
+
+impl RegistrationSource {
+	pub const UNKNOWN: Self = Self { module: 0 };
+
+	pub fn of_module(module: HMODULE) -> Self {
+		Self {
+			module: module.0 as usize,
+		}
+	}
+
+	/// Whichever module `p` points into
+	///
+	/// Callbacks and string literals live in the module that registered them.
+	/// Anything on the heap is attributed to whichever addon is being loaded,
+	/// or failing that, whoever called into the API.
+	pub fn of_ptr(p: *const ()) -> Self {
+		Self::of_ptr_opt(p)
+			.or_else(|| Some(Self::loading()).filter(Self::is_known))
+			.unwrap_or_else(Self::caller)
+	}
+
+	/// The nearest module up the stack that isn't arcloader itself
+	pub fn caller() -> Self {
+		const MAX_FRAMES: usize = 16;
+
+		let mut frames = [ptr::null_mut::<c_void>(); MAX_FRAMES];
+		let captured = unsafe { RtlCaptureStackBackTrace(1, &mut frames, None) } as usize;
+		let own = Loader::self_module().ok();
+		frames[..captured].iter()
+			.filter_map(|&p| get_module_from_ptr(p as *const _).ok().flatten())
+			.find(|&module| Some(module) != own)
+			.and_then(|module| Self::of_ptr_opt(module.0 as *const ()))
+			.unwrap_or(Self::UNKNOWN)
+	}
+
+	fn of_ptr_opt(p: *const ()) -> Option<Self> {
+		if p.is_null() {
+			return None
+		}
+		// the host may well be locked already, in which case ask the loader instead
+		let indexed = NEXUS_HOST.try_read().ok()
+			.and_then(|host| host.module_for_ptr(p).map(|m| m.source));
+		match indexed {
+			Some(source) => Some(source),
+			None => get_module_from_ptr(p as *const _).ok().flatten()
+				.map(Self::of_module),
+		}
+	}
+
+	/// The addon whose `load` is currently running on this thread
+	pub fn loading() -> Self {
+		LOADING_SOURCE.with(|source| source.get())
+	}
+
+	pub fn with_loading<R, F: FnOnce() -> R>(module: HMODULE, f: F) -> R {
+		let prev = LOADING_SOURCE.with(|source| source.replace(Self::of_module(module)));
+		let res = f();
+		LOADING_SOURCE.with(|source| source.set(prev));
+		res
+	}
+
+	pub fn is_known(&self) -> bool {
+		*self != Self::UNKNOWN
+	}
+
+	/// Whether `p` points into this module
+	pub fn owns(&self, p: *const ()) -> bool {
+		self.is_known() && Self::of_ptr_opt(p) == Some(*self)
+	}
+}
+
+impl fmt::Display for RegistrationSource {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self.is_known() {
+			true => {
+				let path = get_module_path(Some(HMODULE(self.module as *mut _))).ok();
+				match path.as_ref().and_then(|path| Path::new(path).file_name()) {
+					Some(name) => write!(f, "{}", name.to_string_lossy()),
+					None => write!(f, "{:#x}", self.module),
+				}
+			},
+			false => f.write_str("(unknown)"),
+		}
+	}
+}
@@ -1,7 +1,7 @@
 use nexus::keybind::{Keybind as NexusKeybind, RawKeybindHandler, RawKeybindHandlerOld};
 use windows::Win32::Foundation::ERROR_KEY_DOES_NOT_EXIST;
 use crate::{
-	host::addonapi::NexusHost,
+	host::addonapi::{NexusHost, RegistrationSource},
 	util::{ffi::cstr_opt, nexus::{InputCode, Keybind, NexusId}, win::{WinError, WinResult}},
 };
 use std::{collections::BTreeMap, ffi::{c_char, CStr, CString}, sync::{RwLock, RwLockReadGuard, RwLockWriteGuard}};
@@ -9,6 +9,8 @@ use std::{collections::BTreeMap, ffi::{c_char, CStr, CString}, sync::{RwLock, Rw
 pub struct InputBinds {
 	/// TODO: a plain set plus cache rebuilt whenever binds changes would be better tbh...
 	pub binds: BTreeMap<Option<InputCode>, Vec<InputRegistration>>,
+	/// Binds to use instead of whatever an addon asks for, by id
+	pub overrides: BTreeMap<CString, Keybind>,
 }
 
 pub static INPUT_BINDS: RwLock<InputBinds> = RwLock::new(InputBinds::new());
@@ -17,12 +19,16 @@ impl InputBinds {
 	pub const fn new() -> Self {
 		Self {
 			binds: BTreeMap::new(),
+			overrides: BTreeMap::new(),
 		}
 	}
 
 	pub fn register<I>(&mut self, id: I, callback: RawKeybindHandler, bind: Option<Keybind>) -> WinResult<()> where
 		I: Into<CString>,
 	{
+		let id = id.into();
+		let bind = self.overrides.get(&id).copied()
+			.or(bind);
 		let (bind, code) = match bind {
 			None => (Keybind::default(), None),
 			Some(bind) => {
@@ -32,9 +38,10 @@ impl InputBinds {
 			},
 		};
 		let registration = InputRegistration {
-			id: id.into(),
+			id,
 			callback,
 			bind,
+			source: RegistrationSource::of_ptr(callback as *const ()),
 		};
 		let binds = self.binds.entry(code)
 			.or_insert(Default::default());
@@ -54,12 +61,27 @@ impl InputBinds {
 	}
 
 	pub fn binds_for_addon<'i>(&'i self, host: &'i NexusHost, sig: NexusId) -> impl Iterator<Item = &'i InputRegistration> + 'i {
+		let source = host.addons.get(&sig)
+			.map(|addon| RegistrationSource::of_module(addon.module()));
 		self.binds.values()
 			.flatten()
-			.filter(move |reg| host.addon_for_ptr(reg.callback as *const _)
-				.map(|addon| addon.signature == sig)
-				.unwrap_or(false)
-			)
+			.filter(move |reg| Some(reg.source) == source)
+	}
+
+	pub fn release(&mut self, source: RegistrationSource) -> usize {
+		let mut leaked = 0;
+		for regs in self.binds.values_mut() {
+			regs.retain(|reg| match reg.source == source {
+				true => {
+					warn!("{source} never deregistered keybind {:?}", reg.id);
+					leaked += 1;
+					false
+				},
+				false => true,
+			});
+		}
+		self.binds.retain(|_, regs| !regs.is_empty());
+		leaked
 	}
 
 	pub fn find_id<'i>(&'i self, id: &CStr) -> Option<&'i InputRegistration> {
@@ -74,6 +96,7 @@ pub struct InputRegistration {
 	pub id: CString,
 	pub bind: Keybind,
 	pub callback: RawKeybindHandler,
+	pub source: RegistrationSource,
 }
 
 #[cfg(todo)]
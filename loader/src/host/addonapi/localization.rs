@@ -1,25 +1,325 @@
 use crate::{
-	host::addonapi::NexusHost,
+	host::addonapi::{NexusAddon, NexusHost, RegistrationSource},
+	settings::Settings,
 	util::ffi::cstr_opt,
 };
-use std::ffi::c_char;
+use std::{collections::BTreeMap, ffi::{c_char, CStr, CString}, fs, io, mem, path::{Path, PathBuf}, sync::{RwLock, RwLockReadGuard, RwLockWriteGuard}};
+
+static LOCALIZATION: RwLock<Localization> = RwLock::new(Localization::new());
+
+/// Translated strings for the AddonAPI, keyed by language and then identifier
+///
+/// Strings are never freed while the host is loaded, so the pointers handed out stay valid
+/// even after they're replaced or their owner unloads.
+#[derive(Debug, Default)]
+pub struct Localization {
+	/// See [Settings::language]
+	language: Option<String>,
+	strings: BTreeMap<String, BTreeMap<CString, LocalizedString>>,
+	/// Replaced and released strings, which someone might still be pointing at
+	retired: Vec<CString>,
+}
+
+#[derive(Debug)]
+pub struct LocalizedString {
+	pub source: RegistrationSource,
+	pub string: CString,
+}
+
+impl Localization {
+	pub const DEFAULT_LANGUAGE: &'static str = "en";
+	/// Where an addon's own directory keeps its translations, one `<language>.json` each
+	pub const LOCALES_DIR: &'static str = "locales";
+
+	pub const fn new() -> Self {
+		Self {
+			language: None,
+			strings: BTreeMap::new(),
+			retired: Vec::new(),
+		}
+	}
+
+	pub fn lock_read() -> RwLockReadGuard<'static, Self> {
+		LOCALIZATION.read()
+			.unwrap_or_else(|e| e.into_inner())
+	}
+
+	pub fn lock_write() -> RwLockWriteGuard<'static, Self> {
+		LOCALIZATION.write()
+			.unwrap_or_else(|e| e.into_inner())
+	}
+
+	pub fn init() {
+		let language = Settings::lock_read().language.clone();
+		Self::lock_write().language = language.as_deref().map(Self::normalize_language);
+	}
+
+	pub fn unload() {
+		*Self::lock_write() = Self::new();
+	}
+
+	/// Language identifiers are compared case-insensitively
+	pub fn normalize_language(language: &str) -> String {
+		language.trim().to_ascii_lowercase()
+	}
+
+	pub fn language(&self) -> &str {
+		self.language.as_deref().unwrap_or(Self::DEFAULT_LANGUAGE)
+	}
+
+	/// Switch languages, remembering the choice in [Settings]
+	pub fn set_language(language: Option<&str>) {
+		let language = language.map(Self::normalize_language)
+			.filter(|l| !l.is_empty() && l != Self::DEFAULT_LANGUAGE);
+		Self::lock_write().language = language.clone();
+		Settings::update_with(|settings| match settings.language != language {
+			true => {
+				settings.language = language;
+				true
+			},
+			false => false,
+		});
+	}
+
+	/// Every language something has been translated into
+	pub fn languages(&self) -> impl Iterator<Item = &str> {
+		self.strings.keys().map(|l| &l[..])
+	}
+
+	pub fn get(&self, language: &str, identifier: &CStr) -> Option<&CStr> {
+		self.strings.get(language)?
+			.get(identifier)
+			.map(|s| s.string.as_c_str())
+	}
+
+	/// `identifier` in the current language, or English if it hasn't been translated
+	pub fn translate(&self, identifier: &CStr) -> Option<&CStr> {
+		self.translate_to(self.language(), identifier)
+	}
+
+	/// `identifier` in `language`, or English if it hasn't been translated
+	pub fn translate_to(&self, language: &str, identifier: &CStr) -> Option<&CStr> {
+		self.get(language, identifier)
+			.or_else(|| self.get(Self::DEFAULT_LANGUAGE, identifier))
+	}
+
+	pub fn set(&mut self, language: &str, identifier: CString, string: CString, source: RegistrationSource) {
+		let strings = self.strings.entry(Self::normalize_language(language))
+			.or_default();
+		match strings.get_mut(&identifier) {
+			// keep whatever pointers were already handed out
+			Some(existing) if existing.string == string => {
+				existing.source = source;
+			},
+			Some(existing) => {
+				let replaced = mem::replace(&mut existing.string, string);
+				existing.source = source;
+				self.retired.push(replaced);
+			},
+			None => {
+				strings.insert(identifier, LocalizedString {
+					source,
+					string,
+				});
+			},
+		}
+	}
+
+	/// Forget everything an unloading addon brought along
+	pub fn release(&mut self, source: RegistrationSource) -> usize {
+		let mut released = 0;
+		let retired = &mut self.retired;
+		for strings in self.strings.values_mut() {
+			let count = strings.len();
+			strings.retain(|_, s| match s.source == source {
+				true => {
+					retired.push(mem::take(&mut s.string));
+					false
+				},
+				false => true,
+			});
+			released += count - strings.len();
+		}
+		self.strings.retain(|_, strings| !strings.is_empty());
+		released
+	}
+
+	pub fn addon_locales_dir(addon: &NexusAddon) -> PathBuf {
+		NexusHost::addons_dir()
+			.join(&addon.name().to_string_lossy()[..])
+			.join(Self::LOCALES_DIR)
+	}
+
+	/// Pick up the translations an addon ships with, before it gets a chance to [set](Self::set) its own
+	pub fn load_addon(addon: &NexusAddon) {
+		let dir = Self::addon_locales_dir(addon);
+		let source = RegistrationSource::of_module(addon.module());
+		match Self::read_dir(&dir) {
+			Ok(locales) if locales.is_empty() => (),
+			Ok(locales) => {
+				let mut localization = Self::lock_write();
+				for (language, strings) in locales {
+					debug!("{addon} has {} strings for {language}", strings.len());
+					for (identifier, string) in strings {
+						localization.set(&language, identifier, string, source);
+					}
+				}
+			},
+			Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+			Err(_e) => warn!("failed to read {addon} translations from {}: {_e}", dir.display()),
+		}
+	}
+
+	/// Read every `<language>.json` in `dir`
+	pub fn read_dir(dir: &Path) -> io::Result<Vec<(String, Vec<(CString, CString)>)>> {
+		let mut locales = Vec::new();
+		for entry in fs::read_dir(dir)? {
+			let path = entry?.path();
+			let language = match path.extension() {
+				Some(ext) if ext.eq_ignore_ascii_case("json") => path.file_stem()
+					.and_then(|stem| stem.to_str())
+					.map(Self::normalize_language),
+				_ => None,
+			};
+			let language = match language {
+				Some(language) => language,
+				None => continue,
+			};
+			match Self::read_file(&path) {
+				Ok(strings) => locales.push((language, strings)),
+				Err(_e) => warn!("skipping translations in {}: {_e}", path.display()),
+			}
+		}
+		Ok(locales)
+	}
+
+	pub fn read_file(path: &Path) -> io::Result<Vec<(CString, CString)>> {
+		let data = fs::read(path)?;
+		let strings: serde_json::Value = serde_json::from_slice(&data)?;
+		Ok(Self::parse_strings(&strings))
+	}
+
+	/// A flat object of identifiers to their translations
+	pub fn parse_strings(strings: &serde_json::Value) -> Vec<(CString, CString)> {
+		let strings = match strings.as_object() {
+			Some(strings) => strings,
+			None => return Vec::new(),
+		};
+		strings.iter()
+			.filter_map(|(identifier, string)| Some((
+				CString::new(&identifier[..]).ok()?,
+				CString::new(string.as_str()?).ok()?,
+			)))
+			.collect()
+	}
+}
 
 impl NexusHost {
 	pub unsafe extern "C-unwind" fn addonapi_localization_translate(identifier: *const c_char) -> *const c_char {
 		let id = cstr_opt(&identifier);
-		addonapi_stub!(localization::translate("{:?}", id) => identifier)
+		addonapi_stub!(localization::translate("{:?}", id));
+
+		let id = match id {
+			Some(id) => id,
+			None => return identifier,
+		};
+		Localization::lock_read().translate(id)
+			.map(|s| s.as_ptr())
+			.unwrap_or(identifier)
 	}
 
 	pub unsafe extern "C-unwind" fn addonapi_localization_translate_to(identifier: *const c_char, language_identifier: *const c_char) -> *const c_char {
 		let id = cstr_opt(&identifier);
 		let lang = cstr_opt(&language_identifier);
-		addonapi_stub!(localization::translate_to("{:?}, {:?}", id, lang) => identifier)
+		addonapi_stub!(localization::translate_to("{:?}, {:?}", id, lang));
+
+		let (id, lang) = match (id, lang) {
+			(Some(id), Some(lang)) => (id, Localization::normalize_language(&lang.to_string_lossy())),
+			(Some(id), None) => return Self::addonapi_localization_translate(id.as_ptr()),
+			(None, _) => return identifier,
+		};
+		Localization::lock_read().translate_to(&lang, id)
+			.map(|s| s.as_ptr())
+			.unwrap_or(identifier)
 	}
 
 	pub unsafe extern "C-unwind" fn addonapi_localization_set(identifier: *const c_char, language_identifier: *const c_char, string: *const c_char) {
 		let id = cstr_opt(&identifier);
 		let lang = cstr_opt(&language_identifier);
 		let string = cstr_opt(&string);
-		addonapi_stub!(localization::set("{:?}, {:?}, {:?}", id, lang, string) => ())
+		addonapi_stub!(localization::set("{:?}, {:?}, {:?}", id, lang, string));
+
+		let (id, lang, string) = match (id, lang, string) {
+			(Some(id), Some(lang), Some(string)) => (id, lang, string),
+			_ => {
+				warn!("localization::set missing its arguments");
+				return
+			},
+		};
+		let source = RegistrationSource::of_ptr(identifier as *const ());
+		Localization::lock_write().set(&lang.to_string_lossy(), id.to_owned(), string.to_owned(), source);
+	}
+}
+
+#[cfg(test)]
+fn test_source(module: usize) -> RegistrationSource {
+	RegistrationSource::of_module(windows::Win32::Foundation::HMODULE(module as *mut _))
+}
+
+#[test]
+fn localization_parse_strings() {
+	let strings = Localization::parse_strings(&serde_json::json!({
+		"greeting": "Hallo",
+		"farewell": "Tschüss",
+		"count": 3,
+		"nested": { "a": "b" },
+		"nul\0": "skipped",
+	}));
+	assert_eq!(strings, [
+		(c"farewell".to_owned(), CString::new("Tschüss").unwrap()),
+		(c"greeting".to_owned(), c"Hallo".to_owned()),
+	]);
+	assert!(Localization::parse_strings(&serde_json::json!(["greeting", "Hallo"])).is_empty());
+}
+
+#[test]
+fn localization_fallback() {
+	let source = test_source(0x1000);
+	let mut localization = Localization::new();
+	localization.set("en", c"greeting".to_owned(), c"Hello".to_owned(), source);
+	localization.set("en", c"farewell".to_owned(), c"Goodbye".to_owned(), source);
+	localization.set("DE", c"greeting".to_owned(), c"Hallo".to_owned(), source);
+
+	assert_eq!(localization.translate(c"greeting"), Some(c"Hello"));
+	localization.language = Some("de".into());
+	assert_eq!(localization.translate(c"greeting"), Some(c"Hallo"));
+	assert_eq!(localization.translate(c"farewell"), Some(c"Goodbye"));
+	assert_eq!(localization.translate(c"missing"), None);
+	assert_eq!(localization.translate_to("fr", c"greeting"), Some(c"Hello"));
+	assert_eq!(localization.languages().collect::<Vec<_>>(), ["de", "en"]);
+}
+
+#[test]
+fn localization_retired() {
+	let (first, second) = (test_source(0x1000), test_source(0x2000));
+	let mut localization = Localization::new();
+	localization.set("en", c"greeting".to_owned(), c"Hello".to_owned(), first);
+	localization.set("en", c"farewell".to_owned(), c"Goodbye".to_owned(), second);
+	let hello = localization.translate(c"greeting").unwrap().as_ptr();
+	let goodbye = localization.translate(c"farewell").unwrap().as_ptr();
+
+	// the same string again keeps the same pointer
+	localization.set("en", c"greeting".to_owned(), c"Hello".to_owned(), first);
+	assert_eq!(localization.translate(c"greeting").unwrap().as_ptr(), hello);
+
+	localization.set("en", c"greeting".to_owned(), c"Hi".to_owned(), first);
+	assert_eq!(localization.translate(c"greeting"), Some(c"Hi"));
+	assert_eq!(localization.release(second), 1);
+	assert_eq!(localization.translate(c"farewell"), None);
+
+	// but what was handed out earlier still reads the same
+	unsafe {
+		assert_eq!(CStr::from_ptr(hello), c"Hello");
+		assert_eq!(CStr::from_ptr(goodbye), c"Goodbye");
 	}
 }
@@ -5,12 +5,24 @@ use windows::{core::Error as WinError, Win32::Foundation::ERROR_API_UNAVAILABLE}
 
 pub mod host;
 pub mod addon;
+pub mod coresident;
+pub mod game_binds;
+pub mod guest;
+pub mod module_index;
 pub mod versioned;
 pub use self::{
 	versioned::AddonApiV,
-	host::{NexusHost, NEXUS_HOST},
+	host::{NexusHost, RegistrationSource, NEXUS_HOST},
+	coresident::CoresidentNexus,
+	game_binds::GameBinds,
+	binds::GameBindWorker,
+	hook::MinHooks,
+	guest::NexusGuest,
+	module_index::{ModuleIndex, ModuleOwner},
 	addon::{NexusAddon, NexusAddonCache},
-	data_link::MumbleIdentity,
+	data_link::{MumbleIdentity, SharedData},
+	localization::Localization,
+	update::Updates,
 	texture::TextureCache,
 };
 
@@ -188,7 +200,7 @@ macro_rules! addonapi_stub {
 
 mod log;
 mod path;
-mod update;
+pub mod update;
 mod event;
 mod wndproc;
 mod hook;
@@ -197,8 +209,8 @@ mod binds;
 pub mod data_link;
 mod font;
 mod texture;
-mod localization;
-mod quick_access;
+pub mod localization;
+pub mod quick_access;
 mod ui;
 mod render;
 #[cfg(feature = "arcdps")]
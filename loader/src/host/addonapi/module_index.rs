@@ -0,0 +1,246 @@
+use crate::{
+	extensions::original_module_path,
+	host::addonapi::{NexusAddon, NexusHost, RegistrationSource},
+	util::{nexus::NexusId, win::{get_module_from_name, get_module_path, get_module_range, pe::PeInfo, WinResult}},
+};
+use std::{ops::Range, path::{Path, PathBuf}};
+use windows::Win32::Foundation::HMODULE;
+use windows_strings::HSTRING;
+
+/// Who a module's callbacks should be attributed to
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ModuleOwner {
+	/// arcloader itself
+	Host,
+	Addon(NexusId),
+	/// An arcdps extension, see [NexusGuest](super::NexusGuest)
+	Guest,
+}
+
+/// Where a module's image sits in memory
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModuleRange {
+	pub range: Range<usize>,
+	pub owner: ModuleOwner,
+	/// The owner's main module, which may not be this one
+	pub source: RegistrationSource,
+}
+
+/// Loaded module images, sorted by address
+///
+/// Saves asking the loader which module a callback belongs to every time one is registered.
+#[derive(Clone, Debug, Default)]
+pub struct ModuleIndex {
+	ranges: Vec<ModuleRange>,
+}
+
+impl ModuleIndex {
+	pub const fn new() -> Self {
+		Self {
+			ranges: Vec::new(),
+		}
+	}
+
+	pub fn lookup(&self, p: usize) -> Option<&ModuleRange> {
+		let i = self.ranges.partition_point(|m| m.range.end <= p);
+		self.ranges.get(i)
+			.filter(|m| m.range.contains(&p))
+	}
+
+	pub fn insert(&mut self, entry: ModuleRange) {
+		if entry.range.is_empty() {
+			return
+		}
+		// a module can only be mapped once, so anything in the way must have gone stale
+		self.ranges.retain(|m| m.range.end <= entry.range.start || m.range.start >= entry.range.end);
+		let i = self.ranges.partition_point(|m| m.range.start < entry.range.start);
+		self.ranges.insert(i, entry);
+	}
+
+	pub fn insert_module(&mut self, module: HMODULE, owner: ModuleOwner, source: RegistrationSource) -> WinResult<()> {
+		let range = get_module_range(module)?;
+		self.insert(ModuleRange {
+			range,
+			owner,
+			source,
+		});
+		Ok(())
+	}
+
+	/// Forget every module belonging to `owner`
+	pub fn remove(&mut self, owner: ModuleOwner, source: RegistrationSource) {
+		self.ranges.retain(|m| m.owner != owner || m.source != source);
+	}
+
+	pub fn clear(&mut self) {
+		self.ranges.clear();
+	}
+
+	/// An addon's image, along with any helper DLLs it ships next to itself
+	///
+	/// Those are declared by way of its import table, and count as the addon's own.
+	/// Reads the addon from disk, so best done before taking any locks.
+	pub fn addon_ranges(addon: &NexusAddon) -> Vec<ModuleRange> {
+		let owner = ModuleOwner::Addon(addon.signature);
+		let source = RegistrationSource::of_module(addon.module());
+		let range = |module| get_module_range(module).map(|range| ModuleRange {
+			range,
+			owner,
+			source,
+		});
+
+		let mut ranges = match range(addon.module()) {
+			Ok(main) => vec![main],
+			Err(_e) => {
+				warn!("{addon} couldn't be indexed: {_e}");
+				return Vec::new()
+			},
+		};
+		for helper in Self::helper_modules(addon.module()) {
+			match range(helper) {
+				Ok(helper_range) => {
+					debug!("attributing {helper:?} to {addon}");
+					ranges.push(helper_range);
+				},
+				Err(_e) => debug!("{addon} helper {helper:?} couldn't be indexed: {_e}"),
+			}
+		}
+		ranges
+	}
+
+	fn helper_modules(module: HMODULE) -> Vec<HMODULE> {
+		let path = match get_module_path(Some(module)) {
+			Ok(path) => PathBuf::from(path),
+			Err(_e) => return Vec::new(),
+		};
+		// shadow copies load their helpers from wherever the original was
+		let original = original_module_path(module).ok();
+		let dirs: Vec<&Path> = [Some(path.as_path()), original.as_deref()].into_iter()
+			.flatten()
+			.filter_map(Path::parent)
+			.collect();
+		let imports = match PeInfo::read(&path) {
+			Ok(info) => info.imports,
+			Err(_e) => {
+				debug!("failed to read imports of {}: {_e}", path.display());
+				return Vec::new()
+			},
+		};
+
+		imports.iter()
+			.filter_map(|import| get_module_from_name(&HSTRING::from(&import.dll[..])).ok().flatten())
+			.filter(|&helper| helper != module)
+			.filter(|&helper| get_module_path(Some(helper)).ok()
+				.map(PathBuf::from)
+				.as_deref().and_then(Path::parent)
+				.map(|dir| dirs.contains(&dir))
+				.unwrap_or(false)
+			)
+			.collect()
+	}
+
+	/// Index `ranges`, unless something else has already claimed them
+	pub fn insert_new(&mut self, ranges: Vec<ModuleRange>) {
+		for (i, entry) in ranges.into_iter().enumerate() {
+			// only helpers can be shared, the main module always wins
+			if i > 0 && self.lookup(entry.range.start).is_some() {
+				continue
+			}
+			self.insert(entry);
+		}
+	}
+}
+
+impl NexusHost {
+	/// Find the owner of a pointer without asking the loader
+	pub fn module_for_ptr(&self, p: *const ()) -> Option<&ModuleRange> {
+		match p.is_null() {
+			true => None,
+			false => self.modules.lookup(p as usize),
+		}
+	}
+}
+
+#[cfg(test)]
+fn test_range(range: Range<usize>, owner: ModuleOwner, base: usize) -> ModuleRange {
+	ModuleRange {
+		range,
+		owner,
+		source: RegistrationSource::of_module(HMODULE(base as *mut _)),
+	}
+}
+
+#[test]
+fn module_index_lookup() {
+	let mut index = ModuleIndex::new();
+	assert_eq!(index.lookup(0x1000), None);
+
+	index.insert(test_range(0x3000..0x4000, ModuleOwner::Guest, 0x3000));
+	index.insert(test_range(0x1000..0x2000, ModuleOwner::Host, 0x1000));
+	index.insert(test_range(0x2000..0x3000, ModuleOwner::Addon(7), 0x2000));
+	// empty ranges index nothing
+	index.insert(test_range(0x8000..0x8000, ModuleOwner::Guest, 0x8000));
+	assert_eq!(index.ranges.len(), 3);
+
+	assert_eq!(index.lookup(0xfff), None);
+	assert_eq!(index.lookup(0x1000).map(|m| m.owner), Some(ModuleOwner::Host));
+	assert_eq!(index.lookup(0x1fff).map(|m| m.owner), Some(ModuleOwner::Host));
+	assert_eq!(index.lookup(0x2000).map(|m| m.owner), Some(ModuleOwner::Addon(7)));
+	assert_eq!(index.lookup(0x3fff).map(|m| m.owner), Some(ModuleOwner::Guest));
+	assert_eq!(index.lookup(0x4000), None);
+	assert_eq!(index.lookup(0x8000), None);
+}
+
+#[test]
+fn module_index_overlap() {
+	let mut index = ModuleIndex::new();
+	index.insert(test_range(0x1000..0x2000, ModuleOwner::Addon(1), 0x1000));
+	index.insert(test_range(0x2000..0x3000, ModuleOwner::Addon(2), 0x2000));
+	index.insert(test_range(0x4000..0x5000, ModuleOwner::Addon(3), 0x4000));
+
+	// something new mapped over a module that must have unloaded
+	index.insert(test_range(0x1800..0x2800, ModuleOwner::Guest, 0x1800));
+	assert_eq!(index.lookup(0x1000), None);
+	assert_eq!(index.lookup(0x1800).map(|m| m.owner), Some(ModuleOwner::Guest));
+	assert_eq!(index.lookup(0x2800), None);
+	assert_eq!(index.lookup(0x4000).map(|m| m.owner), Some(ModuleOwner::Addon(3)));
+	assert_eq!(index.ranges.len(), 2);
+
+	// only helpers give way to what's already there
+	index.insert_new(vec![
+		test_range(0x2000..0x2100, ModuleOwner::Addon(4), 0x2000),
+		test_range(0x4000..0x4100, ModuleOwner::Addon(4), 0x2000),
+		test_range(0x6000..0x7000, ModuleOwner::Addon(4), 0x2000),
+	]);
+	assert_eq!(index.lookup(0x1800), None);
+	assert_eq!(index.lookup(0x2000).map(|m| m.owner), Some(ModuleOwner::Addon(4)));
+	assert_eq!(index.lookup(0x4000).map(|m| m.owner), Some(ModuleOwner::Addon(3)));
+	assert_eq!(index.lookup(0x6fff).map(|m| m.owner), Some(ModuleOwner::Addon(4)));
+	assert!(index.ranges.windows(2).all(|w| w[0].range.end <= w[1].range.start));
+}
+
+#[test]
+fn module_index_remove() {
+	let mut index = ModuleIndex::new();
+	index.insert_new(vec![
+		test_range(0x1000..0x2000, ModuleOwner::Addon(1), 0x1000),
+		test_range(0x5000..0x6000, ModuleOwner::Addon(1), 0x1000),
+	]);
+	index.insert(test_range(0x2000..0x3000, ModuleOwner::Guest, 0x2000));
+	index.insert(test_range(0x3000..0x4000, ModuleOwner::Guest, 0x3000));
+
+	// owner and source both have to match
+	index.remove(ModuleOwner::Guest, RegistrationSource::of_module(HMODULE(0x1000 as *mut _)));
+	index.remove(ModuleOwner::Addon(1), RegistrationSource::of_module(HMODULE(0x2000 as *mut _)));
+	assert_eq!(index.ranges.len(), 4);
+
+	index.remove(ModuleOwner::Addon(1), RegistrationSource::of_module(HMODULE(0x1000 as *mut _)));
+	assert_eq!(index.lookup(0x1000), None);
+	assert_eq!(index.lookup(0x5000), None);
+	index.remove(ModuleOwner::Guest, RegistrationSource::of_module(HMODULE(0x2000 as *mut _)));
+	assert_eq!(index.lookup(0x2000), None);
+	assert_eq!(index.lookup(0x3000).map(|m| m.owner), Some(ModuleOwner::Guest));
+
+	index.clear();
+	assert_eq!(index.lookup(0x3000), None);
+}
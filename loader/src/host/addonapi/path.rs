@@ -18,13 +18,11 @@ impl NexusHost {
 		dir.as_ptr()
 	}
 
-	pub unsafe extern "C-unwind" fn addonapi_path_get_addon_dir(name_c: *const c_char) -> *const c_char {
+	/// Where addons keep their own files, each in a directory of their own
+	pub fn addons_dir() -> &'static Path {
 		static ADDONS_DIR: OnceLock<PathBuf> = OnceLock::new();
-		const FALLBACK: &'static CStr = unsafe {
-			CStr::from_bytes_with_nul_unchecked(b"addons/\0")
-		};
 
-		let addons_dir = ADDONS_DIR.get_or_init(|| {
+		ADDONS_DIR.get_or_init(|| {
 			let addons_dir = match config_dir() {
 				Some(mut config_dir) => {
 					config_dir.pop();
@@ -33,7 +31,15 @@ impl NexusHost {
 				None => PathBuf::from("."),
 			};
 			addons_dir
-		});
+		})
+	}
+
+	pub unsafe extern "C-unwind" fn addonapi_path_get_addon_dir(name_c: *const c_char) -> *const c_char {
+		const FALLBACK: &'static CStr = unsafe {
+			CStr::from_bytes_with_nul_unchecked(b"addons/\0")
+		};
+
+		let addons_dir = Self::addons_dir();
 
 		let name = cstr_opt(&name_c);
 		addonapi_stub!(path::get_addon_dir("{:?}", name));
@@ -1,5 +1,5 @@
 use crate::{
-	host::addonapi::NexusHost,
+	host::addonapi::{NexusHost, RegistrationSource},
 	util::ffi::cstr_opt,
 };
 use nexus::gui::RawGuiRender;
@@ -12,6 +12,8 @@ pub struct QuickAccessMenu {
 	pub items: HashMap<Arc<CStr>, Arc<QuickAccessItem>>,
 	pub context_items: HashMap<Arc<CStr>, Arc<QuickAccessContextItem>>,
 	pub notifications: HashMap<Arc<CStr>, u32>,
+	/// Item ids in the order a previous instance drew them, see [ReloadState](crate::extensions::ReloadState)
+	pub order: Vec<Arc<CStr>>,
 }
 
 pub static QUICK_ACCESS: LazyLock<RwLock<QuickAccessMenu>> = LazyLock::new(Default::default);
@@ -47,6 +49,7 @@ impl QuickAccessMenu {
 			texture,
 			// TODO
 			texture_hover: None,
+			source: RegistrationSource::UNKNOWN,
 		})
 	}
 
@@ -59,11 +62,68 @@ impl QuickAccessMenu {
 			.filter(move |&citem| *citem.id == *target)
 	}
 
+	/// Where an item belongs on the bar, relative to the others
+	pub fn layout_key<'a>(&self, id: &'a CStr) -> (usize, &'a CStr) {
+		let pos = self.order.iter()
+			.position(|ordered| **ordered == *id)
+			.unwrap_or(usize::MAX);
+		(pos, id)
+	}
+
+	/// Item ids in the order they're drawn
+	pub fn layout(&self) -> Vec<&Arc<CStr>> {
+		let mut ids: Vec<_> = self.items.keys().collect();
+		ids.sort_by(|a, b| self.layout_key(a).cmp(&self.layout_key(b)));
+		ids
+	}
+
+	/// Point context items that were waiting on `item` at it
+	fn attach_context_items(&mut self, item: &Arc<QuickAccessItem>) {
+		for citem in self.context_items.values_mut() {
+			if citem.target_id == item.id && Weak::as_ptr(&citem.target) != Arc::as_ptr(item) {
+				*citem = Arc::new(QuickAccessContextItem {
+					target: Arc::downgrade(item),
+					..(**citem).clone()
+				});
+			}
+		}
+	}
+
 	pub fn context_items_for_target(&self, target: *const QuickAccessItem) -> impl Iterator<Item = &Arc<QuickAccessContextItem>> {
 		self.context_items.values()
 			.filter(move |citem| Weak::as_ptr(&citem.target) == target)
 	}
 
+	/// Remove everything `source` added, along with any context items that pointed at it
+	pub fn release(source: RegistrationSource) -> usize {
+		let mut leaked = 0;
+		{
+			let mut menu = Self::lock_write();
+			let QuickAccessMenu { items, context_items, notifications, .. } = &mut *menu;
+			items.retain(|id, item| match item.source == source {
+				true => {
+					warn!("{source} never removed quick access item {id:?}");
+					notifications.remove(id);
+					leaked += 1;
+					false
+				},
+				false => true,
+			});
+			context_items.retain(|id, item| match item.source == source {
+				true => {
+					warn!("{source} never removed quick access context item {id:?}");
+					leaked += 1;
+					false
+				},
+				false => true,
+			});
+		}
+		if leaked > 0 {
+			ui::QuickAccessMenuUi::mark_dirty();
+		}
+		leaked
+	}
+
 	/// TODO
 	pub fn settings() -> QuickAccessSettings {
 		QuickAccessSettings::default()
@@ -88,6 +148,7 @@ pub struct QuickAccessItem {
 	pub texture_hover: Option<CString>,
 	pub keybind_id: Option<CString>,
 	pub tooltip: Option<CString>,
+	pub source: RegistrationSource,
 }
 
 impl QuickAccessItem {
@@ -116,7 +177,10 @@ impl QuickAccessItem {
 pub struct QuickAccessContextItem {
 	pub id: Arc<CStr>,
 	pub target: Weak<QuickAccessItem>,
+	/// Kept around so the item can be found again if it's replaced, or registered later
+	pub target_id: Arc<CStr>,
 	pub render: Option<RawGuiRender>,
+	pub source: RegistrationSource,
 }
 
 impl QuickAccessContextItem {
@@ -178,10 +242,13 @@ impl NexusHost {
 			texture_hover: texture_hover_id.map(ToOwned::to_owned),
 			keybind_id: keybind_id.map(ToOwned::to_owned),
 			tooltip: tooltip.map(ToOwned::to_owned),
+			source: RegistrationSource::of_ptr(identifier as *const ()),
 		};
 		let prev = {
 			let mut menu = QuickAccessMenu::lock_write();
-			menu.items.insert(id, Arc::new(item))
+			let item = Arc::new(item);
+			menu.attach_context_items(&item);
+			menu.items.insert(id, item)
 		};
 		ui::QuickAccessMenuUi::mark_dirty();
 
@@ -275,8 +342,8 @@ impl NexusHost {
 			let target = match menu.items.get(target_id) {
 				Some(target) => Arc::downgrade(&target),
 				None => {
-					warn!("quick access item {:?} not found", target_id);
-					// TODO: return instead?
+					// it may still show up, see attach_context_items
+					debug!("quick access item {:?} not found yet", target_id);
 					Weak::new()
 				},
 			};
@@ -284,7 +351,9 @@ impl NexusHost {
 			let item = QuickAccessContextItem {
 				id: id.clone(),
 				target,
+				target_id: target_id.into(),
 				render: Some(shortcut_render_callback),
+				source: RegistrationSource::of_ptr(shortcut_render_callback as *const ()),
 			};
 			menu.context_items.insert(id, Arc::new(item))
 		};
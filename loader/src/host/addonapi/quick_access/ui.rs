@@ -469,7 +469,7 @@ impl QuickAccessMenuUi {
 			.filter(|item| menu_items.contains(&Arc::as_ptr(item)));
 
 		for item in new_items {
-			let idx = self.draw_items.partition_point(|uitem| uitem.desc.id <= item.id);
+			let idx = self.draw_items.partition_point(|uitem| menu.layout_key(&uitem.desc.id) <= menu.layout_key(&item.id));
 			let mut uitem = QuickAccessItemUi::with_item(item.clone());
 			uitem.rebuild_context(menu);
 			self.draw_items.insert(idx, uitem);
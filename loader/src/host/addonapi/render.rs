@@ -4,7 +4,7 @@ use windows::core::InterfaceRef;
 use windows::Win32::Foundation::ERROR_API_UNAVAILABLE;
 use windows::Win32::Graphics::Direct3D11::{ID3D11Device, ID3D11DeviceContext};
 use windows::Win32::Graphics::Dxgi::IDXGISwapChain;
-use crate::host::addonapi::NexusHost;
+use crate::host::addonapi::{NexusHost, RegistrationSource};
 use crate::util::win::{WinResult, WinError};
 
 impl NexusHost {
@@ -26,10 +26,12 @@ impl NexusHost {
 	pub unsafe extern "C-unwind" fn addonapi_renderer_register(render_type: nexus::gui::RenderType, render_callback: RawGuiRender) {
 		addonapi_stub!(renderer::add("{:?}, {:?}", render_type, render_callback));
 
+		let source = RegistrationSource::of_ptr(render_callback as *const ());
 		Self::cache_write_with(render_callback as *const _, move |mut cache| {
 			let handlers = cache.renderers.entry(render_type)
 				.or_default();
 			handlers.insert(render_callback);
+			cache.register_source(render_callback as *const (), source);
 		});
 	}
 
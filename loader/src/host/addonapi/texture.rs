@@ -1,5 +1,5 @@
 use crate::{
-	host::addonapi::NexusHost,
+	host::addonapi::{NexusHost, RegistrationSource},
 	util::{ffi::{cstr_opt, nonnull_ref}, win::{find_resource, WinError, WinResult, MAKERESOURCEA}},
 };
 use nexus::texture::{RawTextureReceiveCallback, Texture};
@@ -420,6 +420,8 @@ pub enum TextureEntry {
 	Failed,
 	Upload {
 		callback: Option<RawTextureReceiveCallback>,
+		/// Whoever is waiting on `callback`
+		source: RegistrationSource,
 		upload: Option<TextureUpload>,
 	},
 	#[cfg(todo)]
@@ -460,7 +462,7 @@ impl TextureCache {
 		None
 	}
 
-	pub fn queue_upload(&mut self, id: CString, upload: TextureUpload, callback: Option<RawTextureReceiveCallback>) {
+	pub fn queue_upload(&mut self, id: CString, upload: TextureUpload, callback: Option<RawTextureReceiveCallback>, source: RegistrationSource) {
 		if !self.textures.contains_key(id.as_c_str()) {
 			error!("TODO: duplicate texture upload for {id:?}");
 			return
@@ -469,6 +471,7 @@ impl TextureCache {
 		self.upload_count = self.upload_count.saturating_add(1);
 		self.textures.insert(id.into(), TextureEntry::Upload {
 			callback,
+			source,
 			upload: Some(upload),
 		});
 	}
@@ -491,7 +494,7 @@ impl TextureCache {
 				None
 			},
 			Entry::Occupied(mut e) => match e.get_mut() {
-				e @ &mut TextureEntry::Upload { callback, upload: None } => {
+				e @ &mut TextureEntry::Upload { callback, upload: None, .. } => {
 					*e = texture;
 					callback.map(|cb| (cb, ptr))
 				},
@@ -545,7 +548,7 @@ impl TextureCache {
 		}
 	}
 
-	pub fn schedule_upload(&mut self, id: CString, image: WicImage, callback: Option<RawTextureReceiveCallback>) -> WinResult<()> {
+	pub fn schedule_upload(&mut self, id: CString, image: WicImage, callback: Option<RawTextureReceiveCallback>, source: RegistrationSource) -> WinResult<()> {
 		match self.textures.get(id.as_c_str()) {
 			None | Some(TextureEntry::Failed) => (),
 			Some(&TextureEntry::Upload { callback: ucb, .. }) if ucb == callback => {
@@ -563,6 +566,7 @@ impl TextureCache {
 		self.textures.insert(id.into(), TextureEntry::Upload {
 			upload: Some(upload),
 			callback,
+			source,
 		});
 
 		Ok(())
@@ -633,6 +637,25 @@ impl TextureCache {
 		TextureCache::addonapi_ptr_nn_opt(fallback)
 	}
 
+	/// Forget callbacks waiting on uploads that `source` will no longer be around for
+	///
+	/// The textures themselves stay, others may be using them too.
+	pub fn release(source: RegistrationSource) -> usize {
+		let mut cache = TEXTURE_CACHE.write()
+			.unwrap_or_else(|e| e.into_inner());
+		let mut leaked = 0;
+		for (_id, entry) in &mut cache.textures {
+			if let TextureEntry::Upload { callback, source: owner, .. } = entry {
+				// the module is already gone, so its callback can't be looked up anymore
+				if callback.is_some() && *owner == source {
+					warn!("{source} is no longer waiting on texture {_id:?}");
+					*callback = None;
+					leaked += 1;
+				}
+			}
+		}
+		leaked
+	}
 }
 
 impl NexusHost {
@@ -704,6 +727,7 @@ impl NexusHost {
 	}
 
 	fn texture_schedule_load(req: WinResult<TextureUpload>, id: &CStr, callback: RawTextureReceiveCallback) {
+		let source = RegistrationSource::of_ptr(callback as *const ());
 		let texture = {
 			let mut cache = match TEXTURE_CACHE.write() {
 				Ok(cache) => cache,
@@ -728,6 +752,7 @@ impl NexusHost {
 					prev = cache.textures.insert(id.into(), TextureEntry::Upload {
 						upload: Some(upload),
 						callback: Some(callback),
+						source,
 					});
 					None
 				},
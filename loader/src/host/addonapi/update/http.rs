@@ -0,0 +1,169 @@
+use crate::util::win::{WinError, WinResult};
+use std::{ffi::c_void, fmt, io, ptr};
+use windows::Win32::{
+	Foundation::ERROR_INVALID_HANDLE,
+	Networking::WinHttp::{
+		WinHttpCloseHandle, WinHttpConnect, WinHttpCrackUrl, WinHttpOpen, WinHttpOpenRequest,
+		WinHttpQueryDataAvailable, WinHttpQueryHeaders, WinHttpReadData, WinHttpReceiveResponse, WinHttpSendRequest,
+		URL_COMPONENTS, WINHTTP_ACCESS_TYPE_AUTOMATIC_PROXY, WINHTTP_FLAG_SECURE, WINHTTP_INTERNET_SCHEME_HTTPS, WINHTTP_OPEN_REQUEST_FLAGS,
+		WINHTTP_QUERY_FLAG_NUMBER, WINHTTP_QUERY_STATUS_CODE,
+	},
+};
+use windows_strings::{w, HSTRING, PCWSTR};
+
+#[derive(Clone, Debug, Default)]
+pub struct HttpResponse {
+	pub status: u16,
+	pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+	pub fn is_success(&self) -> bool {
+		(200..300).contains(&self.status)
+	}
+
+	/// The body, as long as the request actually succeeded
+	pub fn into_body(self) -> io::Result<Vec<u8>> {
+		match self.status {
+			404 => Err(io::Error::new(io::ErrorKind::NotFound, "HTTP 404")),
+			_ if self.is_success() => Ok(self.body),
+			status => Err(io::Error::new(io::ErrorKind::Other, format!("HTTP {status}"))),
+		}
+	}
+}
+
+/// How [Updater](super::Updater) reaches the outside world
+///
+/// Swappable, so it can be pointed at a stand-in instead.
+pub trait HttpClient: Send + Sync {
+	fn get(&self, url: &str) -> io::Result<HttpResponse>;
+}
+
+/// A WinHTTP session, which follows the system proxy settings
+pub struct WinHttpClient {
+	session: HttpHandle,
+}
+
+impl WinHttpClient {
+	pub const USER_AGENT: &'static str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+	pub fn new() -> WinResult<Self> {
+		let session = unsafe {
+			WinHttpOpen(&HSTRING::from(Self::USER_AGENT), WINHTTP_ACCESS_TYPE_AUTOMATIC_PROXY, PCWSTR::null(), PCWSTR::null(), 0)
+		};
+		Ok(Self {
+			session: HttpHandle::new(session)?,
+		})
+	}
+
+	fn get_win(&self, url: &str) -> WinResult<HttpResponse> {
+		let url: Vec<u16> = url.encode_utf16().collect();
+		// lengths of -1 ask for pointers into `url`, rather than copies
+		let mut parts = URL_COMPONENTS {
+			dwStructSize: size_of::<URL_COMPONENTS>() as u32,
+			dwSchemeLength: u32::MAX,
+			dwHostNameLength: u32::MAX,
+			dwUrlPathLength: u32::MAX,
+			dwExtraInfoLength: u32::MAX,
+			..Default::default()
+		};
+		unsafe {
+			WinHttpCrackUrl(&url, 0, &mut parts)?;
+		}
+		let host = unsafe {
+			HSTRING::from_wide(std::slice::from_raw_parts(parts.lpszHostName.0, parts.dwHostNameLength as usize))
+		};
+		// the query string immediately follows the path
+		let path = match parts.dwUrlPathLength + parts.dwExtraInfoLength {
+			0 => HSTRING::from("/"),
+			len => unsafe {
+				HSTRING::from_wide(std::slice::from_raw_parts(parts.lpszUrlPath.0, len as usize))
+			},
+		};
+		let flags = match parts.nScheme {
+			WINHTTP_INTERNET_SCHEME_HTTPS => WINHTTP_FLAG_SECURE,
+			_ => WINHTTP_OPEN_REQUEST_FLAGS(0),
+		};
+
+		let connect = HttpHandle::new(unsafe {
+			WinHttpConnect(self.session.0, &host, parts.nPort, 0)
+		})?;
+		let request = HttpHandle::new(unsafe {
+			WinHttpOpenRequest(connect.0, w!("GET"), &path, PCWSTR::null(), PCWSTR::null(), ptr::null(), flags)
+		})?;
+		unsafe {
+			WinHttpSendRequest(request.0, None, None, 0, 0, 0)?;
+			WinHttpReceiveResponse(request.0, ptr::null_mut())?;
+		}
+
+		let mut status = 0u32;
+		let mut status_len = size_of::<u32>() as u32;
+		unsafe {
+			WinHttpQueryHeaders(request.0, WINHTTP_QUERY_STATUS_CODE | WINHTTP_QUERY_FLAG_NUMBER, PCWSTR::null(), Some(&mut status as *mut u32 as *mut c_void), &mut status_len, ptr::null_mut())?;
+		}
+
+		let mut body = Vec::new();
+		loop {
+			let mut available = 0u32;
+			unsafe {
+				WinHttpQueryDataAvailable(request.0, &mut available)?;
+			}
+			if available == 0 {
+				break
+			}
+			let start = body.len();
+			body.resize(start + available as usize, 0);
+			let mut read = 0u32;
+			unsafe {
+				WinHttpReadData(request.0, body[start..].as_mut_ptr() as *mut c_void, available, &mut read)?;
+			}
+			body.truncate(start + read as usize);
+		}
+
+		Ok(HttpResponse {
+			status: status as u16,
+			body,
+		})
+	}
+}
+
+impl HttpClient for WinHttpClient {
+	fn get(&self, url: &str) -> io::Result<HttpResponse> {
+		self.get_win(url)
+			.map_err(|e| io::Error::new(io::ErrorKind::Other, format!("GET {url} failed: {e}")))
+	}
+}
+
+impl fmt::Debug for WinHttpClient {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("WinHttpClient")
+			.field("session", &self.session.0)
+			.finish()
+	}
+}
+
+struct HttpHandle(*mut c_void);
+
+impl HttpHandle {
+	fn new(handle: *mut c_void) -> WinResult<Self> {
+		match handle.is_null() {
+			true => Err(match WinError::from_win32() {
+				e if e.code().is_ok() => WinError::new(ERROR_INVALID_HANDLE.to_hresult(), "WinHTTP handle"),
+				e => e,
+			}),
+			false => Ok(Self(handle)),
+		}
+	}
+}
+
+impl Drop for HttpHandle {
+	fn drop(&mut self) {
+		unsafe {
+			let _ = WinHttpCloseHandle(self.0);
+		}
+	}
+}
+
+// WinHTTP handles in synchronous mode can be used from any thread
+unsafe impl Send for HttpHandle {}
+unsafe impl Sync for HttpHandle {}
@@ -0,0 +1,687 @@
+use crate::{
+	extensions::{content_hash, original_module_path, Loader, LoaderCommand},
+	host::addonapi::{NexusAddon, NexusHost},
+	settings::{settings_key, Settings},
+	util::{arc::config_dir, ffi::cstr_opt, nexus::{AddonDesc, AddonVersion, NexusId}, win::{pe::PeFile, retain_library}},
+};
+use std::{collections::BTreeSet, ffi::c_char, fs, io, mem, path::{Path, PathBuf}, sync::{Arc, Mutex, MutexGuard}, thread::{self, JoinHandle}};
+use nexus::UpdateProvider;
+use serde::{Deserialize, Serialize};
+
+mod http;
+pub use self::http::{HttpClient, HttpResponse, WinHttpClient};
+
+static UPDATES: Mutex<Updates> = Mutex::new(Updates::empty());
+
+/// Where an addon says newer builds of it can be found
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UpdateSource {
+	/// The latest release of a GitHub repository
+	GitHub {
+		owner: String,
+		repo: String,
+	},
+	/// A link that always serves the latest build
+	Direct {
+		url: String,
+	},
+}
+
+impl UpdateSource {
+	pub const GITHUB_API: &'static str = "https://api.github.com";
+
+	pub fn for_addon(desc: &AddonDesc) -> Option<Self> {
+		let link = desc.update_link()?.to_str().ok()?;
+		Self::with_provider(desc, link)
+	}
+
+	/// `link` read the way `desc` says its updates are provided
+	pub fn with_provider(desc: &AddonDesc, link: &str) -> Option<Self> {
+		match desc.provider {
+			UpdateProvider::GitHub => Self::github(link),
+			UpdateProvider::Direct => Some(Self::direct(link)),
+			UpdateProvider::None => None,
+			_provider => {
+				debug!("{desc} wants updates from {_provider:?}, which isn't supported");
+				None
+			},
+		}
+	}
+
+	/// `https://github.com/owner/repo`, or anything underneath it
+	pub fn github(link: &str) -> Option<Self> {
+		let path = link.trim()
+			.trim_start_matches("https://").trim_start_matches("http://")
+			.trim_start_matches("www.")
+			.strip_prefix("github.com/")?;
+		let mut parts = path.split('/').filter(|p| !p.is_empty());
+		let owner = parts.next()?;
+		let repo = parts.next()?.trim_end_matches(".git");
+		Some(Self::GitHub {
+			owner: owner.into(),
+			repo: repo.into(),
+		})
+	}
+
+	pub fn direct(url: &str) -> Self {
+		Self::Direct {
+			url: url.trim().into(),
+		}
+	}
+
+	pub fn latest_release_url(&self) -> Option<String> {
+		match self {
+			Self::GitHub { owner, repo } => Some(format!("{}/repos/{owner}/{repo}/releases/latest", Self::GITHUB_API)),
+			Self::Direct { .. } => None,
+		}
+	}
+}
+
+/// A build that might be newer than what's installed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Release {
+	pub tag: Option<String>,
+	/// Direct links don't say, so the download has to be compared instead
+	pub version: Option<AddonVersion>,
+	pub download_url: String,
+}
+
+impl Release {
+	/// A GitHub `releases/latest` response, and the first DLL attached to it
+	pub fn parse_github(release: &serde_json::Value) -> Option<Self> {
+		let tag = release.get("tag_name")?.as_str()?;
+		let download_url = release.get("assets")?.as_array()?.iter()
+			.filter(|asset| asset.get("name").and_then(|n| n.as_str())
+				.map(|name| name.to_ascii_lowercase().ends_with(".dll"))
+				.unwrap_or(false)
+			).find_map(|asset| asset.get("browser_download_url")?.as_str())?;
+		Some(Self {
+			tag: Some(tag.into()),
+			version: Self::parse_version(tag),
+			download_url: download_url.into(),
+		})
+	}
+
+	/// Tags like `v1.2.3`, or `1.2.3.4-beta`
+	pub fn parse_version(tag: &str) -> Option<AddonVersion> {
+		let tag = tag.trim().trim_start_matches(|c| c == 'v' || c == 'V');
+		let mut parts = [0i16; 4];
+		let mut count = 0;
+		for (i, part) in tag.split('.').take(parts.len()).enumerate() {
+			let digits = part.find(|c: char| !c.is_ascii_digit()).unwrap_or(part.len());
+			match part[..digits].parse() {
+				Ok(n) => parts[i] = n,
+				Err(..) => break,
+			}
+			count = i + 1;
+			if digits < part.len() {
+				break
+			}
+		}
+		let [major, minor, build, revision] = parts;
+		match count {
+			0 => None,
+			_ => Some(AddonVersion::new(major, minor, revision, build)),
+		}
+	}
+
+	pub fn is_newer_than(&self, current: &AddonVersion) -> bool {
+		// an unset revision is as good as zero
+		let key = |v: &AddonVersion| (v.major, v.minor, v.build, v.revision.max(0));
+		match &self.version {
+			Some(version) => key(version) > key(current),
+			None => true,
+		}
+	}
+}
+
+/// A download waiting to replace the file it was downloaded for
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StagedUpdate {
+	pub target: PathBuf,
+	pub staged: PathBuf,
+	pub tag: Option<String>,
+}
+
+impl StagedUpdate {
+	/// Swap the download in for the original
+	///
+	/// A loaded DLL can still be renamed out of the way, just not overwritten.
+	pub fn apply(&self) -> io::Result<()> {
+		let old = self.target.with_extension("dll.old");
+		let _ = fs::remove_file(&old);
+		let had_target = match fs::rename(&self.target, &old) {
+			Ok(()) => true,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+			Err(e) => return Err(e),
+		};
+		let res = fs::rename(&self.staged, &self.target)
+			// staging may live on another volume
+			.or_else(|_| fs::copy(&self.staged, &self.target)
+				.and_then(|_| fs::remove_file(&self.staged))
+			);
+		match res {
+			Ok(()) => {
+				// still in use if it's loaded, so the next launch cleans it up
+				let _ = fs::remove_file(&old);
+				Ok(())
+			},
+			Err(e) => {
+				if had_target {
+					let _ = fs::rename(&old, &self.target);
+				}
+				Err(e)
+			},
+		}
+	}
+}
+
+/// Checks for and downloads newer builds
+pub struct Updater {
+	client: Arc<dyn HttpClient>,
+	staging_dir: PathBuf,
+}
+
+impl Updater {
+	pub fn new(client: Arc<dyn HttpClient>, staging_dir: PathBuf) -> Self {
+		Self {
+			client,
+			staging_dir,
+		}
+	}
+
+	/// The latest build, if it's any newer than `current`
+	pub fn check(&self, source: &UpdateSource, current: &AddonVersion) -> io::Result<Option<Release>> {
+		let release = match source {
+			UpdateSource::Direct { url } => Release {
+				tag: None,
+				version: None,
+				download_url: url.clone(),
+			},
+			UpdateSource::GitHub { .. } => {
+				let url = source.latest_release_url().unwrap_or_default();
+				let body = self.client.get(&url)?.into_body()?;
+				let release: serde_json::Value = serde_json::from_slice(&body)?;
+				Release::parse_github(&release)
+					.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("{url} has no DLL release")))?
+			},
+		};
+		Ok(match release.is_newer_than(current) {
+			true => Some(release),
+			false => None,
+		})
+	}
+
+	/// Download `release` into the staging directory, unless it's what `target` already is
+	pub fn stage(&self, release: &Release, target: &Path) -> io::Result<Option<StagedUpdate>> {
+		let data = self.client.get(&release.download_url)?.into_body()?;
+		if data.is_empty() {
+			return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("{} was empty", release.download_url)))
+		}
+		// an error page or the wrong asset must never end up renamed over the addon
+		match PeFile::parse(&data) {
+			Ok(pe) if pe.is_dll() && pe.is_amd64() => (),
+			_ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is not a 64-bit DLL", release.download_url))),
+		}
+		if let Ok(installed) = fs::read(target) {
+			if content_hash(&installed) == content_hash(&data) {
+				return Ok(None)
+			}
+		}
+
+		let fname = target.file_name()
+			.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file"))?;
+		// not named .dll, so nothing mistakes it for an extension
+		let staged = self.staging_dir.join(fname).with_extension("dll.update");
+		fs::create_dir_all(&self.staging_dir)?;
+		let tmp = staged.with_extension("tmp");
+		fs::write(&tmp, &data)?;
+		fs::rename(&tmp, &staged)?;
+
+		Ok(Some(StagedUpdate {
+			target: target.into(),
+			staged,
+			tag: release.tag.clone(),
+		}))
+	}
+
+	pub fn update(&self, source: &UpdateSource, current: &AddonVersion, target: &Path) -> io::Result<Option<StagedUpdate>> {
+		match self.check(source, current)? {
+			Some(release) => self.stage(&release, target),
+			None => Ok(None),
+		}
+	}
+}
+
+/// Updates found this session
+#[derive(Default)]
+pub struct Updates {
+	client: Option<Arc<dyn HttpClient>>,
+	/// Downloaded, waiting for the next launch
+	pub staged: Vec<StagedUpdate>,
+	/// Hot-loadable addons with an update ready to go in
+	pub hotload: Vec<(NexusId, StagedUpdate)>,
+	/// Hotload updates waiting for their addon to finish unloading
+	pub reloading: Vec<StagedUpdate>,
+	/// Addons already looked at, so reloads don't ask again
+	pub checked: BTreeSet<NexusId>,
+	/// A newer arcloader, for arcdps to download
+	pub self_update: Option<Release>,
+	/// Checks and downloads in progress, joined before unloading
+	workers: Vec<JoinHandle<()>>,
+}
+
+impl Updates {
+	pub const DIR_NAME: &'static str = "arcloader-updates";
+	pub const MANIFEST: &'static str = "pending.json";
+	const THREAD_NAME: &'static str = "arcloader-update";
+
+	pub const fn empty() -> Self {
+		Self {
+			client: None,
+			staged: Vec::new(),
+			hotload: Vec::new(),
+			reloading: Vec::new(),
+			checked: BTreeSet::new(),
+			self_update: None,
+			workers: Vec::new(),
+		}
+	}
+
+	pub fn lock() -> MutexGuard<'static, Self> {
+		UPDATES.lock()
+			.unwrap_or_else(|e| e.into_inner())
+	}
+
+	pub fn staging_dir() -> Option<PathBuf> {
+		config_dir()
+			.map(|dir| dir.join(Self::DIR_NAME))
+	}
+
+	/// Route requests through something other than WinHTTP
+	pub fn set_client(client: Arc<dyn HttpClient>) {
+		Self::lock().client = Some(client);
+	}
+
+	pub fn client() -> io::Result<Arc<dyn HttpClient>> {
+		let mut updates = Self::lock();
+		if let Some(client) = &updates.client {
+			return Ok(client.clone())
+		}
+		let client: Arc<dyn HttpClient> = Arc::new(WinHttpClient::new()
+			.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
+		);
+		updates.client = Some(client.clone());
+		Ok(client)
+	}
+
+	pub fn updater() -> io::Result<Updater> {
+		let staging_dir = Self::staging_dir()
+			.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "arcdps config dir unavailable"))?;
+		Ok(Updater::new(Self::client()?, staging_dir))
+	}
+
+	/// Put last session's downloads in place, before anything gets loaded
+	pub fn init() {
+		let dir = match Self::staging_dir() {
+			Some(dir) => dir,
+			None => return,
+		};
+		let pending = match Self::read_manifest(&dir.join(Self::MANIFEST)) {
+			Ok(pending) => pending,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+			Err(_e) => {
+				warn!("failed to read staged updates: {_e}");
+				return
+			},
+		};
+
+		let mut remaining = Vec::new();
+		for update in pending {
+			match update.apply() {
+				Ok(()) => info!("updated {} to {}", update.target.display(), update.tag.as_deref().unwrap_or("its latest build")),
+				Err(e) if e.kind() == io::ErrorKind::NotFound => debug!("dropping {}: {e}", update.staged.display()),
+				Err(_e) => {
+					warn!("failed to update {}, will try again next time: {_e}", update.target.display());
+					remaining.push(update);
+				},
+			}
+		}
+		Self::lock().staged = remaining;
+		Self::save_manifest();
+
+		if Settings::lock_read().check_updates {
+			Self::check_self();
+		}
+	}
+
+	pub fn unload() {
+		let workers = {
+			let mut updates = Self::lock();
+			updates.hotload.clear();
+			updates.reloading.clear();
+			updates.checked.clear();
+			updates.client = None;
+			mem::take(&mut updates.workers)
+		};
+		// a download can't be left running in code that's about to be unmapped
+		for worker in workers {
+			if let Err(_e) = worker.join() {
+				error!("update worker panicked: {_e:?}");
+			}
+		}
+	}
+
+	/// Run `f` in the background, on a thread that [unload](Self::unload) waits for
+	pub fn spawn<F: FnOnce() + Send + 'static>(f: F) {
+		match thread::Builder::new().name(Self::THREAD_NAME.into()).spawn(f) {
+			Ok(worker) => {
+				let mut updates = Self::lock();
+				updates.workers.retain(|worker| !worker.is_finished());
+				updates.workers.push(worker);
+			},
+			Err(_e) => error!("failed to start update worker: {_e}"),
+		}
+	}
+
+	pub fn read_manifest(path: &Path) -> io::Result<Vec<StagedUpdate>> {
+		let data = fs::read(path)?;
+		serde_json::from_slice(&data)
+			.map_err(Into::into)
+	}
+
+	fn save_manifest() {
+		let dir = match Self::staging_dir() {
+			Some(dir) => dir,
+			None => return,
+		};
+		let staged = Self::lock().staged.clone();
+		let path = dir.join(Self::MANIFEST);
+		let res = match staged.is_empty() {
+			true => match fs::remove_file(&path) {
+				Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+				res => res,
+			},
+			false => serde_json::to_vec_pretty(&staged)
+				.map_err(Into::into)
+				.and_then(|data| {
+					fs::create_dir_all(&dir)?;
+					fs::write(&path, data)
+				}),
+		};
+		if let Err(_e) = res {
+			error!("failed to save staged updates: {_e}");
+		}
+	}
+
+	/// Remember a download for the next launch
+	pub fn stage(update: StagedUpdate) {
+		{
+			let mut updates = Self::lock();
+			updates.staged.retain(|u| u.target != update.target);
+			updates.staged.push(update);
+		}
+		Self::save_manifest();
+	}
+
+	/// Forget a download that's already been applied
+	pub fn unstage(update: &StagedUpdate) {
+		Self::lock().staged.retain(|u| u != update);
+		Self::save_manifest();
+	}
+
+	/// Put in whatever update was waiting on `path` to be reloaded
+	///
+	/// Called by the loader worker once the old module is gone, and before the new one loads.
+	pub fn apply_reloading(path: &Path) {
+		let key = settings_key(path);
+		let updates: Vec<StagedUpdate> = {
+			let mut updates = Self::lock();
+			let (matching, rest): (Vec<_>, _) = mem::take(&mut updates.reloading).into_iter()
+				.partition(|update| key.is_some() && settings_key(&update.target) == key);
+			updates.reloading = rest;
+			matching
+		};
+		for update in updates {
+			match update.apply() {
+				Ok(()) => {
+					info!("updated {} to {}", update.target.display(), update.tag.as_deref().unwrap_or("its latest build"));
+					Self::unstage(&update);
+				},
+				Err(_e) => error!("failed to apply update to {}, will try again next launch: {_e}", update.target.display()),
+			}
+		}
+	}
+
+	pub fn take_hotload() -> Vec<(NexusId, StagedUpdate)> {
+		match UPDATES.try_lock() {
+			Ok(mut updates) if !updates.hotload.is_empty() => mem::take(&mut updates.hotload),
+			_ => Vec::new(),
+		}
+	}
+
+	/// Look for a newer arcloader in the background
+	pub fn check_self() {
+		let source = match UpdateSource::github(env!("CARGO_PKG_REPOSITORY")) {
+			Some(source) => source,
+			None => return,
+		};
+		let current = Release::parse_version(env!("CARGO_PKG_VERSION"))
+			.unwrap_or(AddonVersion::new(0, 0, 0, 0));
+		Self::spawn(move || {
+			let res = Self::updater()
+				.and_then(|updater| updater.check(&source, &current));
+			match res {
+				Ok(Some(release)) => {
+					info!("arcloader {} is available", release.tag.as_deref().unwrap_or("update"));
+					Self::lock().self_update = Some(release);
+				},
+				Ok(None) => debug!("arcloader is up to date"),
+				Err(_e) => debug!("arcloader update check failed: {_e}"),
+			}
+		});
+	}
+
+	/// Where arcdps can download a newer arcloader from, once [Updates::check_self] has found one
+	pub fn self_update_url() -> Option<String> {
+		Self::lock().self_update.as_ref()
+			.map(|release| release.download_url.clone())
+	}
+}
+
+impl NexusHost {
+	/// Look for a newer build of `addon` in the background, once per session
+	pub fn update_check(addon: &NexusAddon) {
+		if !Settings::lock_read().check_updates {
+			return
+		}
+		let source = match UpdateSource::for_addon(addon) {
+			Some(source) => source,
+			None => return,
+		};
+		if !Updates::lock().checked.insert(addon.signature) {
+			return
+		}
+		Self::update_check_with(addon, source)
+	}
+
+	fn update_check_with(addon: &NexusAddon, source: UpdateSource) {
+		let target = match original_module_path(addon.module()) {
+			Ok(path) => path,
+			Err(_e) => {
+				debug!("can't update {addon}, its path is unknown: {_e}");
+				return
+			},
+		};
+		let sig = addon.signature;
+		let hotload = addon.can_hotload();
+		let current = *addon.version();
+		let _name = addon.to_string();
+
+		Updates::spawn(move || {
+			let res = Updates::updater()
+				.and_then(|updater| updater.update(&source, &current, &target));
+			let update = match res {
+				Ok(Some(update)) => update,
+				Ok(None) => {
+					debug!("{_name} is up to date");
+					return
+				},
+				Err(_e) => {
+					warn!("{_name} update check failed: {_e}");
+					return
+				},
+			};
+
+			info!("{_name} update {} downloaded", update.tag.as_deref().unwrap_or("(untagged)"));
+			Updates::stage(update.clone());
+			if hotload {
+				Updates::lock().hotload.push((sig, update));
+			}
+		});
+	}
+
+	/// Swap in updates for hot-loadable addons, from the render thread
+	///
+	/// Loaded addons go through [LoaderCommand::Reload],
+	/// which waits for the old module to be freed before [applying](Updates::apply_reloading) the update.
+	pub fn update_hotload() {
+		for (sig, update) in Updates::take_hotload() {
+			let addon = Self::lock_read().addons.get(&sig).cloned();
+			let addon = match addon {
+				Some(addon) => addon,
+				None => {
+					match update.apply() {
+						Ok(()) => Updates::unstage(&update),
+						Err(_e) => error!("failed to apply update to {}: {_e}", update.target.display()),
+					}
+					continue
+				},
+			};
+
+			info!("reloading {addon} to update it...");
+			let module = match retain_library(addon.module()) {
+				Ok(module) => module,
+				Err(_e) => {
+					error!("failed to reload {addon}, its update will wait until next launch: {_e}");
+					continue
+				},
+			};
+			Updates::lock().reloading.push(update);
+			let _id = Loader::queue_command(LoaderCommand::Reload { module });
+		}
+	}
+
+	pub unsafe extern "C-unwind" fn addonapi_request_update(signature: i32, update_url: *const c_char) {
+		let update_url = cstr_opt(&update_url);
+
+		addonapi_stub!(update::request("{:?}, {:?}", signature, update_url));
+
+		let addon = Self::lock_read().addons.get(&signature).cloned();
+		let addon = match addon {
+			Some(addon) => addon,
+			None => {
+				warn!("update requested for unknown addon {signature}");
+				return
+			},
+		};
+		let source = match update_url.and_then(|url| url.to_str().ok()) {
+			Some(url) if !url.is_empty() => UpdateSource::with_provider(&addon, url),
+			_ => UpdateSource::for_addon(&addon),
+		};
+		let source = match source {
+			Some(source) => source,
+			None => {
+				debug!("{addon} requested an update, but didn't say where from");
+				return
+			},
+		};
+		Self::update_check_with(&addon, source)
+	}
+}
+
+/// Serves canned responses in place of the network
+#[cfg(test)]
+struct StandIn(std::collections::BTreeMap<String, Vec<u8>>);
+
+#[cfg(test)]
+impl HttpClient for StandIn {
+	fn get(&self, url: &str) -> io::Result<HttpResponse> {
+		Ok(match self.0.get(url) {
+			Some(body) => HttpResponse { status: 200, body: body.clone() },
+			None => HttpResponse { status: 404, body: Vec::new() },
+		})
+	}
+}
+
+#[test]
+fn update_versions() {
+	assert_eq!(Release::parse_version("v1.2.3"), Some(AddonVersion::new(1, 2, 0, 3)));
+	assert_eq!(Release::parse_version("1.2.3.4-beta"), Some(AddonVersion::new(1, 2, 4, 3)));
+	assert_eq!(Release::parse_version("2-rc1"), Some(AddonVersion::new(2, 0, 0, 0)));
+	assert_eq!(Release::parse_version("nightly"), None);
+
+	let release = |tag| Release { tag: None, version: Release::parse_version(tag), download_url: String::new() };
+	let current = AddonVersion::new(1, 2, -1, 3);
+	assert!(release("1.2.4").is_newer_than(&current));
+	assert!(!release("1.2.3").is_newer_than(&current));
+	assert!(!release("1.2.3.0").is_newer_than(&current));
+	assert!(release("1.2.3.1").is_newer_than(&current));
+
+	assert_eq!(UpdateSource::github("https://github.com/arcnmx/arcloader/releases"), Some(UpdateSource::GitHub {
+		owner: "arcnmx".into(),
+		repo: "arcloader".into(),
+	}));
+	assert_eq!(UpdateSource::github("https://example.com/arcloader.dll"), None);
+}
+
+#[cfg(test)]
+const FIXTURE_OLD_DLL: &[u8] = include_bytes!("../../../../../dyload/fixtures/pe/nexus_fixture.dll");
+#[cfg(test)]
+const FIXTURE_NEW_DLL: &[u8] = include_bytes!("../../../../../dyload/fixtures/pe/arcdps_fixture.dll");
+
+#[test]
+fn update_stand_in() {
+	let source = UpdateSource::github("https://github.com/example/addon").unwrap();
+	let release = serde_json::json!({
+		"tag_name": "v1.1.0",
+		"assets": [
+			{ "name": "addon.pdb", "browser_download_url": "https://example.com/addon.pdb" },
+			{ "name": "addon.dll", "browser_download_url": "https://example.com/addon.dll" },
+		],
+	});
+	let client = StandIn([
+		(source.latest_release_url().unwrap(), serde_json::to_vec(&release).unwrap()),
+		("https://example.com/addon.dll".into(), FIXTURE_NEW_DLL.to_vec()),
+		("https://github.com/example/addon".into(), b"<!DOCTYPE html><html></html>".to_vec()),
+		("https://example.com/addon32.dll".into(), include_bytes!("../../../../../dyload/fixtures/pe/i386_fixture.dll").to_vec()),
+	].into_iter().collect());
+
+	let dir = std::env::temp_dir().join(format!("arcloader-update-test-{}", std::process::id()));
+	let target = dir.join("addon.dll");
+	fs::create_dir_all(&dir).unwrap();
+	fs::write(&target, FIXTURE_OLD_DLL).unwrap();
+
+	let updater = Updater::new(Arc::new(client), dir.join("staging"));
+	assert_eq!(updater.update(&source, &AddonVersion::new(1, 1, -1, 0), &target).unwrap(), None);
+	let staged = updater.update(&source, &AddonVersion::new(1, 0, -1, 0), &target).unwrap()
+		.expect("update staged");
+	assert_eq!(staged.tag.as_deref(), Some("v1.1.0"));
+	assert_eq!(fs::read(&target).unwrap(), FIXTURE_OLD_DLL);
+
+	staged.apply().unwrap();
+	assert_eq!(fs::read(&target).unwrap(), FIXTURE_NEW_DLL);
+	assert!(!staged.staged.exists());
+	// already up to date, even with no version to go by
+	let direct = UpdateSource::direct("https://example.com/addon.dll");
+	assert_eq!(updater.update(&direct, &AddonVersion::new(1, 0, -1, 0), &target).unwrap(), None);
+
+	// only DLLs that could actually load get staged
+	for url in ["https://github.com/example/addon", "https://example.com/addon32.dll"] {
+		let err = updater.update(&UpdateSource::direct(url), &AddonVersion::new(1, 0, -1, 0), &target).unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+	}
+	assert_eq!(fs::read(&target).unwrap(), FIXTURE_NEW_DLL);
+
+	let _ = fs::remove_dir_all(&dir);
+}
@@ -1,16 +1,19 @@
-use std::{collections::BTreeSet, ptr, sync::{atomic::{AtomicUsize, Ordering}, RwLock}};
+use std::{collections::BTreeSet, ptr, sync::{atomic::{AtomicUsize, Ordering}, RwLock, RwLockReadGuard, RwLockWriteGuard}};
 
 use nexus::wnd_proc::RawWndProcCallback;
 use windows::Win32::{Foundation::{HWND, LPARAM, LRESULT, WPARAM}, UI::WindowsAndMessaging::{self as wnd, PostMessageA}};
 
-use crate::host::addonapi::NexusHost;
+use crate::host::addonapi::{NexusHost, RegistrationSource};
 
 pub static WNDPROC_CALLBACKS: RwLock<BTreeSet<WndRegistration>> = RwLock::new(BTreeSet::new());
 pub static WNDPROC_WINDOW: AtomicUsize = AtomicUsize::new(0);
 
+/// Callbacks are called in the order they were registered
 #[derive(Debug, Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash)]
 pub struct WndRegistration {
+	order: u64,
 	callback: RawWndProcCallback,
+	source: RegistrationSource,
 }
 
 unsafe impl Send for WndRegistration {}
@@ -23,14 +26,36 @@ impl NexusHost {
 		HWND(WNDPROC_WINDOW.load(Ordering::Relaxed) as *mut _)
 	}
 
-	pub fn wndproc_call(window: HWND, message: u32, param_w: WPARAM, param_l: LPARAM) -> u32 {
-		let callbacks = match WNDPROC_CALLBACKS.read() {
-			Ok(cb) => cb,
-			Err(..) => return message,
-		};
+	fn wndproc_read() -> RwLockReadGuard<'static, BTreeSet<WndRegistration>> {
+		WNDPROC_CALLBACKS.read()
+			.unwrap_or_else(|e| e.into_inner())
+	}
+
+	fn wndproc_write() -> RwLockWriteGuard<'static, BTreeSet<WndRegistration>> {
+		WNDPROC_CALLBACKS.write()
+			.unwrap_or_else(|e| e.into_inner())
+	}
 
-		for cb in callbacks.iter() {
-			match (cb.callback)(window, message, param_w, param_l) {
+	/// The message [send_to_game](Self::addonapi_wndproc_send_to_game) wrapped up, if any
+	pub fn wndproc_passthrough(message: u32) -> Option<u32> {
+		match message.checked_sub(Self::WM_PASSTHROUGH_FIRST) {
+			Some(m) if m < wnd::WM_USER => Some(m),
+			_ => None,
+		}
+	}
+
+	/// Hand `message` to each registered callback in turn, until one consumes it by returning 0
+	pub fn wndproc_call(window: HWND, message: u32, param_w: WPARAM, param_l: LPARAM) -> u32 {
+		// callbacks are free to (de)register themselves or others
+		let callbacks: Vec<WndRegistration> = Self::wndproc_read()
+			.iter().copied()
+			.collect();
+
+		for reg in callbacks {
+			if !Self::wndproc_read().contains(&reg) {
+				continue
+			}
+			match (reg.callback)(window, message, param_w, param_l) {
 				0 => return 0,
 				m if m != 1 && m != message => {
 					warn!("wndproc callback wanted to replace {message} with {m}?");
@@ -42,10 +67,16 @@ impl NexusHost {
 		message
 	}
 
-	pub fn wndproc_filter(window: HWND, message: u32, param_w: WPARAM, param_l: LPARAM) -> u32 {
-		message
+	/// After arcdps is done with `message`, and only if it didn't want it
+	pub fn wndproc_filter(_window: HWND, message: u32, _param_w: WPARAM, _param_l: LPARAM) -> u32 {
+		// unwrapped only now so that neither addons nor arcdps see it
+		match Self::wndproc_passthrough(message) {
+			Some(m) => m,
+			None => message,
+		}
 	}
 
+	/// Every message, before arcdps sees it
 	pub fn wndproc_nofilter(window: HWND, mut message: u32, param_w: WPARAM, param_l: LPARAM) -> u32 {
 		WNDPROC_WINDOW.store(window.0 as usize, Ordering::Relaxed);
 
@@ -54,6 +85,8 @@ impl NexusHost {
 				// XXX: nexus triggers this from hooking DXGIResizeBuffers instead
 				Self::event_broadcast(Self::EV_WINDOW_RESIZED, ptr::null());
 			},
+			// meant for the game alone, see wndproc_filter
+			m if Self::wndproc_passthrough(m).is_some() => return message,
 			_ => (),
 		}
 
@@ -62,12 +95,48 @@ impl NexusHost {
 		message
 	}
 
+	pub fn wndproc_release(source: RegistrationSource) -> usize {
+		let mut callbacks = Self::wndproc_write();
+		let mut leaked = 0;
+		callbacks.retain(|reg| match reg.source == source {
+			true => {
+				warn!("{source} never deregistered wndproc {:?}", reg.callback);
+				leaked += 1;
+				false
+			},
+			false => true,
+		});
+		leaked
+	}
+
 	pub unsafe extern "C-unwind" fn addonapi_wndproc_register(wnd_proc_callback: RawWndProcCallback) {
-		addonapi_stub!(wndproc::register("{:?}", wnd_proc_callback) => ())
+		addonapi_stub!(wndproc::register("{:?}", wnd_proc_callback));
+
+		let source = RegistrationSource::of_ptr(wnd_proc_callback as *const ());
+		let mut callbacks = Self::wndproc_write();
+		if callbacks.iter().any(|reg| reg.callback as usize == wnd_proc_callback as usize) {
+			debug!("{source} registered wndproc {wnd_proc_callback:?} twice");
+			return
+		}
+		let order = callbacks.last()
+			.map(|reg| reg.order + 1)
+			.unwrap_or_default();
+		callbacks.insert(WndRegistration {
+			order,
+			callback: wnd_proc_callback,
+			source,
+		});
 	}
 
 	pub unsafe extern "C-unwind" fn addonapi_wndproc_deregister(wnd_proc_callback: RawWndProcCallback) {
-		addonapi_stub!(wndproc::deregister("{:?}", wnd_proc_callback) => ())
+		addonapi_stub!(wndproc::deregister("{:?}", wnd_proc_callback));
+
+		let mut callbacks = Self::wndproc_write();
+		let count = callbacks.len();
+		callbacks.retain(|reg| reg.callback as usize != wnd_proc_callback as usize);
+		if callbacks.len() == count {
+			debug!("wndproc {wnd_proc_callback:?} was never registered");
+		}
 	}
 
 	pub unsafe extern "C-unwind" fn addonapi_wndproc_send_to_game(h_wnd: HWND, message: u32, param_w: WPARAM, param_l: LPARAM) -> LRESULT {
@@ -9,6 +9,7 @@ pub mod extensions;
 pub mod host;
 pub mod ui;
 pub mod supervisor;
+pub mod settings;
 pub mod export;
 
 pub struct RenderThread;
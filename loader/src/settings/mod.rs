@@ -0,0 +1,374 @@
+use crate::{extensions::{AddonLoader, FileHash, TrustedHash}, supervisor::ExtDir, util::arc::config_dir};
+use std::{collections::{BTreeMap, BTreeSet}, fs, io, num::NonZeroU32, path::{Path, PathBuf}, sync::{RwLock, RwLockReadGuard, RwLockWriteGuard}};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+mod profile;
+pub use self::profile::{Profile, ProfileExts};
+
+pub static SETTINGS: RwLock<Settings> = RwLock::new(Settings::empty());
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct Settings {
+	pub arcdps: ExtSettings,
+	pub nexus: ExtSettings,
+	/// Additional directories to search for extensions
+	pub dirs: Vec<ExtDir>,
+	/// Whether to search the built-in arcdps/arcloader/game directories
+	pub default_dirs: bool,
+	/// Rescan search directories whenever their contents change
+	pub watch: bool,
+	/// Reload hot-loadable nexus addons when their DLL is rebuilt
+	pub dev_mode: bool,
+	/// Load extensions from a copy, see [ShadowCache](crate::extensions::ShadowCache)
+	pub shadow_copy: bool,
+	/// Ask before loading anything that isn't in [Settings::trusted]
+	pub verify_trust: bool,
+	/// SHA-256 of extension binaries the user has vouched for, in hex
+	pub trusted: BTreeMap<String, TrustedHash>,
+	/// Which loader hosts extensions that export entry points for both, keyed by [settings_key]
+	pub hosts: BTreeMap<String, AddonLoader>,
+	/// Saved sets of extensions, by name
+	pub profiles: BTreeMap<String, Profile>,
+	/// The last profile switched to
+	pub profile: Option<String>,
+	/// Language for AddonAPI translations, English when unset
+	pub language: Option<String>,
+	/// Look for newer builds of addons that say where to find them
+	///
+	/// Off by default, since it downloads and runs new code without asking.
+	pub check_updates: bool,
+}
+
+/// Per-loader extension preferences, keyed by [settings_key]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct ExtSettings {
+	pub autoload: BTreeSet<String>,
+	pub blacklist: BTreeSet<String>,
+	pub cache: BTreeMap<String, ExtCache>,
+	/// Blacklisted after crashing mid-load, see [LoadJournal](crate::extensions::LoadJournal)
+	pub quarantine: BTreeMap<String, ExtQuarantine>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct ExtQuarantine {
+	/// When the fatal load was attempted, in seconds since the unix epoch
+	pub since: u64,
+}
+
+/// Last-known metadata for an extension
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct ExtCache {
+	pub sig: Option<NonZeroU32>,
+	pub name: String,
+	pub version: Option<String>,
+	pub path: Option<PathBuf>,
+}
+
+impl Settings {
+	pub const FILE_NAME: &'static str = "arcloader.json";
+
+	pub const fn empty() -> Self {
+		Self {
+			arcdps: ExtSettings::empty(),
+			nexus: ExtSettings::empty(),
+			dirs: Vec::new(),
+			default_dirs: true,
+			watch: true,
+			dev_mode: false,
+			shadow_copy: false,
+			verify_trust: false,
+			trusted: BTreeMap::new(),
+			hosts: BTreeMap::new(),
+			profiles: BTreeMap::new(),
+			profile: None,
+			language: None,
+			check_updates: false,
+		}
+	}
+
+	pub fn path() -> Option<PathBuf> {
+		config_dir()
+			.map(|dir| dir.join(Self::FILE_NAME))
+	}
+
+	pub fn lock_read() -> RwLockReadGuard<'static, Self> {
+		SETTINGS.read()
+			.unwrap_or_else(|e| e.into_inner())
+	}
+
+	pub fn lock_write() -> RwLockWriteGuard<'static, Self> {
+		SETTINGS.write()
+			.unwrap_or_else(|e| e.into_inner())
+	}
+
+	pub fn init() {
+		let path = match Self::path() {
+			Some(p) => p,
+			None => {
+				warn!("arcdps config dir unavailable, settings will not persist");
+				return
+			},
+		};
+
+		match Self::load_from(&path) {
+			Ok(settings) => {
+				debug!("loaded settings from {}", path.display());
+				*Self::lock_write() = settings;
+			},
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {
+				debug!("{} not found, using defaults", path.display());
+			},
+			Err(_e) => {
+				error!("failed to load {}: {_e}", path.display());
+			},
+		}
+	}
+
+	pub fn unload() {
+		if let Err(_e) = Self::save() {
+			error!("failed to save settings: {_e}");
+		}
+	}
+
+	pub fn save() -> io::Result<()> {
+		let path = Self::path()
+			.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "arcdps config dir unavailable"))?;
+
+		let settings = Self::lock_read();
+		settings.save_to(&path)
+	}
+
+	/// Apply `f` and persist the result if it reports a change
+	pub fn update_with<F: FnOnce(&mut Self) -> bool>(f: F) -> bool {
+		let changed = f(&mut Self::lock_write());
+		if changed {
+			if let Err(_e) = Self::save() {
+				error!("failed to save settings: {_e}");
+			}
+		}
+		changed
+	}
+
+	#[cfg(feature = "serde")]
+	pub fn load_from(path: &Path) -> io::Result<Self> {
+		let f = fs::File::open(path)?;
+		serde_json::from_reader(io::BufReader::new(f))
+			.map_err(Into::into)
+	}
+
+	#[cfg(feature = "serde")]
+	pub fn save_to(&self, path: &Path) -> io::Result<()> {
+		let data = serde_json::to_vec_pretty(self)?;
+
+		// write out a copy first so a crash can't leave us with half a file
+		let tmp = path.with_extension("json.tmp");
+		fs::write(&tmp, &data)?;
+		fs::rename(&tmp, path)
+	}
+
+	#[cfg(not(feature = "serde"))]
+	pub fn load_from(_path: &Path) -> io::Result<Self> {
+		Err(io::Error::new(io::ErrorKind::Unsupported, "arcloader built without serde support"))
+	}
+
+	#[cfg(not(feature = "serde"))]
+	pub fn save_to(&self, _path: &Path) -> io::Result<()> {
+		Err(io::Error::new(io::ErrorKind::Unsupported, "arcloader built without serde support"))
+	}
+
+	/// [Settings::dev_mode] is no use if the DLL can't be rebuilt while it's loaded
+	pub fn wants_shadow_copy(&self) -> bool {
+		self.shadow_copy || self.dev_mode
+	}
+
+	pub fn is_trusted(&self, hash: &FileHash) -> bool {
+		self.trusted.contains_key(&hash.to_string())
+	}
+
+	pub fn trust(&mut self, hash: FileHash, name: String, since: u64) -> bool {
+		self.trusted.insert(hash.to_string(), TrustedHash { name, since })
+			.is_none()
+	}
+
+	pub fn extensions(&self, loader: AddonLoader) -> &ExtSettings {
+		match loader {
+			AddonLoader::Arcdps => &self.arcdps,
+			AddonLoader::NexusHost => &self.nexus,
+		}
+	}
+
+	pub fn extensions_mut(&mut self, loader: AddonLoader) -> &mut ExtSettings {
+		match loader {
+			AddonLoader::Arcdps => &mut self.arcdps,
+			AddonLoader::NexusHost => &mut self.nexus,
+		}
+	}
+}
+
+impl Default for Settings {
+	fn default() -> Self {
+		Self::empty()
+	}
+}
+
+impl ExtSettings {
+	pub const fn empty() -> Self {
+		Self {
+			autoload: BTreeSet::new(),
+			blacklist: BTreeSet::new(),
+			cache: BTreeMap::new(),
+			quarantine: BTreeMap::new(),
+		}
+	}
+
+	pub fn should_autoload(&self, key: &str) -> bool {
+		self.autoload.contains(key) && !self.is_blacklisted(key)
+	}
+
+	pub fn is_autoload(&self, key: &str) -> bool {
+		self.autoload.contains(key)
+	}
+
+	pub fn is_blacklisted(&self, key: &str) -> bool {
+		self.blacklist.contains(key)
+	}
+
+	pub fn set_autoload(&mut self, key: &str, autoload: bool) -> bool {
+		match autoload {
+			true => self.autoload.insert(key.into()),
+			false => self.autoload.remove(key),
+		}
+	}
+
+	pub fn set_blacklisted(&mut self, key: &str, blacklisted: bool) -> bool {
+		match blacklisted {
+			true => self.blacklist.insert(key.into()),
+			false => self.blacklist.remove(key),
+		}
+	}
+
+	pub fn quarantined(&self, key: &str) -> Option<&ExtQuarantine> {
+		self.quarantine.get(key)
+	}
+
+	pub fn quarantine(&mut self, key: &str, since: u64) -> bool {
+		let quarantine = ExtQuarantine { since };
+		let changed = self.quarantine.get(key) != Some(&quarantine);
+		self.quarantine.insert(key.into(), quarantine);
+		self.set_blacklisted(key, true) || changed
+	}
+
+	/// Give a quarantined extension another chance
+	pub fn release_quarantine(&mut self, key: &str) -> bool {
+		match self.quarantine.remove(key) {
+			Some(..) => {
+				self.set_blacklisted(key, false);
+				true
+			},
+			None => false,
+		}
+	}
+
+	pub fn update_cache(&mut self, key: &str, cache: ExtCache) -> bool {
+		match self.cache.get(key) {
+			Some(prev) if *prev == cache => false,
+			_ => {
+				self.cache.insert(key.into(), cache);
+				true
+			},
+		}
+	}
+}
+
+/// Identifies an extension by its file name, which survives across sessions and
+/// (unlike a signature) is known before it is ever loaded.
+pub fn settings_key(path: &Path) -> Option<String> {
+	path.file_name()
+		.map(|name| name.to_string_lossy().to_lowercase())
+}
+
+#[test]
+fn settings_defaults() {
+	let settings = Settings::default();
+	assert!(settings.default_dirs);
+	assert!(settings.watch);
+	assert!(!settings.verify_trust);
+	assert!(!settings.check_updates);
+	assert!(!settings.wants_shadow_copy());
+	assert_eq!(settings.arcdps, ExtSettings::default());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn settings_missing_fields() {
+	// anything left out falls back to its default
+	let settings: Settings = serde_json::from_str("{}").unwrap();
+	assert_eq!(settings, Settings::default());
+
+	let settings: Settings = serde_json::from_str(r#"{
+		"dev_mode": true,
+		"arcdps": { "autoload": ["arcdps_thing.dll"] },
+		"dirs": [{ "path": "C:\\addons", "depth": 1 }],
+		"trusted": { "ab": { "name": "arcdps_thing.dll" } }
+	}"#).unwrap();
+	assert!(settings.wants_shadow_copy());
+	assert!(settings.default_dirs);
+	assert!(settings.arcdps.should_autoload("arcdps_thing.dll"));
+	assert!(settings.arcdps.cache.is_empty());
+	assert_eq!(settings.nexus, ExtSettings::default());
+	assert_eq!(settings.dirs, [ExtDir { depth: 1, ..ExtDir::new(r"C:\addons") }]);
+	assert_eq!(settings.trusted["ab"], TrustedHash { name: "arcdps_thing.dll".into(), since: 0 });
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn settings_round_trip() {
+	let mut settings = Settings::default();
+	settings.dirs.push(ExtDir {
+		depth: 2,
+		include: Some("*.dll".into()),
+		loader: Some(AddonLoader::Arcdps),
+		..ExtDir::new(r"C:\addons")
+	});
+	settings.arcdps.set_autoload("arcdps_thing.dll", true);
+	settings.arcdps.update_cache("arcdps_thing.dll", ExtCache {
+		sig: NonZeroU32::new(0x1234),
+		name: "Thing".into(),
+		version: Some("1.0".into()),
+		path: Some(r"C:\addons\arcdps_thing.dll".into()),
+	});
+	settings.nexus.quarantine("nexus_crash.dll", 1234);
+	settings.trust(FileHash([0xab; 32]), "arcdps_thing.dll".into(), 5678);
+	settings.hosts.insert("dual.dll".into(), AddonLoader::NexusHost);
+	settings.profiles.insert("raid".into(), Profile::default());
+	settings.profile = Some("raid".into());
+	settings.language = Some("de".into());
+	settings.check_updates = true;
+
+	let path = std::env::temp_dir().join(format!("arcloader-settings-{}.json", std::process::id()));
+	settings.save_to(&path).unwrap();
+	let loaded = Settings::load_from(&path);
+	let _ = fs::remove_file(&path);
+	assert_eq!(loaded.unwrap(), settings);
+}
+
+#[test]
+fn settings_quarantine() {
+	let mut ext = ExtSettings::empty();
+	assert!(ext.quarantine("crash.dll", 1));
+	assert!(ext.is_blacklisted("crash.dll"));
+	assert!(!ext.should_autoload("crash.dll"));
+	// nothing new to save
+	assert!(!ext.quarantine("crash.dll", 1));
+	assert!(ext.quarantine("crash.dll", 2));
+	assert_eq!(ext.quarantined("crash.dll"), Some(&ExtQuarantine { since: 2 }));
+
+	assert!(ext.release_quarantine("crash.dll"));
+	assert!(!ext.is_blacklisted("crash.dll"));
+	assert!(!ext.release_quarantine("crash.dll"));
+}
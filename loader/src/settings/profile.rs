@@ -0,0 +1,65 @@
+use crate::extensions::AddonLoader;
+use super::ExtSettings;
+use std::collections::BTreeSet;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A named set of extensions, along with their options,
+/// to switch between with [Supervisor::switch_profile](crate::supervisor::Supervisor::switch_profile)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct Profile {
+	pub arcdps: ProfileExts,
+	pub nexus: ProfileExts,
+}
+
+/// Per-loader half of a [Profile], keyed by [settings_key](super::settings_key)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct ProfileExts {
+	/// Loaded while the profile is active
+	pub enabled: BTreeSet<String>,
+	pub autoload: BTreeSet<String>,
+	pub blacklist: BTreeSet<String>,
+}
+
+impl Profile {
+	pub fn extensions(&self, loader: AddonLoader) -> &ProfileExts {
+		match loader {
+			AddonLoader::Arcdps => &self.arcdps,
+			AddonLoader::NexusHost => &self.nexus,
+		}
+	}
+
+	pub fn extensions_mut(&mut self, loader: AddonLoader) -> &mut ProfileExts {
+		match loader {
+			AddonLoader::Arcdps => &mut self.arcdps,
+			AddonLoader::NexusHost => &mut self.nexus,
+		}
+	}
+}
+
+impl ProfileExts {
+	pub fn with_settings(enabled: BTreeSet<String>, settings: &ExtSettings) -> Self {
+		Self {
+			enabled,
+			autoload: settings.autoload.clone(),
+			blacklist: settings.blacklist.iter()
+				// quarantine is about the binary, not the profile
+				.filter(|key| settings.quarantined(key).is_none())
+				.cloned()
+				.collect(),
+		}
+	}
+
+	/// Replace the options in `settings` with ours
+	pub fn apply_to(&self, settings: &mut ExtSettings) -> bool {
+		let mut blacklist = self.blacklist.clone();
+		blacklist.extend(settings.quarantine.keys().cloned());
+
+		let changed = settings.autoload != self.autoload || settings.blacklist != blacklist;
+		settings.autoload = self.autoload.clone();
+		settings.blacklist = blacklist;
+		changed
+	}
+}
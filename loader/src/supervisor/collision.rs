@@ -0,0 +1,153 @@
+use crate::{extensions::AddonLoader, settings::{settings_key, Settings}, util::{arc::remove_extension, win::WinError}};
+use super::{ExtImage, Supervisor, SUPERVISOR};
+use std::{fmt, num::NonZeroU32, path::{Path, PathBuf}};
+use windows::Win32::Foundation::{ERROR_ALREADY_EXISTS, HMODULE};
+
+/// Two extensions that can't both be loaded, so the later one was refused
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtCollision {
+	pub kind: CollisionKind,
+	/// Whatever got there first, and is staying loaded
+	pub existing: String,
+	/// Where the refused extension was loaded from
+	pub rejected: PathBuf,
+	pub rejected_loader: AddonLoader,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CollisionKind {
+	/// Both claim the same signature
+	Signature(u32),
+	/// The same DLL, hosted by both arcdps and nexus
+	SameFile,
+}
+
+impl ExtCollision {
+	pub fn to_error(&self) -> WinError {
+		WinError::new(ERROR_ALREADY_EXISTS.to_hresult(), self.to_string())
+	}
+}
+
+impl fmt::Display for ExtCollision {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let name = self.rejected.file_name()
+			.unwrap_or(self.rejected.as_os_str())
+			.to_string_lossy();
+		match self.kind {
+			CollisionKind::Signature(sig) =>
+				write!(f, "{name} claims signature {sig:08x}, which already belongs to {}", self.existing),
+			CollisionKind::SameFile =>
+				write!(f, "{name} is already hosted as {}", self.existing),
+		}
+	}
+}
+
+impl Supervisor {
+	/// Remember a refusal, so it can be shown next to the extensions table
+	pub fn record_collision(collision: ExtCollision) {
+		if let Ok(mut sv) = SUPERVISOR.write() {
+			sv.push_collision(collision);
+		}
+	}
+
+	pub fn push_collision(&mut self, collision: ExtCollision) {
+		warn!("refusing to load: {collision}");
+		if !self.collisions.contains(&collision) {
+			self.collisions.push(collision);
+		}
+	}
+
+	pub fn dismiss_collisions() {
+		if let Ok(mut sv) = SUPERVISOR.write() {
+			sv.collisions.clear();
+		}
+	}
+
+	/// Whether a nexus addon would clash with an arcdps extension we already know of
+	pub fn arcdps_collision(&self, sig: u32, path: Option<&Path>) -> Option<(CollisionKind, String)> {
+		let key = path.and_then(settings_key);
+		self.arcdps.values().find_map(|ext| {
+			let kind = match () {
+				_ if ext.sig.get() == sig =>
+					CollisionKind::Signature(sig),
+				_ if key.is_some() && ext.path.as_deref().and_then(settings_key) == key =>
+					CollisionKind::SameFile,
+				_ => return None,
+			};
+			Some((kind, format!("{} (arcdps)", ext.name)))
+		})
+	}
+
+	/// Whether a module about to be handed to arcdps is already hosted as a nexus addon
+	#[cfg_attr(not(feature = "host-addonapi"), allow(unused_variables))]
+	pub fn nexus_collision(module: HMODULE, path: Option<&Path>) -> Option<(CollisionKind, String)> {
+		#[cfg(feature = "host-addonapi")] {
+			use crate::{extensions::original_module_path, host::addonapi::NexusHost};
+
+			let key = path.and_then(settings_key);
+			let host = NexusHost::lock_read();
+			return host.addons.values()
+				.find(|addon| addon.module() == module || (key.is_some() && original_module_path(addon.module()).ok().as_deref().and_then(settings_key) == key))
+				.map(|addon| (CollisionKind::SameFile, format!("{addon} (nexus)")))
+		}
+
+		#[allow(unreachable_code)]
+		None
+	}
+
+	/// arcdps can't tell us an extension's signature until it's already loaded,
+	/// so one that clashes with a nexus addon gets unloaded again straight away
+	pub(super) fn refuse_nexus_sig_collision(&mut self, sig: NonZeroU32) {
+		if sig == crate::export::arcdps::SIG {
+			return
+		}
+		let existing = match Self::nexus_sig_collision(sig.get()) {
+			Some(existing) => existing,
+			None => return,
+		};
+		let ext = match self.arcdps.remove(&sig) {
+			Some(ext) => ext,
+			None => return,
+		};
+		if let Err(()) = remove_extension(sig) {
+			warn!("failed to remove {}, leaving it loaded", ext.name);
+			self.arcdps.insert(sig, ext);
+			return
+		}
+		Self::arcdps_released(&ext);
+
+		self.push_collision(ExtCollision {
+			kind: CollisionKind::Signature(sig.get()),
+			existing,
+			rejected: ext.path.clone().unwrap_or_else(|| PathBuf::from(&ext.name)),
+			rejected_loader: AddonLoader::Arcdps,
+		});
+	}
+
+	/// Whether an arcdps extension's signature is already taken by a nexus addon
+	#[cfg_attr(not(feature = "host-addonapi"), allow(unused_variables))]
+	pub fn nexus_sig_collision(sig: u32) -> Option<String> {
+		#[cfg(feature = "host-addonapi")] {
+			use crate::host::addonapi::NexusHost;
+
+			return NexusHost::lock_read().addons.get(&(sig as i32))
+				.map(|addon| format!("{addon} (nexus)"))
+		}
+
+		#[allow(unreachable_code)]
+		None
+	}
+}
+
+impl ExtImage {
+	/// Which loader should host an extension, taking the user's choice into account
+	/// when it exports entry points for both
+	pub fn preferred_loader(&self, path: &Path) -> Option<AddonLoader> {
+		match self.dual_host {
+			true => settings_key(path)
+				.and_then(|key| Settings::lock_read().hosts.get(&key).copied())
+				.or(self.loader),
+			false => self.loader,
+		}
+	}
+}
@@ -1,12 +1,25 @@
-use crate::util::{arc::{config_dir, game_dir}, win::WinResult};
-use std::{collections::{BTreeMap, BTreeSet}, ffi::OsStr, fs, io, iter, num::NonZeroU32, ops::Deref, os::windows::fs::FileTypeExt, path::{Path, PathBuf}, sync::{Arc, RwLock}};
+use crate::{extensions::{original_module_path, AddonLoader, FileHash, Loader, LoaderCommand, TrustStore}, settings::{settings_key, ExtCache, Settings}, util::{arc::{config_dir, game_dir}, glob::glob_match, win::{pe::{PeInfo, VersionInfo}, WinResult}}};
+use std::{collections::{BTreeMap, BTreeSet}, fs, io, iter, num::NonZeroU32, ops::Deref, os::windows::fs::FileTypeExt, path::{Path, PathBuf}, sync::{Arc, RwLock, RwLockWriteGuard}};
 use arcdps::exports;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 #[cfg(feature = "arcdps-extras")]
 use arcdps::extras::{ExtrasAddonInfo, UserInfoIter};
-use windows::{core::Error as WinError, Win32::Foundation::{ERROR_INDEX_OUT_OF_BOUNDS, ERROR_INVALID_HANDLE, ERROR_THREAD_WAS_SUSPENDED, HMODULE}};
+use windows::{core::Error as WinError, Win32::{Foundation::{ERROR_INDEX_OUT_OF_BOUNDS, ERROR_INVALID_HANDLE, ERROR_THREAD_WAS_SUSPENDED, HMODULE}, System::SystemInformation::{IMAGE_FILE_MACHINE, IMAGE_FILE_MACHINE_AMD64, IMAGE_FILE_MACHINE_ARM64, IMAGE_FILE_MACHINE_I386}}};
 
 use crate::util::{arc::ArcDpsExtensionRef, win::get_module_path};
 
+mod collision;
+mod profile;
+mod safe_mode;
+mod watcher;
+mod worker;
+pub use self::collision::{CollisionKind, ExtCollision};
+pub use self::profile::{ProfileOutcome, ProfileSwitch};
+pub use self::safe_mode::{SafeMode, SafeModeReason};
+pub use self::watcher::{ExtChanges, ExtWatcher};
+pub use self::worker::{SupervisorProgress, SupervisorResult, SupervisorStatus, SupervisorWorker, SUPERVISOR_STATUS};
+
 pub static SUPERVISOR: RwLock<Supervisor> = RwLock::new(Supervisor::empty());
 
 #[derive(Clone, Debug)]
@@ -15,9 +28,26 @@ pub enum SupervisorCommand {
 	RefreshExternal,
 }
 
+/// A directory searched for extensions that aren't loaded yet
 #[derive(Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
 pub struct ExtDir {
 	pub path: PathBuf,
+	/// How many levels of subdirectories to descend into
+	pub depth: u32,
+	/// Only consider file names matching this pattern (see [glob_match]),
+	/// rather than requiring an `arcdps_`/`nexus_` infix
+	pub include: Option<String>,
+	/// Ignore file names matching this pattern
+	pub exclude: Option<String>,
+	/// Loader to use when it can't be guessed from the file name
+	pub loader: Option<AddonLoader>,
+}
+
+impl Default for ExtDir {
+	fn default() -> Self {
+		Self::new(PathBuf::new())
+	}
 }
 
 impl ExtDir {
@@ -31,40 +61,74 @@ impl ExtDir {
 
 		Self {
 			path,
+			depth: 0,
+			include: None,
+			exclude: None,
+			loader: None,
+		}
+	}
+
+	pub fn accepts(&self, fname: &str) -> bool {
+		if !Path::new(fname).extension().map(|ext| ext.eq_ignore_ascii_case(Self::EXTENSION)).unwrap_or(false) {
+			return false
+		}
+
+		if let Some(exclude) = &self.exclude {
+			if glob_match(exclude, fname) {
+				return false
+			}
+		}
+
+		match &self.include {
+			Some(include) => glob_match(include, fname),
+			None => AddonLoader::for_path(Path::new(fname)).is_some(),
 		}
 	}
 
 	pub fn enumerate_extensions(&self) -> io::Result<Vec<ExtDisk>> {
 		let mut files = Vec::new();
+		self.enumerate_into(&self.path, self.depth, &mut files)?;
+		Ok(files)
+	}
 
-		for f in fs::read_dir(&self.path)? {
+	fn enumerate_into(&self, dir: &Path, depth: u32, files: &mut Vec<ExtDisk>) -> io::Result<()> {
+		for f in fs::read_dir(dir)? {
 			let f = match f {
-				Ok(f) if !f.file_type().map(|ft| ft.is_file() || ft.is_symlink_file()).unwrap_or(false) =>
-					continue,
 				Ok(f) => f,
 				Err(_e) => {
-					warn!("failed to enumerate {}: {}", self.path.display(), _e);
+					warn!("failed to enumerate {}: {}", dir.display(), _e);
 					continue
 				},
 			};
-			let fname = f.file_name();
+			let path = dir.join(f.file_name());
 
-			if Path::new(&fname).extension() != Some(OsStr::new(Self::EXTENSION)) {
-				continue
+			match f.file_type() {
+				Ok(ft) if ft.is_dir() && depth > 0 => {
+					if let Err(_e) = self.enumerate_into(&path, depth - 1, files) {
+						warn!("failed to enumerate {}: {}", path.display(), _e);
+					}
+					continue
+				},
+				Ok(ft) if ft.is_file() || ft.is_symlink_file() => (),
+				_ => continue,
 			}
 
-			match f.file_name().as_os_str().to_str() {
-				Some(s) if s.contains(Self::INFIX_ARCDPS) => (),
-				#[cfg(feature = "host-addonapi")]
-				Some(s) if s.contains(Self::INFIX_NEXUS) => (),
+			match f.file_name().to_str() {
+				Some(fname) if self.accepts(fname) => (),
 				_ => continue,
 			}
+
+			let loader = AddonLoader::for_path(&path)
+				.or(self.loader);
 			files.push(ExtDisk {
-				path: self.path.join(fname)
+				path,
+				loader,
+				image: None,
+				hash: None,
 			});
 		}
 
-		Ok(files)
+		Ok(())
 	}
 }
 
@@ -109,8 +173,8 @@ impl ExtArcDesc {
 			sig: ext.sig(),
 			name: ext.name().to_string_lossy().into_owned(),
 			build: ext.build().to_string_lossy().into_owned(),
-			path: match get_module_path(Some(ext.module())) {
-				Ok(p) => Some(PathBuf::from(p)),
+			path: match original_module_path(ext.module()) {
+				Ok(p) => Some(p),
 				Err(_e) => {
 					warn!("no path found for arcdps extension {}: {}", ext, _e);
 					None
@@ -120,9 +184,113 @@ impl ExtArcDesc {
 	}
 }
 
+impl ExtArcDesc {
+	pub fn to_cache(&self) -> ExtCache {
+		ExtCache {
+			sig: Some(self.sig),
+			name: self.name.clone(),
+			version: match self.build.is_empty() {
+				true => None,
+				false => Some(self.build.clone()),
+			},
+			path: self.path.clone(),
+		}
+	}
+}
+
 #[derive(Clone, Debug)]
 pub struct ExtDisk {
 	pub path: PathBuf,
+	/// Decided by its exports if possible,
+	/// otherwise guessed from the file name or configured by its [ExtDir]
+	pub loader: Option<AddonLoader>,
+	/// See [ExtDisk::inspect]
+	pub image: Option<ExtImage>,
+	/// SHA-256 of the file as of the last scan,
+	/// only taken if something was loaded from it
+	pub hash: Option<FileHash>,
+}
+
+/// What an extension's PE headers say about it, without loading it
+#[derive(Clone, Debug)]
+pub struct ExtImage {
+	pub machine: IMAGE_FILE_MACHINE,
+	pub timestamp: u32,
+	/// Decided by which entry points it exports
+	pub loader: Option<AddonLoader>,
+	/// Exports entry points for both arcdps and nexus,
+	/// see [ExtImage::preferred_loader]
+	pub dual_host: bool,
+	pub version: Option<VersionInfo>,
+}
+
+impl ExtDisk {
+	/// Fill in [ExtDisk::image] and [ExtDisk::hash] by reading the file
+	pub fn inspect(&mut self) {
+		// only needed to tell whether what's loaded is stale
+		if TrustStore::loaded_hash(&self.path).is_some() {
+			match FileHash::of_file(&self.path) {
+				Ok(hash) => self.hash = Some(hash),
+				Err(_e) => debug!("failed to hash {}: {_e}", self.path.display()),
+			}
+		}
+
+		match ExtImage::read(&self.path) {
+			Ok(image) => {
+				if let Some(loader) = image.preferred_loader(&self.path) {
+					self.loader = Some(loader);
+				}
+				self.image = Some(image);
+			},
+			Err(_e) => {
+				debug!("failed to inspect {}: {_e}", self.path.display());
+			},
+		}
+	}
+
+	pub fn version(&self) -> Option<&VersionInfo> {
+		self.image.as_ref()
+			.and_then(|image| image.version.as_ref())
+	}
+
+	/// Only false if we know for sure it can't be loaded into the game
+	pub fn is_compatible(&self) -> bool {
+		self.image.as_ref()
+			.map(|image| image.is_compatible())
+			.unwrap_or(true)
+	}
+}
+
+impl ExtImage {
+	pub fn read(path: &Path) -> WinResult<Self> {
+		PeInfo::read(path)
+			.map(|info| Self::with_info(&info))
+	}
+
+	pub fn with_info(info: &PeInfo) -> Self {
+		Self {
+			machine: info.machine,
+			timestamp: info.timestamp,
+			loader: AddonLoader::for_exports(info),
+			dual_host: cfg!(feature = "host-addonapi")
+				&& info.has_export(AddonLoader::SYM_ARCDPS)
+				&& info.has_export(AddonLoader::SYM_NEXUS),
+			version: info.version.clone(),
+		}
+	}
+
+	pub fn is_compatible(&self) -> bool {
+		self.machine == IMAGE_FILE_MACHINE_AMD64
+	}
+
+	pub fn machine_name(&self) -> String {
+		match self.machine {
+			IMAGE_FILE_MACHINE_AMD64 => "x64".into(),
+			IMAGE_FILE_MACHINE_I386 => "x86".into(),
+			IMAGE_FILE_MACHINE_ARM64 => "arm64".into(),
+			IMAGE_FILE_MACHINE(machine) => format!("machine {machine:04x}"),
+		}
+	}
 }
 
 #[derive(Debug)]
@@ -130,10 +298,30 @@ pub struct Supervisor {
 	pub arcdps: BTreeMap<NonZeroU32, ExtArc>,
 	pub external: Vec<Arc<ExtDisk>>,
 	pub arcdps_dirty: bool,
+	pub autoload_pending: bool,
+	/// [Supervisor::external] has been filled in at least once,
+	/// so autoload has to wait for it
+	pub external_scanned: bool,
+	/// Extensions refused for clashing with one already loaded
+	pub collisions: Vec<ExtCollision>,
 }
 
-/// TODO: this into settings!
+/// Search dirs from settings, along with the defaults unless disabled
 pub fn external_dirs() -> BTreeSet<ExtDir> {
+	let settings = Settings::lock_read();
+	let mut dirs: BTreeSet<ExtDir> = settings.dirs.iter()
+		.filter(|dir| !dir.path.as_os_str().is_empty())
+		.cloned()
+		.collect();
+
+	if settings.default_dirs {
+		dirs.extend(default_dirs());
+	}
+
+	dirs
+}
+
+pub fn default_dirs() -> BTreeSet<ExtDir> {
 	let mut dirs = BTreeSet::new();
 
 	if let Some(arcdps) = config_dir() {
@@ -164,25 +352,51 @@ impl Supervisor {
 			arcdps: BTreeMap::new(),
 			external: Vec::new(),
 			arcdps_dirty: true,
+			autoload_pending: false,
+			external_scanned: false,
+			collisions: Vec::new(),
 		}
 	}
 
 	pub fn init() {
-		// TODO: try scheduling updates for later
-		// sv.refresh_external();
+		Settings::init();
+
+		let mut sv = match SUPERVISOR.write() {
+			Ok(s) => s,
+			Err(_e) => return,
+		};
+		// arcdps isn't ready to add extensions while it's still busy initializing us,
+		// and autoload waits for the scan below anyway
+		sv.autoload_pending = true;
+		drop(sv);
+
+		ExtWatcher::init();
+		if let Err(_e) = SupervisorWorker::start() {
+			error!("failed to start supervisor worker, commands will block: {_e}");
+		}
+		// also starts watching whatever it finds
+		let _ = Self::send_command(SupervisorCommand::RefreshExternal);
 	}
 
 	pub fn imgui_present() {
 		Self::dirty_update();
+		Self::autoload_update();
 	}
 
 	pub fn unload() {
+		// the worker may still have a refresh queued that would restart the watcher
+		SupervisorWorker::stop();
+		ExtWatcher::stop();
+
 		let mut sv = match SUPERVISOR.write() {
 			Ok(s) => s,
 			Err(e) => return,
 		};
 
 		sv.shutdown();
+		drop(sv);
+
+		Settings::unload();
 	}
 
 	pub fn dirty_update() {
@@ -201,11 +415,60 @@ impl Supervisor {
 		}
 	}
 
+	/// Whether the first scan of [Supervisor::external] has finished
+	pub fn external_scanned() -> bool {
+		SUPERVISOR.try_read().ok()
+			.map(|sv| sv.external_scanned)
+			.unwrap_or(false)
+	}
+
+	pub fn autoload_update() {
+		let pending = SUPERVISOR.try_read().ok()
+			.map(|sv| sv.autoload_pending && sv.external_scanned);
+		if pending != Some(true) {
+			return
+		}
+		let paths = match SUPERVISOR.try_write() {
+			Ok(mut sv) => {
+				sv.autoload_pending = false;
+				sv.autoload_paths(AddonLoader::Arcdps)
+			},
+			_ => return,
+		};
+
+		for path in paths {
+			info!("autoloading {}", path.display());
+			let _id = Loader::queue_command(LoaderCommand::LoadPath { path: path.as_path().into(), loader: Some(AddonLoader::Arcdps) });
+		}
+	}
+
+	/// External extensions that settings would like loaded with `loader`,
+	/// unless we're in [SafeMode]
+	pub fn autoload_paths(&self, loader: AddonLoader) -> Vec<PathBuf> {
+		if SafeMode::is_active() {
+			return Vec::new()
+		}
+
+		let settings = Settings::lock_read();
+		let settings = settings.extensions(loader);
+
+		let mut seen = BTreeSet::new();
+		self.external.iter()
+			.filter(|ext| ext.loader == Some(loader) && ext.is_compatible())
+			.filter_map(|ext| settings_key(&ext.path).map(|key| (key, ext)))
+			.filter(|(key, _)| settings.should_autoload(key))
+			// the same file name may appear in more than one search dir
+			.filter(|(key, _)| seen.insert(key.clone()))
+			.map(|(_, ext)| ext.path.clone())
+			.collect()
+	}
+
 	pub fn refresh_arcdps(&mut self) {
 		if !exports::has_list_extension() {
 			return
 		}
 
+		let mut extensions_new = Vec::new();
 		let extensions_unloaded = {
 			let mut extensions_unloaded: BTreeSet<NonZeroU32> = self.arcdps.keys().copied().collect();
 
@@ -218,7 +481,10 @@ impl Supervisor {
 				}
 				extensions_unloaded.remove(&ext.sig());
 				extensions.entry(ext.sig())
-					.or_insert_with(|| ExtArc::with_extension(ext));
+					.or_insert_with(|| {
+						extensions_new.push(ext.sig());
+						ExtArc::with_extension(ext)
+					});
 			};
 
 			exports::list_extension(&mut ext);
@@ -226,40 +492,83 @@ impl Supervisor {
 			extensions_unloaded
 		};
 
+		for sig in extensions_new {
+			self.refuse_nexus_sig_collision(sig);
+		}
+
 		for sig in extensions_unloaded {
-			if let Some(_ext) = self.arcdps.remove(&sig) {
-				info!("lost track of arcdps extension {}", _ext.desc.name);
+			if let Some(ext) = self.arcdps.remove(&sig) {
+				info!("lost track of arcdps extension {}", ext.desc.name);
+				Self::arcdps_released(&ext);
 			} else {
 				warn!("what happened to arcdps extension {:08x}?", sig);
 			}
 		}
 
 		self.arcdps_dirty = false;
+
+		Settings::update_with(|settings| {
+			let mut changed = false;
+			for ext in self.arcdps.values() {
+				let key = match ext.path.as_ref().and_then(|p| settings_key(p)) {
+					Some(key) => key,
+					None => continue,
+				};
+				changed |= settings.arcdps.update_cache(&key, ext.desc.to_cache());
+			}
+			changed
+		});
 	}
 
-	pub fn refresh_external(&mut self) {
-		let extensions = external_dirs().into_iter()
-			.flat_map(|dir| match dir.enumerate_extensions() {
-				Ok(dir) => Some(dir),
-				Err(_e) => {
-					error!("failed to enumerate {}: {_e}", dir.path.display());
-					None
+	pub fn refresh_external(&mut self, dirs: &BTreeSet<ExtDir>) {
+		let (extensions, _errors) = Self::scan_external(dirs, |_, _| ());
+		self.external = extensions;
+		self.external_scanned = true;
+	}
+
+	/// Enumerate every search dir, without needing the supervisor lock
+	pub fn scan_external<F: FnMut(usize, usize)>(dirs: &BTreeSet<ExtDir>, mut progress: F) -> (Vec<Arc<ExtDisk>>, Vec<String>) {
+		let total = dirs.len();
+
+		let mut extensions = Vec::new();
+		let mut errors = Vec::new();
+		for (i, dir) in dirs.iter().enumerate() {
+			progress(i, total);
+			match dir.enumerate_extensions() {
+				Ok(found) => extensions.extend(found.into_iter().map(|mut ext| {
+					ext.inspect();
+					Arc::new(ext)
+				})),
+				Err(e) => {
+					error!("failed to enumerate {}: {e}", dir.path.display());
+					errors.push(format!("{}: {e}", dir.path.display()));
 				},
-			}).flatten();
+			}
+		}
 
-		self.external.clear();
-		self.external.extend(extensions.map(Arc::new));
+		(extensions, errors)
 	}
 
 	pub fn shutdown(&mut self) {
 		self.arcdps.clear();
 		self.external.clear();
+		self.external_scanned = false;
 	}
 
-	/// TODO: channel to another thread!
+	/// Queue `cmd` for the worker, or run it right here if there isn't one
 	pub fn send_command(cmd: SupervisorCommand) -> Result<(), ()> {
-		let mut sv = match SUPERVISOR.write() {
-			Ok(s) => s,
+		match SupervisorWorker::send(cmd) {
+			Ok(()) => Ok(()),
+			Err(cmd) => {
+				Self::execute_command(cmd);
+				Ok(())
+			},
+		}
+	}
+
+	fn lock_write() -> Result<RwLockWriteGuard<'static, Self>, ()> {
+		match SUPERVISOR.write() {
+			Ok(s) => Ok(s),
 			Err(_e) => {
 				warn!("supervisor poisoned, providing antidote...");
 				SUPERVISOR.clear_poison();
@@ -268,20 +577,45 @@ impl Supervisor {
 					.map_err(drop)?;
 				sv.arcdps.clear();
 				sv.external.clear();
-				sv
+				Ok(sv)
 			},
-		};
-
-		sv.handle_command(cmd);
-		Ok(())
+		}
 	}
 
-	pub fn handle_command(&mut self, cmd: SupervisorCommand) {
+	/// Returns a summary for the UI, if the command has anything to report
+	pub fn execute_command(cmd: SupervisorCommand) -> Option<Result<String, String>> {
 		match cmd {
-			SupervisorCommand::RefreshArcdps =>
-				self.refresh_arcdps(),
-			SupervisorCommand::RefreshExternal =>
-				self.refresh_external(),
+			SupervisorCommand::RefreshArcdps => {
+				// arcdps' extension list belongs to the render thread,
+				// so leave it for the next `dirty_update`
+				Self::lock_write().ok()?.arcdps_dirty = true;
+				None
+			},
+			SupervisorCommand::RefreshExternal => {
+				let dirs = external_dirs();
+				let (extensions, errors) = Self::scan_external(&dirs, |done, total| {
+					SupervisorStatus::set_progress(&cmd, done, total)
+				});
+				let found = extensions.len();
+				// search dirs may have changed since we last looked
+				ExtWatcher::update(&dirs);
+				#[cfg(feature = "host-addonapi")] {
+					crate::host::addonapi::CoresidentNexus::refresh();
+				}
+
+				match Self::lock_write() {
+					Ok(mut sv) => {
+						sv.external = extensions;
+						sv.external_scanned = true;
+					},
+					Err(()) => return Some(Err("supervisor unavailable".into())),
+				}
+
+				Some(match errors.is_empty() {
+					true => Ok(format!("found {found} extensions")),
+					false => Err(errors.join("\n")),
+				})
+			},
 		}
 	}
 
@@ -290,13 +624,25 @@ impl Supervisor {
 			Ok(s) => s,
 			Err(_e) => return Err(ERROR_THREAD_WAS_SUSPENDED.into()),
 		};
-		match (sv.arcdps.remove(&sig), dead_module) {
+		let ext = sv.arcdps.remove(&sig);
+		if let Some(ext) = &ext {
+			Self::arcdps_released(ext);
+		}
+		match (ext, dead_module) {
 			(None, _) => Err(ERROR_INDEX_OUT_OF_BOUNDS.into()),
 			(Some(ext), Some(module)) if ext.module != module =>
 				Err(WinError::new(ERROR_INVALID_HANDLE.to_hresult(), format!("expected handle {:?}, got {module:?}", ext.module))),
 			(Some(..), _) => Ok(()),
 		}
 	}
+
+	/// Drop anything the extension registered with us while it was loaded
+	#[cfg_attr(not(feature = "host-addonapi"), allow(unused_variables))]
+	fn arcdps_released(ext: &ExtArc) {
+		#[cfg(feature = "host-addonapi")] {
+			crate::host::addonapi::NexusHost::guest_release(ext.module);
+		}
+	}
 }
 
 fn get_module_name(handle: HMODULE) -> Arc<str> {
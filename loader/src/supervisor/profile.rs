@@ -0,0 +1,309 @@
+use crate::{extensions::{AddonLoader, Loader, LoaderCommand, LoaderQueue, LoaderRequestId, LoaderStatus}, settings::{settings_key, Profile, ProfileExts, Settings}};
+use super::{SafeMode, Supervisor, SUPERVISOR};
+use std::{collections::{BTreeMap, BTreeSet}, fmt, num::NonZeroU32, path::PathBuf};
+use arcdps::exports;
+#[cfg(feature = "host-addonapi")]
+use crate::util::nexus::NexusId;
+
+/// Everything that has to happen to get from what's currently loaded to a [Profile]
+#[derive(Debug, Default)]
+pub struct ProfileSwitch {
+	pub name: String,
+	pub arcdps_unload: Vec<NonZeroU32>,
+	pub arcdps_load: Vec<PathBuf>,
+	/// Enumerated already, just not loaded
+	#[cfg(feature = "host-addonapi")]
+	pub nexus_load: Vec<NexusId>,
+	#[cfg(feature = "host-addonapi")]
+	pub nexus_load_paths: Vec<PathBuf>,
+	#[cfg(feature = "host-addonapi")]
+	pub nexus_unload: Vec<NexusId>,
+	/// Loaded, not part of the profile, and unable to leave until the game restarts
+	pub stuck: Vec<String>,
+	/// Part of the profile, but nowhere to be found
+	pub missing: Vec<String>,
+}
+
+/// How a [ProfileSwitch] went
+#[derive(Clone, Debug, Default)]
+pub struct ProfileOutcome {
+	pub name: String,
+	pub loaded: usize,
+	pub unloaded: usize,
+	pub failed: usize,
+	/// Loads that [SafeMode] kept from happening
+	pub held_back: usize,
+	pub stuck: Vec<String>,
+	pub missing: Vec<String>,
+	/// Queued requests that haven't finished yet, and whether they were loads
+	pub pending: Vec<(LoaderRequestId, bool)>,
+}
+
+impl Supervisor {
+	/// arcdps extensions we could unload, by [settings_key]
+	pub fn arcdps_loaded(&self) -> BTreeMap<String, NonZeroU32> {
+		self.arcdps.values()
+			.filter(|ext| ext.sig != crate::export::arcdps::SIG)
+			.filter_map(|ext| Some((settings_key(ext.path.as_deref()?)?, ext.sig)))
+			.collect()
+	}
+
+	/// Where to find an extension that isn't loaded yet
+	pub fn external_path(&self, loader: AddonLoader, key: &str) -> Option<PathBuf> {
+		self.external.iter()
+			.filter(|ext| ext.loader == Some(loader) && ext.is_compatible())
+			.find(|ext| settings_key(&ext.path).as_deref() == Some(key))
+			.map(|ext| ext.path.clone())
+	}
+
+	/// Record what's loaded right now, along with its options
+	pub fn capture_profile(&self) -> Profile {
+		let settings = Settings::lock_read();
+		let arcdps = self.arcdps_loaded().into_keys().collect();
+
+		#[cfg_attr(not(feature = "host-addonapi"), allow(unused_mut))]
+		let mut nexus = BTreeSet::new();
+		#[cfg(feature = "host-addonapi")] {
+			use crate::{extensions::original_module_path, host::addonapi::NexusHost};
+
+			nexus.extend(NexusHost::lock_read().addons.values()
+				.filter(|addon| addon.is_loaded())
+				.filter_map(|addon| settings_key(&original_module_path(addon.module()).ok()?))
+			);
+		}
+
+		Profile {
+			arcdps: ProfileExts::with_settings(arcdps, &settings.arcdps),
+			nexus: ProfileExts::with_settings(nexus, &settings.nexus),
+		}
+	}
+
+	/// Save what's loaded right now as `name`, replacing any existing profile
+	pub fn save_profile(name: &str) -> bool {
+		let profile = match SUPERVISOR.read() {
+			Ok(sv) => sv.capture_profile(),
+			Err(_e) => return false,
+		};
+		Settings::update_with(|settings| {
+			settings.profiles.insert(name.into(), profile);
+			settings.profile = Some(name.into());
+			true
+		})
+	}
+
+	pub fn delete_profile(name: &str) -> bool {
+		Settings::update_with(|settings| {
+			if settings.profile.as_deref() == Some(name) {
+				settings.profile = None;
+			}
+			settings.profiles.remove(name).is_some()
+		})
+	}
+
+	/// Load and unload whatever it takes to match the profile called `name`
+	///
+	/// Must be called from the render thread, like any other nexus load.
+	pub fn switch_profile(name: &str) -> Option<ProfileOutcome> {
+		let profile = Settings::lock_read().profiles.get(name).cloned()?;
+		let switch = {
+			let sv = SUPERVISOR.read().ok()?;
+			ProfileSwitch::plan(&sv, name, &profile)
+		};
+
+		Settings::update_with(|settings| {
+			let mut changed = profile.arcdps.apply_to(&mut settings.arcdps);
+			changed |= profile.nexus.apply_to(&mut settings.nexus);
+			if settings.profile.as_deref() != Some(name) {
+				settings.profile = Some(name.into());
+				changed = true;
+			}
+			changed
+		});
+
+		Some(switch.execute())
+	}
+}
+
+impl ProfileSwitch {
+	pub fn plan(sv: &Supervisor, name: &str, profile: &Profile) -> Self {
+		let mut switch = Self {
+			name: name.into(),
+			..Default::default()
+		};
+
+		let arcdps_loaded = sv.arcdps_loaded();
+		for (key, &sig) in &arcdps_loaded {
+			if profile.arcdps.enabled.contains(key) {
+				continue
+			}
+			match exports::has_remove_extension() {
+				true => switch.arcdps_unload.push(sig),
+				false => switch.stuck.push(key.clone()),
+			}
+		}
+		for key in &profile.arcdps.enabled {
+			if arcdps_loaded.contains_key(key) {
+				continue
+			}
+			match sv.external_path(AddonLoader::Arcdps, key) {
+				Some(path) => switch.arcdps_load.push(path),
+				None => switch.missing.push(key.clone()),
+			}
+		}
+
+		#[cfg(feature = "host-addonapi")] {
+			use crate::{extensions::original_module_path, host::addonapi::NexusHost};
+
+			let host = NexusHost::lock_read();
+			let mut enumerated = BTreeSet::new();
+			for addon in host.addons.values() {
+				let key = match original_module_path(addon.module()).ok().and_then(|path| settings_key(&path)) {
+					Some(key) => key,
+					None => continue,
+				};
+				let enabled = profile.nexus.enabled.contains(&key);
+				match (addon.is_loaded(), enabled) {
+					(true, false) if addon.can_hotload() =>
+						switch.nexus_unload.push(addon.signature),
+					(true, false) =>
+						switch.stuck.push(key.clone()),
+					(false, true) =>
+						switch.nexus_load.push(addon.signature),
+					_ => (),
+				}
+				enumerated.insert(key);
+			}
+			for key in profile.nexus.enabled.difference(&enumerated) {
+				match sv.external_path(AddonLoader::NexusHost, key) {
+					Some(path) => switch.nexus_load_paths.push(path),
+					None => switch.missing.push(key.clone()),
+				}
+			}
+		}
+
+		switch
+	}
+
+	pub fn execute(self) -> ProfileOutcome {
+		info!("switching to profile {}", self.name);
+
+		let mut outcome = ProfileOutcome {
+			name: self.name,
+			stuck: self.stuck,
+			missing: self.missing,
+			..Default::default()
+		};
+
+		// unloads go first, in case something in the new profile conflicts with the old
+		for sig in self.arcdps_unload {
+			let id = Loader::queue_command(LoaderCommand::Unload { sig });
+			outcome.pending.push((id, false));
+		}
+
+		let safe_mode = SafeMode::is_active();
+		if safe_mode {
+			#[cfg_attr(not(feature = "host-addonapi"), allow(unused_mut))]
+			let mut held_back = self.arcdps_load.len();
+			#[cfg(feature = "host-addonapi")] {
+				held_back += self.nexus_load.len() + self.nexus_load_paths.len();
+			}
+			if held_back > 0 {
+				info!("safe mode is holding back {held_back} loads");
+			}
+			outcome.held_back = held_back;
+		}
+
+		for path in self.arcdps_load.into_iter().filter(|_| !safe_mode) {
+			let id = Loader::queue_command(LoaderCommand::LoadPath { path: path.as_path().into(), loader: Some(AddonLoader::Arcdps) });
+			outcome.pending.push((id, true));
+		}
+
+		#[cfg(feature = "host-addonapi")] {
+			use crate::host::addonapi::NexusHost;
+
+			for sig in self.nexus_unload {
+				match NexusHost::unload_addon(sig) {
+					Ok(()) => outcome.unloaded += 1,
+					Err(_e) => outcome.failed += 1,
+				}
+			}
+			for sig in self.nexus_load.into_iter().filter(|_| !safe_mode) {
+				match NexusHost::load_addon(sig) {
+					Ok(()) => outcome.loaded += 1,
+					Err(_e) => outcome.failed += 1,
+				}
+			}
+			for path in self.nexus_load_paths.into_iter().filter(|_| !safe_mode) {
+				match NexusHost::autoload_path(&path) {
+					Ok(()) => outcome.loaded += 1,
+					Err(_e) => {
+						error!("failed to load {}: {_e}", path.display());
+						outcome.failed += 1
+					},
+				}
+			}
+		}
+
+		outcome
+	}
+}
+
+impl ProfileOutcome {
+	/// Count whatever queued requests have finished since last time
+	pub fn update(&mut self) {
+		if self.pending.is_empty() {
+			return
+		}
+		let queue = match LoaderQueue::try_lock() {
+			Some(queue) => queue,
+			None => return,
+		};
+
+		let Self { pending, loaded, unloaded, failed, .. } = self;
+		pending.retain(|&(id, load)| {
+			let ok = match queue.request(id).map(|req| &req.status) {
+				Some(LoaderStatus::Pending) => return true,
+				Some(LoaderStatus::Ok) => true,
+				Some(LoaderStatus::Err(..)) => false,
+				// forgotten already, so assume the worst
+				None => false,
+			};
+			match (ok, load) {
+				(true, true) => *loaded += 1,
+				(true, false) => *unloaded += 1,
+				(false, _) => *failed += 1,
+			}
+			false
+		});
+	}
+
+	pub fn is_finished(&self) -> bool {
+		self.pending.is_empty()
+	}
+}
+
+impl fmt::Display for ProfileOutcome {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let switching = match self.is_finished() {
+			true => "switched",
+			false => "switching",
+		};
+		write!(f, "{switching} to {}: {} loaded, {} unloaded", self.name, self.loaded, self.unloaded)?;
+		if !self.pending.is_empty() {
+			write!(f, ", {} pending", self.pending.len())?;
+		}
+		if self.failed > 0 {
+			write!(f, ", {} failed", self.failed)?;
+		}
+		if self.held_back > 0 {
+			write!(f, ", {} held back by safe mode", self.held_back)?;
+		}
+		if !self.stuck.is_empty() {
+			write!(f, ", {} needs a restart", self.stuck.join(", "))?;
+		}
+		if !self.missing.is_empty() {
+			write!(f, ", {} not found", self.missing.join(", "))?;
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,119 @@
+use crate::util::arc::config_dir;
+use std::{env, fmt, fs, io, path::PathBuf, sync::{RwLock, RwLockReadGuard, RwLockWriteGuard}};
+use windows::Win32::UI::Input::KeyboardAndMouse::{GetAsyncKeyState, VK_SHIFT};
+
+static SAFE_MODE: RwLock<SafeMode> = RwLock::new(SafeMode::empty());
+
+/// Enumerate extensions as usual, but don't autoload any of them
+///
+/// A way out when something we'd otherwise load keeps taking the game down.
+#[derive(Debug)]
+pub struct SafeMode {
+	pub reason: Option<SafeModeReason>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SafeModeReason {
+	/// [SafeMode::MARKER_FILE] was found in the arcdps config dir
+	MarkerFile(PathBuf),
+	/// [SafeMode::ENV_VAR] was set
+	Environment,
+	/// [SafeMode::MODIFIER] was held down while we were initializing
+	Modifier,
+}
+
+impl SafeMode {
+	pub const MARKER_FILE: &'static str = "arcloader-safe-mode";
+	pub const ENV_VAR: &'static str = "ARCLOADER_SAFE_MODE";
+	pub const MODIFIER: &'static str = "shift";
+
+	pub const fn empty() -> Self {
+		Self {
+			reason: None,
+		}
+	}
+
+	pub fn lock_read() -> RwLockReadGuard<'static, Self> {
+		SAFE_MODE.read()
+			.unwrap_or_else(|e| e.into_inner())
+	}
+
+	pub fn lock_write() -> RwLockWriteGuard<'static, Self> {
+		SAFE_MODE.write()
+			.unwrap_or_else(|e| e.into_inner())
+	}
+
+	pub fn marker_path() -> Option<PathBuf> {
+		config_dir()
+			.map(|dir| dir.join(Self::MARKER_FILE))
+	}
+
+	/// Decide whether to start up in safe mode, before anything gets autoloaded
+	pub fn init() {
+		let reason = Self::detect();
+		if let Some(_reason) = &reason {
+			warn!("starting in safe mode: {_reason}");
+		}
+		Self::lock_write().reason = reason;
+	}
+
+	fn detect() -> Option<SafeModeReason> {
+		let held = unsafe { GetAsyncKeyState(VK_SHIFT.0 as i32) } as u16 & 0x8000 != 0;
+		if held {
+			return Some(SafeModeReason::Modifier)
+		}
+
+		match env::var_os(Self::ENV_VAR) {
+			Some(v) if !v.is_empty() && v != "0" =>
+				return Some(SafeModeReason::Environment),
+			_ => (),
+		}
+
+		Self::marker_path()
+			.filter(|path| path.try_exists().unwrap_or(false))
+			.map(SafeModeReason::MarkerFile)
+	}
+
+	pub fn is_active() -> bool {
+		Self::lock_read().reason.is_some()
+	}
+
+	pub fn reason() -> Option<SafeModeReason> {
+		Self::lock_read().reason.clone()
+	}
+
+	/// Leave safe mode, and autoload everything that was held back
+	pub fn exit() {
+		let reason = match Self::lock_write().reason.take() {
+			Some(reason) => reason,
+			None => return,
+		};
+		info!("leaving safe mode");
+
+		// otherwise the next session would end up right back here
+		if let SafeModeReason::MarkerFile(path) = &reason {
+			match fs::remove_file(path) {
+				Err(e) if e.kind() != io::ErrorKind::NotFound =>
+					warn!("failed to remove {}: {e}", path.display()),
+				_ => (),
+			}
+		}
+
+		if let Ok(mut sv) = super::SUPERVISOR.write() {
+			sv.autoload_pending = true;
+		}
+		#[cfg(feature = "host-addonapi")] {
+			crate::host::addonapi::NexusHost::lock_write().autoload_pending = true;
+		}
+	}
+}
+
+impl fmt::Display for SafeModeReason {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			SafeModeReason::MarkerFile(path) => write!(f, "found {}", path.display()),
+			SafeModeReason::Environment => write!(f, "{} is set", SafeMode::ENV_VAR),
+			SafeModeReason::Modifier => write!(f, "{} was held", SafeMode::MODIFIER),
+		}
+	}
+}
@@ -0,0 +1,279 @@
+use crate::{settings::Settings, supervisor::{ExtDir, Supervisor, SupervisorCommand}, util::win::WinResult};
+use std::{collections::{BTreeMap, BTreeSet}, fs, path::PathBuf, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex, MutexGuard}, thread::{self, JoinHandle}, time::{Duration, Instant, SystemTime}};
+use windows::Win32::{
+	Foundation::{HANDLE, WAIT_FAILED, WAIT_OBJECT_0},
+	Storage::FileSystem::{FindCloseChangeNotification, FindFirstChangeNotificationW, FindNextChangeNotification, FILE_NOTIFY_CHANGE_FILE_NAME, FILE_NOTIFY_CHANGE_LAST_WRITE, FILE_NOTIFY_CHANGE_SIZE},
+	System::Threading::WaitForMultipleObjects,
+};
+use windows_strings::HSTRING;
+
+static EXT_WATCHER: Mutex<ExtWatcher> = Mutex::new(ExtWatcher::empty());
+
+/// Keeps [Supervisor::external] up to date as files come and go
+#[derive(Debug)]
+pub struct ExtWatcher {
+	dirs: BTreeSet<ExtDir>,
+	stop: Option<Arc<AtomicBool>>,
+	thread: Option<JoinHandle<()>>,
+	/// Set by [stop](Self::stop), so late refreshes don't start it back up
+	shutdown: bool,
+}
+
+/// What changed between two scans
+#[derive(Clone, Debug, Default)]
+pub struct ExtChanges {
+	pub added: Vec<PathBuf>,
+	pub removed: Vec<PathBuf>,
+	pub modified: Vec<PathBuf>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct ExtSnapshot {
+	files: BTreeMap<PathBuf, (Option<SystemTime>, u64)>,
+}
+
+struct ChangeNotification(HANDLE);
+
+impl ChangeNotification {
+	fn new(dir: &ExtDir) -> WinResult<Self> {
+		let filter = FILE_NOTIFY_CHANGE_FILE_NAME | FILE_NOTIFY_CHANGE_LAST_WRITE | FILE_NOTIFY_CHANGE_SIZE;
+		unsafe {
+			FindFirstChangeNotificationW(&HSTRING::from(dir.path.as_path()), dir.depth > 0, filter)
+		}.map(Self)
+	}
+}
+
+impl Drop for ChangeNotification {
+	fn drop(&mut self) {
+		let _ = unsafe { FindCloseChangeNotification(self.0) };
+	}
+}
+
+impl ExtWatcher {
+	const THREAD_NAME: &'static str = "arcloader-watcher";
+	/// How often to check whether we've been asked to stop
+	const POLL_INTERVAL_MS: u32 = 250;
+	/// Builds and copies tend to touch a file several times in a row
+	const SETTLE_TIME: Duration = Duration::from_millis(500);
+	/// MAXIMUM_WAIT_OBJECTS
+	const MAX_DIRS: usize = 64;
+
+	pub const fn empty() -> Self {
+		Self {
+			dirs: BTreeSet::new(),
+			stop: None,
+			thread: None,
+			shutdown: false,
+		}
+	}
+
+	fn lock() -> MutexGuard<'static, Self> {
+		EXT_WATCHER.lock()
+			.unwrap_or_else(|e| e.into_inner())
+	}
+
+	/// Allow [update](Self::update) to start watching again after a [stop](Self::stop)
+	pub fn init() {
+		Self::lock().shutdown = false;
+	}
+
+	/// (Re)start watching `dirs`, unless watching is disabled in settings
+	pub fn update(dirs: &BTreeSet<ExtDir>) {
+		let enabled = Settings::lock_read().watch;
+		let old = {
+			let mut watcher = Self::lock();
+			if watcher.shutdown {
+				return
+			}
+			let running = watcher.thread.is_some();
+			if running == enabled && (!enabled || watcher.dirs == *dirs) {
+				return
+			}
+			watcher.take()
+		};
+		Self::join(old);
+
+		if !enabled {
+			return
+		}
+
+		let stop = Arc::new(AtomicBool::new(false));
+		let thread = {
+			let dirs = dirs.clone();
+			let stop = stop.clone();
+			thread::Builder::new()
+				.name(Self::THREAD_NAME.into())
+				.spawn(move || Self::run(dirs, stop))
+		};
+		match thread {
+			Ok(thread) => {
+				let old = {
+					let mut watcher = Self::lock();
+					match watcher.shutdown {
+						// stopped while we were busy starting it
+						true => {
+							stop.store(true, Ordering::Relaxed);
+							Some(thread)
+						},
+						false => {
+							let old = watcher.take();
+							watcher.dirs = dirs.clone();
+							watcher.stop = Some(stop);
+							watcher.thread = Some(thread);
+							old
+						},
+					}
+				};
+				// in case someone else raced us here
+				Self::join(old);
+			},
+			Err(_e) => {
+				error!("failed to start extension watcher: {_e}");
+			},
+		}
+	}
+
+	pub fn stop() {
+		let old = {
+			let mut watcher = Self::lock();
+			watcher.shutdown = true;
+			watcher.take()
+		};
+		Self::join(old);
+	}
+
+	/// Ask the current thread to stop, without waiting for it
+	fn take(&mut self) -> Option<JoinHandle<()>> {
+		if let Some(stop) = self.stop.take() {
+			stop.store(true, Ordering::Relaxed);
+		}
+		self.dirs.clear();
+		self.thread.take()
+	}
+
+	fn join(thread: Option<JoinHandle<()>>) {
+		let thread = match thread {
+			// the watcher can't wait for itself to finish
+			Some(thread) if thread.thread().id() != thread::current().id() => thread,
+			_ => return,
+		};
+		if let Err(_e) = thread.join() {
+			error!("extension watcher panicked: {_e:?}");
+		}
+	}
+
+	fn run(dirs: BTreeSet<ExtDir>, stop: Arc<AtomicBool>) {
+		if dirs.len() > Self::MAX_DIRS {
+			warn!("only watching the first {} of {} extension dirs", Self::MAX_DIRS, dirs.len());
+		}
+		let notifications: Vec<_> = dirs.iter()
+			.take(Self::MAX_DIRS)
+			.filter_map(|dir| match ChangeNotification::new(dir) {
+				Ok(n) => Some(n),
+				Err(_e) => {
+					warn!("cannot watch {}: {_e}", dir.path.display());
+					None
+				},
+			}).collect();
+		if notifications.is_empty() {
+			return
+		}
+		let handles: Vec<HANDLE> = notifications.iter()
+			.map(|n| n.0)
+			.collect();
+
+		debug!("watching {} extension dirs", handles.len());
+
+		let mut snapshot = ExtSnapshot::scan(&dirs);
+		let mut changed_at = None;
+		while !stop.load(Ordering::Relaxed) {
+			let res = unsafe {
+				WaitForMultipleObjects(&handles, false, Self::POLL_INTERVAL_MS)
+			};
+			let signalled = res.0.wrapping_sub(WAIT_OBJECT_0.0) as usize;
+			if let Some(&handle) = handles.get(signalled) {
+				if let Err(_e) = unsafe { FindNextChangeNotification(handle) } {
+					error!("extension watcher failed to rearm: {_e}");
+					break
+				}
+				changed_at = Some(Instant::now());
+				continue
+			} else if res == WAIT_FAILED {
+				error!("extension watcher failed: {}", windows::core::Error::from_win32());
+				break
+			}
+
+			match changed_at {
+				Some(t) if t.elapsed() >= Self::SETTLE_TIME => (),
+				_ => continue,
+			}
+			changed_at = None;
+
+			let next = ExtSnapshot::scan(&dirs);
+			let changes = snapshot.diff(&next);
+			snapshot = next;
+			if !changes.is_empty() {
+				Self::notify(changes);
+			}
+		}
+
+		debug!("extension watcher exiting");
+	}
+
+	fn notify(changes: ExtChanges) {
+		debug!("extensions changed: {changes:?}");
+
+		let _ = Supervisor::send_command(SupervisorCommand::RefreshExternal);
+
+		#[cfg(feature = "host-addonapi")]
+		if Settings::lock_read().dev_mode {
+			for path in changes.modified {
+				crate::host::addonapi::NexusHost::queue_dev_reload(path);
+			}
+		}
+	}
+}
+
+impl ExtChanges {
+	pub fn is_empty(&self) -> bool {
+		self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+	}
+}
+
+impl ExtSnapshot {
+	fn scan(dirs: &BTreeSet<ExtDir>) -> Self {
+		let files = dirs.iter()
+			.flat_map(|dir| dir.enumerate_extensions().ok())
+			.flatten()
+			.map(|ext| {
+				let meta = fs::metadata(&ext.path).ok();
+				let stamp = (
+					meta.as_ref().and_then(|m| m.modified().ok()),
+					meta.map(|m| m.len()).unwrap_or_default(),
+				);
+				(ext.path, stamp)
+			}).collect();
+
+		Self {
+			files,
+		}
+	}
+
+	fn diff(&self, next: &Self) -> ExtChanges {
+		let mut changes = ExtChanges::default();
+
+		for (path, stamp) in &next.files {
+			match self.files.get(path) {
+				None => changes.added.push(path.clone()),
+				Some(prev) if prev != stamp => changes.modified.push(path.clone()),
+				Some(..) => (),
+			}
+		}
+		changes.removed.extend(self.files.keys()
+			.filter(|path| !next.files.contains_key(*path))
+			.cloned()
+		);
+
+		changes
+	}
+}
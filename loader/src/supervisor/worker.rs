@@ -0,0 +1,169 @@
+use crate::supervisor::{Supervisor, SupervisorCommand};
+use std::{collections::VecDeque, io, sync::{mpsc, Mutex, MutexGuard, TryLockError}, thread::{self, JoinHandle}};
+
+static SUPERVISOR_WORKER: Mutex<SupervisorWorker> = Mutex::new(SupervisorWorker::empty());
+pub static SUPERVISOR_STATUS: Mutex<SupervisorStatus> = Mutex::new(SupervisorStatus::empty());
+
+/// Runs [SupervisorCommand]s away from the render thread
+#[derive(Debug)]
+pub struct SupervisorWorker {
+	sender: Option<mpsc::Sender<SupervisorCommand>>,
+	thread: Option<JoinHandle<()>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SupervisorProgress {
+	pub command: SupervisorCommand,
+	pub done: usize,
+	pub total: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct SupervisorResult {
+	pub command: SupervisorCommand,
+	pub result: Result<String, String>,
+}
+
+/// What the worker is up to, for the UI to peek at
+#[derive(Debug)]
+pub struct SupervisorStatus {
+	pub queued: usize,
+	pub progress: Option<SupervisorProgress>,
+	pub results: VecDeque<SupervisorResult>,
+}
+
+impl SupervisorWorker {
+	const THREAD_NAME: &'static str = "arcloader-supervisor";
+
+	pub const fn empty() -> Self {
+		Self {
+			sender: None,
+			thread: None,
+		}
+	}
+
+	fn lock() -> MutexGuard<'static, Self> {
+		SUPERVISOR_WORKER.lock()
+			.unwrap_or_else(|e| e.into_inner())
+	}
+
+	pub fn start() -> io::Result<()> {
+		let mut worker = Self::lock();
+		if worker.sender.is_some() {
+			return Ok(())
+		}
+
+		let (sender, receiver) = mpsc::channel();
+		let thread = thread::Builder::new()
+			.name(Self::THREAD_NAME.into())
+			.spawn(move || Self::run(receiver))?;
+
+		worker.sender = Some(sender);
+		worker.thread = Some(thread);
+
+		Ok(())
+	}
+
+	/// Waits for any queued commands to finish
+	pub fn stop() {
+		let thread = {
+			let mut worker = Self::lock();
+			// hanging up lets the worker drain its queue and exit
+			worker.sender = None;
+			worker.thread.take()
+		};
+
+		if let Some(thread) = thread {
+			if let Err(_e) = thread.join() {
+				error!("supervisor worker panicked: {_e:?}");
+			}
+		}
+
+		*SupervisorStatus::lock() = SupervisorStatus::empty();
+	}
+
+	/// Queue `cmd`, or hand it back if the worker isn't running
+	pub fn send(cmd: SupervisorCommand) -> Result<(), SupervisorCommand> {
+		let worker = Self::lock();
+		let sender = match &worker.sender {
+			Some(sender) => sender,
+			None => return Err(cmd),
+		};
+
+		SupervisorStatus::lock().queued += 1;
+		sender.send(cmd)
+			.map_err(|mpsc::SendError(cmd)| {
+				SupervisorStatus::lock().queued -= 1;
+				cmd
+			})
+	}
+
+	fn run(receiver: mpsc::Receiver<SupervisorCommand>) {
+		debug!("supervisor worker started");
+
+		for cmd in receiver {
+			let result = Supervisor::execute_command(cmd.clone());
+
+			let mut status = SupervisorStatus::lock();
+			status.queued = status.queued.saturating_sub(1);
+			status.progress = None;
+			if let Some(result) = result {
+				status.post_result(SupervisorResult {
+					command: cmd,
+					result,
+				});
+			}
+		}
+
+		debug!("supervisor worker exiting");
+	}
+}
+
+impl SupervisorStatus {
+	pub const MAX_RESULTS: usize = 8;
+
+	pub const fn empty() -> Self {
+		Self {
+			queued: 0,
+			progress: None,
+			results: VecDeque::new(),
+		}
+	}
+
+	pub fn lock() -> MutexGuard<'static, Self> {
+		SUPERVISOR_STATUS.lock()
+			.unwrap_or_else(|e| e.into_inner())
+	}
+
+	/// For the render thread, which would rather skip a frame than wait
+	pub fn try_lock() -> Option<MutexGuard<'static, Self>> {
+		match SUPERVISOR_STATUS.try_lock() {
+			Ok(status) => Some(status),
+			Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+			Err(TryLockError::WouldBlock) => None,
+		}
+	}
+
+	pub fn set_progress(command: &SupervisorCommand, done: usize, total: usize) {
+		Self::lock().progress = Some(SupervisorProgress {
+			command: command.clone(),
+			done,
+			total,
+		});
+	}
+
+	pub fn post_result(&mut self, result: SupervisorResult) {
+		while self.results.len() >= Self::MAX_RESULTS {
+			self.results.pop_front();
+		}
+		self.results.push_back(result);
+	}
+
+	pub fn latest(&self) -> Option<&SupervisorResult> {
+		self.results.back()
+	}
+
+	pub fn is_busy(&self) -> bool {
+		self.queued > 0 || self.progress.is_some()
+	}
+}
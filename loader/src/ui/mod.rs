@@ -1,14 +1,13 @@
-use crate::{extensions::{Loader, LoaderCommand}, supervisor::{Supervisor, SupervisorCommand, SUPERVISOR}, util::{arc::game_dir, win::get_module_from_name}, RenderThread};
-use std::{cell::RefCell, collections::{BTreeSet, HashSet}, ffi::{CStr, OsString}, num::NonZeroU32, path::{Path, MAIN_SEPARATOR_STR}, sync::Arc};
+use crate::{extensions::{original_module_path, unix_now, AddonLoader, Loader, LoaderCommand, LoaderQueue, LoaderStatus, TrustPrompt, TrustStore}, settings::{settings_key, Settings}, supervisor::{ExtDir, ExtImage, ProfileOutcome, SafeMode, Supervisor, SupervisorCommand, SupervisorStatus, SUPERVISOR}, util::{arc::game_dir, win::{get_module_from_name, retain_library}}, RenderThread};
+use std::{cell::RefCell, collections::HashSet, ffi::{CStr, OsString}, num::NonZeroU32, path::{Path, MAIN_SEPARATOR_STR}, sync::Arc};
 use arcdps::{
-	 exports::{self, CoreColor}, imgui::{Id, StyleColor, TableColumnSetup, TableFlags, Ui}
+	 exports::{self, CoreColor}, imgui::{Id, StyleColor, TableColumnSetup, TableFlags, TreeNodeFlags, Ui}
 };
 #[cfg(feature = "arcdps-extras")]
 use arcdps::extras::{ExtrasAddonInfo, UserInfoIter};
 use windows::core::Owned;
 use windows_strings::HSTRING;
 
-use crate::util::win::get_module_path;
 
 mod render;
 
@@ -16,19 +15,6 @@ thread_local! {
 	static OPTIONS: RefCell<Option<Options>> = RefCell::new(None);
 }
 
-#[derive(Clone, Debug)]
-struct ExtCache {
-	pub sig: Option<NonZeroU32>,
-	pub name: String,
-	pub file_name: OsString,
-}
-
-#[derive(Clone, Debug)]
-struct ExtSettings {
-	pub blacklist: BTreeSet<u32>,
-	pub cache: BTreeSet<ExtCache>,
-}
-
 #[derive(Debug)]
 enum UserCommand {
 	Loader(LoaderCommand),
@@ -36,11 +22,20 @@ enum UserCommand {
 }
 
 pub struct Options {
+	new_dir: String,
+	/// A search directory being edited, and where it sits in [Settings::dirs]
+	edit_dir: Option<(usize, ExtDir)>,
+	new_profile: String,
+	profile_outcome: Option<ProfileOutcome>,
 }
 
 impl Options {
 	pub fn new() -> Self {
 		Self {
+			new_dir: String::new(),
+			edit_dir: None,
+			new_profile: String::new(),
+			profile_outcome: None,
 		}
 	}
 
@@ -64,19 +59,500 @@ impl Options {
 		if needs_init {
 			OPTIONS.set(Some(Self::new()));
 		}
+
+		RenderThread::with_ui(Self::imgui_trust_prompts);
+	}
+
+	/// Ask about anything [TrustStore] refused to load
+	fn imgui_trust_prompts(ui: &Ui) {
+		let pending = TrustStore::pending();
+		let prompt = match pending.first() {
+			Some(prompt) => prompt,
+			None => return,
+		};
+
+		arcdps::imgui::Window::new("arcloader: untrusted extension")
+			.always_auto_resize(true)
+			.collapsible(false)
+			.build(ui, || {
+				let colour = Self::colours()
+					.and_then(|c| c.core(CoreColor::LightYellow))
+					.unwrap_or(ui.style_color(StyleColor::PlotHistogramHovered));
+				match prompt.changed {
+					true => ui.text_colored(colour, "This extension has changed since you last trusted it:"),
+					false => ui.text_colored(colour, "This extension has not been trusted yet:"),
+				}
+				ui.text(prompt.path.display().to_string());
+				ui.text_disabled(format!("SHA-256 {}", prompt.hash));
+				if pending.len() > 1 {
+					ui.text_disabled(format!("{} more waiting", pending.len() - 1));
+				}
+				ui.spacing();
+
+				if ui.button("trust and load") {
+					TrustStore::trust(prompt);
+					Self::retry_trusted(prompt);
+				}
+				ui.same_line();
+				if ui.button("not now") {
+					TrustStore::dismiss(prompt);
+				}
+				ui.same_line();
+				if ui.button("block") {
+					TrustStore::dismiss(prompt);
+					Self::block_untrusted(prompt);
+				}
+				if ui.is_item_hovered() {
+					ui.tooltip_text("blacklist it, so it won't be asked about again");
+				}
+			});
+	}
+
+	fn prompt_loader(prompt: &TrustPrompt) -> AddonLoader {
+		ExtImage::read(&prompt.path).ok()
+			.and_then(|image| image.loader)
+			.or_else(|| AddonLoader::for_path(&prompt.path))
+			.unwrap_or(AddonLoader::Arcdps)
+	}
+
+	fn retry_trusted(prompt: &TrustPrompt) {
+		#[allow(unreachable_patterns)]
+		match Self::prompt_loader(prompt) {
+			#[cfg(feature = "host-addonapi")]
+			AddonLoader::NexusHost => {
+				if let Err(_e) = crate::host::addonapi::NexusHost::autoload_path(&prompt.path) {
+					error!("failed to load {}: {_e}", prompt.path.display());
+				}
+			},
+			loader => {
+				let _id = Loader::queue_command(LoaderCommand::LoadPath { path: prompt.path.as_path().into(), loader: Some(loader) });
+			},
+		}
+	}
+
+	fn block_untrusted(prompt: &TrustPrompt) {
+		let key = match settings_key(&prompt.path) {
+			Some(key) => key,
+			None => return,
+		};
+		let loader = Self::prompt_loader(prompt);
+		Settings::update_with(|settings| settings.extensions_mut(loader).set_blacklisted(&key, true));
+	}
+
+	/// Whether the module loaded from `path` has since been replaced on disk
+	fn stale_status(ui: &Ui, sv: &Supervisor, path: Option<&Path>) {
+		let path = match path {
+			Some(path) => path,
+			None => return,
+		};
+		let on_disk = sv.external.iter()
+			.find(|ext| ext.path == path)
+			.and_then(|ext| ext.hash);
+		if !TrustStore::is_stale(path, on_disk) {
+			return
+		}
+
+		let colour = Self::colours()
+			.and_then(|c| c.core(CoreColor::LightRed))
+			.unwrap_or(ui.style_color(StyleColor::PlotHistogramHovered));
+		ui.text_colored(colour, "stale");
+		if ui.is_item_hovered() {
+			ui.tooltip_text("the file on disk no longer matches what was loaded");
+		}
 	}
 
 	pub fn imgui_render(&mut self, ui: &Ui) {
 		ui.spacing();
 
+		Self::imgui_safe_mode(ui);
+
 		if ui.button("Refresh") {
 			let _ = Supervisor::send_command(SupervisorCommand::RefreshArcdps);
 			let _ = Supervisor::send_command(SupervisorCommand::RefreshExternal);
 		}
+		ui.same_line();
+		self.imgui_supervisor_status(ui);
+
+		Self::imgui_collisions(ui);
+
+		if ui.collapsing_header("Search directories", TreeNodeFlags::empty()) {
+			self.imgui_search_dirs(ui);
+		}
+
+		if ui.collapsing_header("Profiles", TreeNodeFlags::empty()) {
+			self.imgui_profiles(ui);
+		}
+
+		#[cfg(feature = "verify")]
+		if ui.collapsing_header("Security", TreeNodeFlags::empty()) {
+			Self::imgui_security(ui);
+		}
+
+		#[cfg(feature = "host-addonapi")]
+		if ui.collapsing_header("Language", TreeNodeFlags::empty()) {
+			Self::imgui_language(ui);
+		}
 
 		self.imgui_options_table(ui)
 	}
 
+	fn imgui_safe_mode(ui: &Ui) {
+		let reason = match SafeMode::reason() {
+			Some(reason) => reason,
+			None => return,
+		};
+
+		let colour = Self::colours()
+			.and_then(|c| c.core(CoreColor::LightYellow))
+			.unwrap_or(ui.style_color(StyleColor::PlotHistogramHovered));
+		ui.text_colored(colour, "safe mode: extensions were not loaded");
+		if ui.is_item_hovered() {
+			ui.tooltip_text(reason.to_string());
+		}
+		ui.same_line();
+		if ui.small_button("load extensions") {
+			SafeMode::exit();
+		}
+		ui.separator();
+	}
+
+	fn imgui_collisions(ui: &Ui) {
+		let collisions = match SUPERVISOR.try_read() {
+			Ok(sv) if !sv.collisions.is_empty() => sv.collisions.clone(),
+			_ => return,
+		};
+
+		let colour = Self::colours()
+			.and_then(|c| c.core(CoreColor::LightRed))
+			.unwrap_or(ui.style_color(StyleColor::PlotHistogramHovered));
+		for collision in &collisions {
+			ui.text_colored(colour, collision.to_string());
+			if ui.is_item_hovered() {
+				ui.tooltip_text(collision.rejected.display().to_string());
+			}
+		}
+		if ui.small_button("dismiss") {
+			Supervisor::dismiss_collisions();
+		}
+	}
+
+	pub fn imgui_supervisor_status(&mut self, ui: &Ui) {
+		let status = match SupervisorStatus::try_lock() {
+			Some(status) => status,
+			None => {
+				ui.text_disabled("working...");
+				return
+			},
+		};
+
+		match (&status.progress, status.latest()) {
+			(Some(progress), _) =>
+				ui.text_disabled(format!("{:?} {}/{}...", progress.command, progress.done + 1, progress.total)),
+			(None, _) if status.is_busy() =>
+				ui.text_disabled("working..."),
+			(None, Some(latest)) => match &latest.result {
+				Ok(msg) => ui.text_disabled(msg),
+				Err(msg) => {
+					let colour = Self::colours()
+						.and_then(|c| c.core(CoreColor::LightRed))
+						.unwrap_or(ui.style_color(StyleColor::PlotHistogramHovered));
+					ui.text_colored(colour, format!("{:?} failed", latest.command));
+					if ui.is_item_hovered() {
+						ui.tooltip_text(msg);
+					}
+				},
+			},
+			(None, None) => (),
+		}
+	}
+
+	pub fn imgui_search_dirs(&mut self, ui: &Ui) {
+		let mut changed = false;
+
+		let mut default_dirs = Settings::lock_read().default_dirs;
+		if ui.checkbox("default directories", &mut default_dirs) {
+			changed |= Settings::update_with(|settings| {
+				settings.default_dirs = default_dirs;
+				true
+			});
+		}
+		if ui.is_item_hovered() {
+			ui.tooltip_text("search the arcdps config, addons/arcloader, and game directories");
+		}
+
+		let mut watch = Settings::lock_read().watch;
+		if ui.checkbox("watch for changes", &mut watch) {
+			changed |= Settings::update_with(|settings| {
+				settings.watch = watch;
+				true
+			});
+		}
+
+		let mut shadow_copy = Settings::lock_read().shadow_copy;
+		if ui.checkbox("shadow copy", &mut shadow_copy) {
+			Settings::update_with(|settings| {
+				settings.shadow_copy = shadow_copy;
+				true
+			});
+		}
+		if ui.is_item_hovered() {
+			ui.tooltip_text("load copies of extensions, so the originals can be replaced while the game is running");
+		}
+
+		#[cfg(feature = "host-addonapi")] {
+			let mut dev_mode = Settings::lock_read().dev_mode;
+			if ui.checkbox("addon dev mode", &mut dev_mode) {
+				Settings::update_with(|settings| {
+					settings.dev_mode = dev_mode;
+					true
+				});
+			}
+			if ui.is_item_hovered() {
+				ui.tooltip_text("reload nexus addons that support hotloading whenever their DLL is rebuilt\nextensions are always shadow copied while this is on");
+			}
+			if dev_mode && !shadow_copy {
+				ui.same_line();
+				ui.text_disabled("(shadow copying anyway)");
+			}
+
+			let mut check_updates = Settings::lock_read().check_updates;
+			if ui.checkbox("check for updates", &mut check_updates) {
+				Settings::update_with(|settings| {
+					settings.check_updates = check_updates;
+					true
+				});
+			}
+			if ui.is_item_hovered() {
+				ui.tooltip_text("download new releases of nexus addons, applied on next launch or right away if they support hotloading");
+			}
+		}
+
+		let mut remove = None;
+		let mut save = None;
+		let dirs = Settings::lock_read().dirs.clone();
+		for (i, dir) in dirs.iter().enumerate() {
+			let dir_token = ui.push_id(Id::Int(i as i32));
+			if ui.small_button("remove") {
+				remove = Some(i);
+			}
+			ui.same_line();
+			let editing = matches!(self.edit_dir, Some((edit, _)) if edit == i);
+			if ui.small_button(if editing { "close" } else { "edit" }) {
+				self.edit_dir = match editing {
+					true => None,
+					false => Some((i, dir.clone())),
+				};
+			}
+			ui.same_line();
+			ui.text(dir.path.display().to_string());
+			if dir.depth > 0 || dir.include.is_some() || dir.exclude.is_some() || dir.loader.is_some() {
+				ui.same_line();
+				ui.text_disabled(format!("(depth {}, include {:?}, exclude {:?}, loader {:?})", dir.depth, dir.include, dir.exclude, dir.loader));
+			}
+			match &mut self.edit_dir {
+				Some((edit, edited)) if *edit == i => match Self::imgui_dir_editor(ui, edited) {
+					Some(true) => save = self.edit_dir.take(),
+					Some(false) => self.edit_dir = None,
+					None => (),
+				},
+				_ => (),
+			}
+			dir_token.end();
+		}
+		if let Some(i) = remove {
+			changed |= Settings::update_with(|settings| match i < settings.dirs.len() {
+				true => {
+					settings.dirs.remove(i);
+					true
+				},
+				false => false,
+			});
+			self.edit_dir = None;
+		}
+		if let Some((i, dir)) = save {
+			changed |= Settings::update_with(|settings| match settings.dirs.get(i) {
+				Some(existing) if *existing == dir => false,
+				// leave it be if the list changed underneath us
+				Some(existing) if existing.path != dirs[i].path => false,
+				Some(_) => {
+					settings.dirs[i] = dir;
+					true
+				},
+				None => false,
+			});
+		}
+
+		ui.input_text("##new_dir", &mut self.new_dir).build();
+		ui.same_line();
+		if ui.button("add") && !self.new_dir.trim().is_empty() {
+			let dir = ExtDir::new(self.new_dir.trim());
+			changed |= Settings::update_with(|settings| match settings.dirs.contains(&dir) {
+				true => false,
+				false => {
+					settings.dirs.push(dir);
+					true
+				},
+			});
+			self.new_dir.clear();
+		}
+
+		if changed {
+			let _ = Supervisor::send_command(SupervisorCommand::RefreshExternal);
+		}
+	}
+
+	/// Returns whether to save or discard the changes, once either is clicked
+	fn imgui_dir_editor(ui: &Ui, dir: &mut ExtDir) -> Option<bool> {
+		ui.indent();
+
+		let mut path = dir.path.display().to_string();
+		if ui.input_text("path", &mut path).build() {
+			dir.path = path.trim().into();
+		}
+
+		let mut depth = dir.depth as i32;
+		if ui.input_int("depth", &mut depth).build() {
+			dir.depth = depth.max(0) as u32;
+		}
+		if ui.is_item_hovered() {
+			ui.tooltip_text("how many levels of subdirectories to search");
+		}
+
+		let patterns = [
+			("include", &mut dir.include, "only consider file names matching these patterns, instead of requiring arcdps_ or nexus_ in the name"),
+			("exclude", &mut dir.exclude, "ignore file names matching these patterns"),
+		];
+		for (label, pattern, tooltip) in patterns {
+			let mut text = pattern.clone().unwrap_or_default();
+			if ui.input_text(label, &mut text).hint("*.dll; arcdps_*").build() {
+				*pattern = Some(text).filter(|p| !p.trim().is_empty());
+			}
+			if ui.is_item_hovered() {
+				ui.tooltip_text(format!("{tooltip}\n* matches anything, ? any single character, and ; separates alternatives"));
+			}
+		}
+
+		let loaders = [
+			("guess", None),
+			("arcdps", Some(AddonLoader::Arcdps)),
+			#[cfg(feature = "host-addonapi")]
+			("nexus", Some(AddonLoader::NexusHost)),
+		];
+		ui.text("loader:");
+		if ui.is_item_hovered() {
+			ui.tooltip_text("what to load extensions as when their file name doesn't say");
+		}
+		for (label, loader) in loaders {
+			ui.same_line();
+			if ui.radio_button_bool(label, dir.loader == loader) {
+				dir.loader = loader;
+			}
+		}
+
+		let mut res = None;
+		if ui.small_button("save") && !dir.path.as_os_str().is_empty() {
+			res = Some(true);
+		}
+		ui.same_line();
+		if ui.small_button("cancel") {
+			res = Some(false);
+		}
+
+		ui.unindent();
+		res
+	}
+
+	#[cfg(feature = "verify")]
+	fn imgui_security(ui: &Ui) {
+		let (mut verify_trust, trusted) = {
+			let settings = Settings::lock_read();
+			(settings.verify_trust, settings.trusted.len())
+		};
+		if ui.checkbox("verify extensions", &mut verify_trust) {
+			// whatever is already running is presumably fine
+			if verify_trust {
+				let _trusted = TrustStore::trust_loaded();
+				info!("trusting {_trusted} loaded extensions");
+			}
+			Settings::update_with(|settings| {
+				settings.verify_trust = verify_trust;
+				true
+			});
+		}
+		if ui.is_item_hovered() {
+			ui.tooltip_text("ask before loading any extension whose SHA-256 hasn't been trusted yet\nturning this on trusts everything loaded right now");
+		}
+		ui.same_line();
+		ui.text_disabled(format!("({trusted} trusted)"));
+	}
+
+	#[cfg(feature = "host-addonapi")]
+	fn imgui_language(ui: &Ui) {
+		use crate::host::addonapi::Localization;
+
+		let (current, mut languages) = {
+			let localization = Localization::lock_read();
+			let languages: Vec<String> = localization.languages().map(Into::into).collect();
+			(localization.language().to_owned(), languages)
+		};
+		if !languages.iter().any(|l| *l == Localization::DEFAULT_LANGUAGE) {
+			languages.insert(0, Localization::DEFAULT_LANGUAGE.into());
+		}
+		ui.text("addon language:");
+		for language in &languages {
+			ui.same_line();
+			if ui.radio_button_bool(language, *language == current) && *language != current {
+				Localization::set_language(Some(language));
+			}
+		}
+	}
+
+	pub fn imgui_profiles(&mut self, ui: &Ui) {
+		let (names, active) = {
+			let settings = Settings::lock_read();
+			(settings.profiles.keys().cloned().collect::<Vec<_>>(), settings.profile.clone())
+		};
+
+		for (i, name) in names.iter().enumerate() {
+			let profile_token = ui.push_id(Id::Int(i as i32));
+			if ui.small_button("switch") {
+				self.profile_outcome = Supervisor::switch_profile(name);
+			}
+			ui.same_line();
+			if ui.small_button("save") {
+				Supervisor::save_profile(name);
+			}
+			if ui.is_item_hovered() {
+				ui.tooltip_text("replace with what's loaded right now");
+			}
+			ui.same_line();
+			if ui.small_button("delete") {
+				Supervisor::delete_profile(name);
+			}
+			ui.same_line();
+			match active.as_deref() == Some(name.as_str()) {
+				true => ui.text(name),
+				false => ui.text_disabled(name),
+			}
+			profile_token.end();
+		}
+
+		ui.input_text("##new_profile", &mut self.new_profile).build();
+		ui.same_line();
+		if ui.button("save as") && !self.new_profile.trim().is_empty() {
+			Supervisor::save_profile(self.new_profile.trim());
+			self.new_profile.clear();
+		}
+		if ui.is_item_hovered() {
+			ui.tooltip_text("remember which extensions are loaded, and their options");
+		}
+
+		if let Some(outcome) = &mut self.profile_outcome {
+			outcome.update();
+			ui.text_disabled(outcome.to_string());
+		}
+	}
+
 	pub fn imgui_options_table(&mut self, ui: &Ui) {
 		if !exports::has_list_extension() {
 			ui.text_disabled("unsupported");
@@ -96,12 +572,113 @@ impl Options {
 		}
 
 		if let Some(cmd) = ext_cmd {
-			// TODO: this may be async, need a way to get result back later!
-			let res = Loader::send_command(cmd);
+			// results show up next to the extension via `loader_status`
+			let _id = Loader::queue_command(cmd);
+		}
+	}
 
-			if let Err(_e) = res {
-				error!("loader failed: {_e}");
-			}
+	#[cfg_attr(not(feature = "host-addonapi"), allow(unused_variables))]
+	fn managed_by_nexus(loader: AddonLoader, path: &Path) -> bool {
+		#[cfg(feature = "host-addonapi")]
+		if loader == AddonLoader::NexusHost {
+			return crate::host::addonapi::NexusHost::managed_by_nexus(path)
+		}
+
+		false
+	}
+
+	/// Pick which loader hosts an extension that supports both
+	fn host_choice(ui: &Ui, loader: AddonLoader, path: &Path) {
+		let key = match settings_key(path) {
+			Some(key) => key,
+			None => return,
+		};
+		let (label, next) = match loader {
+			AddonLoader::Arcdps => ("host: arcdps", AddonLoader::NexusHost),
+			AddonLoader::NexusHost => ("host: nexus", AddonLoader::Arcdps),
+		};
+		if ui.small_button(label) {
+			Settings::update_with(|settings| settings.hosts.insert(key, next) != Some(next));
+			let _ = Supervisor::send_command(SupervisorCommand::RefreshExternal);
+		}
+	}
+
+	/// Outcome of the latest [LoaderCommand] concerning an extension
+	fn loader_status(ui: &Ui, path: Option<&Path>, sig: Option<NonZeroU32>) {
+		let queue = match LoaderQueue::try_lock() {
+			Some(queue) => queue,
+			None => return,
+		};
+		let key = path.and_then(settings_key);
+		let request = match queue.latest_for(key.as_deref(), sig) {
+			Some(req) => req,
+			None => return,
+		};
+
+		match &request.status {
+			LoaderStatus::Pending => ui.text_disabled("pending..."),
+			LoaderStatus::Ok => ui.text_disabled("ok"),
+			LoaderStatus::Err(msg) => {
+				let colour = Self::colours()
+					.and_then(|c| c.core(CoreColor::LightRed))
+					.unwrap_or(ui.style_color(StyleColor::PlotHistogramHovered));
+				ui.text_colored(colour, "error");
+				if ui.is_item_hovered() {
+					ui.tooltip_text(msg);
+				}
+			},
+		}
+	}
+
+	fn autoload_checkbox(ui: &Ui, loader: AddonLoader, path: &Path) {
+		let key = match settings_key(path) {
+			Some(key) => key,
+			None => return,
+		};
+		let mut autoload = Settings::lock_read().extensions(loader).is_autoload(&key);
+		if ui.checkbox("autoload", &mut autoload) {
+			Settings::update_with(|settings| settings.extensions_mut(loader).set_autoload(&key, autoload));
+		}
+	}
+
+	fn blacklist_checkbox(ui: &Ui, loader: AddonLoader, path: &Path) -> bool {
+		let key = match settings_key(path) {
+			Some(key) => key,
+			None => return false,
+		};
+		let mut blacklisted = Settings::lock_read().extensions(loader).is_blacklisted(&key);
+		if ui.checkbox("never load", &mut blacklisted) {
+			Settings::update_with(|settings| settings.extensions_mut(loader).set_blacklisted(&key, blacklisted));
+		}
+		if ui.is_item_hovered() {
+			ui.tooltip_text("skip this extension, even if it is set to autoload");
+		}
+		blacklisted
+	}
+
+	/// Flag extensions the [LoadJournal](crate::extensions::LoadJournal) caught crashing
+	fn quarantine_status(ui: &Ui, loader: AddonLoader, path: &Path) {
+		let key = match settings_key(path) {
+			Some(key) => key,
+			None => return,
+		};
+		let since = match Settings::lock_read().extensions(loader).quarantined(&key) {
+			Some(quarantine) => quarantine.since,
+			None => return,
+		};
+
+		let colour = Self::colours()
+			.and_then(|c| c.core(CoreColor::LightRed))
+			.unwrap_or(ui.style_color(StyleColor::PlotHistogramHovered));
+		ui.text_colored(colour, "quarantined");
+		if ui.is_item_hovered() {
+			ui.tooltip_text(format!("crashed the game while loading {}", format_ago(since)));
+		}
+		if ui.small_button("retry") {
+			Settings::update_with(|settings| settings.extensions_mut(loader).release_quarantine(&key));
+		}
+		if ui.is_item_hovered() {
+			ui.tooltip_text("allow this extension to load again");
 		}
 	}
 
@@ -173,7 +750,31 @@ impl Options {
 					info!("removing {}...", ext.name);
 					cmd = Some(LoaderCommand::Unload { sig: ext.sig });
 				}
+				if exports::has_add_extension() && ui.button_with_size("reload", [width, 0.0]) {
+					match retain_library(ext.module) {
+						Ok(module) => cmd = Some(LoaderCommand::Reload { module }),
+						Err(_e) => error!("cannot reload {}: {_e}", ext.name),
+					}
+				}
+			} else if is_self && exports::has_remove_extension() {
+				let width = ui.current_column_width()
+					.max(button_width);
+				if exports::has_add_extension() && ui.button_with_size("reload", [width, 0.0]) {
+					cmd = Some(LoaderCommand::LoaderReload);
+				}
+				if ui.button_with_size("exit", [width, 0.0]) {
+					cmd = Some(LoaderCommand::LoaderExit);
+				}
+				if ui.is_item_hovered() {
+					ui.tooltip_text("Unload arcloader and everything it hosts");
+				}
+			}
+			if let Some(path) = &ext.path {
+				if !is_self {
+					Self::autoload_checkbox(ui, AddonLoader::Arcdps, path);
+				}
 			}
+			Self::loader_status(ui, ext.path.as_deref(), Some(ext.sig));
 			ui.table_next_column();
 
 			ui.text(&ext.name);
@@ -185,6 +786,12 @@ impl Options {
 			let c = ui.push_style_color(StyleColor::Text, colour);
 			ui.text_wrapped(&ext.build);
 			c.end();
+			if let Some(disk_version) = Self::disk_version(sv, ext.path.as_deref()) {
+				if !ext.build.contains(&disk_version) {
+					ui.text_disabled(format!("{disk_version} on disk"));
+				}
+			}
+			Self::stale_status(ui, sv, ext.path.as_deref());
 
 			ui.same_line();
 			ui.text(" (arcdps)");
@@ -208,6 +815,15 @@ impl Options {
 		cmd
 	}
 
+	/// Version of the file a loaded extension came from, which may since have been replaced
+	fn disk_version(sv: &Supervisor, path: Option<&Path>) -> Option<String> {
+		let path = path?;
+		sv.external.iter()
+			.find(|ext| ext.path == path)
+			.and_then(|ext| ext.version())
+			.and_then(|version| version.version())
+	}
+
 	pub fn extensions_table_external(&mut self, ui: &Ui, sv: &Supervisor, seen: &mut HashSet<OsString>) -> Option<LoaderCommand> {
 		let mut cmd = None;
 
@@ -227,10 +843,35 @@ impl Options {
 			let ext_token = ui.push_id(Id::Ptr(Arc::as_ptr(ext) as *const _));
 			let width = ui.current_column_width()
 					.max(button_width);
+			let loader = ext.loader
+				.unwrap_or(AddonLoader::Arcdps);
+			let blacklisted = Self::blacklist_checkbox(ui, loader, &ext.path);
+			Self::quarantine_status(ui, loader, &ext.path);
 			if !exports::has_add_extension() {
 				ui.text_disabled("unavailable");
+			} else if blacklisted {
+				ui.text_disabled("blocked");
+			} else if !ext.is_compatible() {
+				ui.text_disabled("incompatible");
+				if let (true, Some(image)) = (ui.is_item_hovered(), &ext.image) {
+					ui.tooltip_text(format!("built for {}", image.machine_name()));
+				}
+			} else if Self::managed_by_nexus(loader, &ext.path) {
+				ui.text_disabled("Nexus");
+				if ui.is_item_hovered() {
+					ui.tooltip_text("loaded by Nexus, rather than arcloader");
+				}
 			} else if ui.button_with_size("load", [width, 0.0]) {
-				cmd = Some(LoaderCommand::LoadPath { path: ext.path.as_path().into() });
+				cmd = Some(LoaderCommand::LoadPath { path: ext.path.as_path().into(), loader: ext.loader });
+			}
+			if !blacklisted {
+				Self::autoload_checkbox(ui, loader, &ext.path);
+			}
+			if let Some(image) = ext.image.as_ref().filter(|image| image.dual_host) {
+				Self::host_choice(ui, loader, &ext.path);
+				if ui.is_item_hovered() {
+					ui.tooltip_text(format!("{} works with both arcdps and nexus, click to switch", image.version.as_ref().and_then(|v| v.product_name()).unwrap_or("this extension")));
+				}
 			}
 			if ui.button_with_size("free", [width, 0.0]) {
 				// TODO: move this into a command!
@@ -257,11 +898,14 @@ impl Options {
 					},
 				}
 			}
+			Self::loader_status(ui, Some(&ext.path), None);
 			ui.table_next_column();
 
 			let fname = ext.path.file_name().and_then(|f| f.to_str());
-			let name = match () {
-				_ => fname,
+			let version = ext.version();
+			let name = match version.and_then(|v| v.product_name().or(v.file_description())) {
+				Some(product) => Some(product),
+				None => fname,
 			};
 			let sig = None::<NonZeroU32>;
 
@@ -270,10 +914,26 @@ impl Options {
 			} else {
 				ui.text_disabled("external");
 			}
+			if let Some(description) = version.and_then(|v| v.file_description()).filter(|&d| Some(d) != name) {
+				if ui.is_item_hovered() {
+					ui.tooltip_text(description);
+				}
+			}
 			if let Some(sig) = sig {
 				ui.same_line();
 				ui.text_disabled(format!("{:08x}", sig));
 			}
+			if let Some(build) = version.and_then(|v| v.version()) {
+				let colour = colours.as_ref()
+					.and_then(|c| c.core(CoreColor::LightTeal))
+					.unwrap_or(ui.style_color(StyleColor::NavHighlight));
+				let c = ui.push_style_color(StyleColor::Text, colour);
+				ui.text_wrapped(build);
+				c.end();
+			}
+			if let Some(company) = version.and_then(|v| v.company_name()) {
+				ui.text_disabled(format!("by {company}"));
+			}
 			let (path, path_is_file) = match (ext.path.parent(), fname) {
 				(Some(parent), fname@Some(..)) if name == fname =>
 					(parent, false),
@@ -309,9 +969,9 @@ impl Options {
 	}
 
 	#[cfg(feature = "host-addonapi")]
-	pub fn extensions_table_nexus(&mut self, ui: &Ui, _sv: &Supervisor, seen: &mut HashSet<OsString>) -> Option<()> {
+	pub fn extensions_table_nexus(&mut self, ui: &Ui, sv: &Supervisor, seen: &mut HashSet<OsString>) -> Option<()> {
 		use nexus::AddonFlags;
-		use crate::host::addonapi::{NexusHost, NEXUS_HOST};
+		use crate::host::addonapi::{CoresidentNexus, NexusHost, NEXUS_HOST};
 
 		let colours = Self::colours();
 		let button_width = ui.current_font().fallback_advance_x * 10.0;
@@ -323,14 +983,25 @@ impl Options {
 			_ => return None,
 		};
 
+		if let Some(nexus) = CoresidentNexus::lock_read().as_ref() {
+			let name = match &nexus.version {
+				Some(version) => format!("Nexus {version}"),
+				None => "Nexus".into(),
+			};
+			ui.text_disabled(format!("{name} is also loaded, its addons are left to it"));
+			if ui.is_item_hovered() {
+				ui.tooltip_text(nexus.path.display().to_string());
+			}
+		}
+
 		for addon in host.addons.values() {
 			let ext_token = ui.push_id(Id::Ptr(Arc::as_ptr(addon) as *const _));
 
-			if let Ok(path) = get_module_path(Some(addon.module())) {
-				if let Some(fname) = Path::new(&path).file_name() {
+			if let Ok(path) = original_module_path(addon.module()) {
+				if let Some(fname) = path.file_name() {
 					seen.insert(fname.to_owned());
 				}
-				seen.insert(path);
+				seen.insert(path.into_os_string());
 			}
 
 			let width = ui.current_column_width()
@@ -349,6 +1020,14 @@ impl Options {
 				let res = NexusHost::unload_addon(sig);
 				return None
 			}
+			if addon.can_hotload() && ui.button_with_size("reload", [width, 0.0]) {
+				match retain_library(addon.module()) {
+					Ok(module) => {
+						let _id = Loader::queue_command(LoaderCommand::Reload { module });
+					},
+					Err(_e) => error!("cannot reload {addon}: {_e}"),
+				}
+			}
 			if !addon.is_loaded() {
 				let load = ui.button_with_size("load", [width, 0.0]);
 				if load {
@@ -359,6 +1038,10 @@ impl Options {
 					return None
 				}
 			}
+			if let Ok(path) = original_module_path(addon.module()) {
+				Self::autoload_checkbox(ui, AddonLoader::NexusHost, &path);
+				Self::loader_status(ui, Some(&path), None);
+			}
 			ui.table_next_column();
 
 			ui.text(addon.name().to_string_lossy());
@@ -388,6 +1071,9 @@ impl Options {
 
 			ui.same_line();
 			ui.text(format!(" ({})", addon.api));
+			if let Ok(path) = original_module_path(addon.module()) {
+				Self::stale_status(ui, sv, Some(&path));
+			}
 
 			if let Some(desc) = addon.description() {
 				ui.text_wrapped(desc.to_string_lossy());
@@ -488,3 +1174,13 @@ pub fn imgui_id_cstr(id: &CStr) -> Id {
 		_ => Id::Ptr(id.as_ptr() as *const _),
 	}
 }
+
+fn format_ago(since: u64) -> String {
+	let secs = unix_now().saturating_sub(since);
+	match secs {
+		0..60 => "just now".into(),
+		60..3600 => format!("{} minutes ago", secs / 60),
+		3600..86400 => format!("{} hours ago", secs / 3600),
+		_ => format!("{} days ago", secs / 86400),
+	}
+}
@@ -0,0 +1,86 @@
+/// Case-insensitive wildcard matching
+///
+/// Supports `*` and `?` wildcards,
+/// and multiple alternative patterns separated by `;`
+pub fn glob_match(patterns: &str, name: &str) -> bool {
+	patterns.split(';')
+		.map(str::trim)
+		.filter(|p| !p.is_empty())
+		.any(|pattern| glob_match_one(pattern, name))
+}
+
+fn glob_match_one(pattern: &str, name: &str) -> bool {
+	let pattern: Vec<char> = pattern.chars().flat_map(char::to_lowercase).collect();
+	let name: Vec<char> = name.chars().flat_map(char::to_lowercase).collect();
+
+	let (mut p, mut n) = (0, 0);
+	// position of the last `*` seen, and where in `name` it started matching
+	let mut backtrack = None;
+	while n < name.len() {
+		match pattern.get(p) {
+			Some('*') => {
+				backtrack = Some((p, n));
+				p += 1;
+			},
+			Some(&c) if c == '?' || c == name[n] => {
+				p += 1;
+				n += 1;
+			},
+			_ => match backtrack {
+				Some((star, start)) => {
+					// let the star consume one more character and retry
+					p = star + 1;
+					n = start + 1;
+					backtrack = Some((star, start + 1));
+				},
+				None => return false,
+			},
+		}
+	}
+
+	pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[test]
+fn glob_wildcards() {
+	assert!(glob_match("*", "arcdps_foo.dll"));
+	assert!(glob_match("*", ""));
+	assert!(glob_match("arcdps_*.dll", "arcdps_foo.dll"));
+	assert!(glob_match("arcdps_*.dll", "arcdps_.dll"));
+	assert!(!glob_match("arcdps_*.dll", "nexus_foo.dll"));
+	assert!(glob_match("a*b*c", "aXbYbZc"));
+	assert!(!glob_match("a*b*c", "aXbYcZ"));
+	assert!(glob_match("d3d11?.dll", "d3d11_.dll"));
+	assert!(!glob_match("d3d11?.dll", "d3d11.dll"));
+	assert!(!glob_match("d3d11?.dll", "d3d11ab.dll"));
+	assert!(glob_match("??", "ab"));
+	assert!(glob_match("*?", "a"));
+	assert!(!glob_match("*?", ""));
+}
+
+#[test]
+fn glob_anchored() {
+	assert!(!glob_match("arc", "arcdps.dll"));
+	assert!(!glob_match("dps", "arcdps.dll"));
+	assert!(!glob_match("*.dll", "foo.dll.old"));
+	assert!(glob_match("*.dll*", "foo.dll.old"));
+	assert!(!glob_match("foo.dll", "xfoo.dll"));
+	assert!(glob_match("foo.dll", "foo.dll"));
+}
+
+#[test]
+fn glob_case() {
+	assert!(glob_match("ArcDPS_*.DLL", "arcdps_Foo.dll"));
+	assert!(glob_match("*.dll", "FOO.DLL"));
+	assert!(glob_match("ÄÖ?", "äöü"));
+}
+
+#[test]
+fn glob_alternatives() {
+	assert!(glob_match("arcdps_*; nexus_*", "nexus_foo.dll"));
+	assert!(glob_match("arcdps_*;nexus_*", "arcdps_foo.dll"));
+	assert!(!glob_match("arcdps_*; nexus_*", "d3d11.dll"));
+	assert!(glob_match(";; *.dll ;", "foo.dll"));
+	assert!(!glob_match("", "foo.dll"));
+	assert!(!glob_match(" ; ", "foo.dll"));
+}
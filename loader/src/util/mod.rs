@@ -24,6 +24,7 @@ macro_rules! error {
 pub mod arc;
 #[cfg(any(feature = "addonapi", feature = "host-addonapi"))]
 pub mod nexus;
+pub mod glob;
 pub(crate) mod ffi {
 	pub use arcffi::*;
 	pub use arcffi::cstr::*;
@@ -231,6 +231,7 @@ impl From<AddonVersion> for addon::AddonVersion {
 pub type KeybindMods = [u8; 3];
 
 #[derive(Debug, Copy, Clone, Default, PartialOrd, Ord, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C)]
 pub struct Keybind {
 	pub code: u16,